@@ -1,16 +1,30 @@
-mod blank_file;
+mod apply_changes;
+mod cache;
 mod change_counter;
 pub mod change_events;
 mod custom_metadata;
+mod database;
 mod file_types;
+mod ignore_rules;
 mod local_changes;
+mod placeholders;
+mod selective_sync;
 mod server_version;
 
-pub use blank_file::make_blank_file;
+pub use apply_changes::{apply_change, apply_server_changes, is_applied};
+pub use cache::{cache_size, evict, record_access};
 pub use change_counter::ChangeCounter;
-pub use custom_metadata::CustomMetadata;
+pub use custom_metadata::{CustomMetadata, HashPolicy};
+pub use database::{
+    AppliedState, ClientDatabase, ConflictRecord, DiskState, Transfer, TransferDirection,
+    DATABASE_FILE,
+};
 pub use file_types::*;
-pub use local_changes::{parse_change, read_changes};
+pub use ignore_rules::{IgnoreRules, IGNORE_FILE};
+pub(crate) use local_changes::read_changes_in;
+pub use local_changes::{format_change, parse_change, read_changes, rebase_local_changes};
+pub use placeholders::{create_placeholder, hydrate, pin, request_content, unpin};
+pub use selective_sync::{catch_up_changes, SelectiveSync};
 pub use server_version::ServerVersion;
 
 use crate::config::parse_path_buf;
@@ -35,12 +49,39 @@ pub struct FileHandlerConfig {
     pub temporary_directory: path::PathBuf,
 
     /// `program_data_directory` is the directory where the program data is stored.
-    /// This includes:
+    /// This is the [`ClientDatabase`], which includes:
     /// - `server_version`
     /// - `change_count`
     /// - `un-synced changes`
+    /// - `in-flight transfers`
     #[serde(deserialize_with = "parse_path_buf")]
     pub program_data_directory: path::PathBuf,
+
+    /// `ignore` is a list of gitignore-style patterns of paths that are never synced, in addition
+    /// to those in the [`IGNORE_FILE`]s. See [`IgnoreRules`].
+    #[serde(default)]
+    pub ignore: Vec<String>,
+
+    /// `selective_sync` is the set of subtrees that are synced to this device. See
+    /// [`SelectiveSync`].
+    #[serde(default)]
+    pub selective_sync: SelectiveSync,
+
+    /// `placeholders` enables on-demand files: files received from the server appear in the
+    /// `symlink_directory` straight away, but their content is only downloaded when requested.
+    /// See [`create_placeholder`].
+    #[serde(default)]
+    pub placeholders: bool,
+
+    /// `cache_budget` is the number of bytes that the content in the `storage_directory` may
+    /// use. See [`evict`]. `None` never evicts anything.
+    #[serde(default)]
+    pub cache_budget: Option<u64>,
+
+    /// `hash_policy` decides when the content of a file is hashed to find out whether it was
+    /// modified while offline. See [`HashPolicy`].
+    #[serde(default)]
+    pub hash_policy: HashPolicy,
 }
 
 impl FileHandlerConfig {
@@ -56,6 +97,11 @@ impl FileHandlerConfig {
             symlink_directory: path::PathBuf::from(symlink_directory),
             temporary_directory: path::PathBuf::from(temporary_directory),
             program_data_directory,
+            ignore: vec![],
+            selective_sync: SelectiveSync::default(),
+            placeholders: false,
+            cache_budget: None,
+            hash_policy: HashPolicy::default(),
         }
     }
 }
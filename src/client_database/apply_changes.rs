@@ -0,0 +1,1105 @@
+use std::{collections::BTreeSet, collections::HashMap, fs, io, path, time};
+
+use log::debug;
+
+use crate::{client_database, data};
+
+/// Size and modification time of `path`, or `None` if nothing is there.
+fn disk_state(path: &path::Path) -> Option<client_database::DiskState> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    Some(client_database::DiskState {
+        size: metadata.len(),
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos() as i64)
+            .unwrap_or(0),
+    })
+}
+
+/// What is at `relative_path` in the storage and symlink directories.
+fn written_state(
+    relative_path: &path::Path,
+    config: &client_database::FileHandlerConfig,
+) -> client_database::AppliedState {
+    client_database::AppliedState::Written {
+        storage: disk_state(&config.storage_directory.join(relative_path)),
+        symlink: disk_state(&config.symlink_directory.join(relative_path)),
+    }
+}
+
+/// Adds the paths inside of the directory `dir`, relative to `root`, to `paths`.
+fn paths_inside(
+    dir: &path::Path,
+    root: &path::Path,
+    paths: &mut BTreeSet<path::PathBuf>,
+) -> io::Result<()> {
+    if !fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir()) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        paths.insert(
+            path.strip_prefix(root)
+                .map_err(io::Error::other)?
+                .to_path_buf(),
+        );
+        paths_inside(&path, root, paths)?;
+    }
+    Ok(())
+}
+
+/// Records that `relative_path` is about to be written by the client itself (see
+/// [`is_applied`]).
+fn record_writing(
+    relative_path: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    open_database(config)?
+        .record_applied(
+            relative_path,
+            &[(
+                relative_path.to_string(),
+                client_database::AppliedState::Writing,
+            )],
+        )
+        .map_err(io::Error::other)
+}
+
+/// Records what the client itself has written at `relative_path` and everything inside of it
+/// (see [`is_applied`]).
+fn record_written(
+    relative_path: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let mut paths = BTreeSet::from([path::PathBuf::from(relative_path)]);
+    for root in [&config.storage_directory, &config.symlink_directory] {
+        paths_inside(&root.join(relative_path), root, &mut paths)?;
+    }
+    let states = paths
+        .into_iter()
+        .map(|path| {
+            (
+                path.to_string_lossy().into_owned(),
+                written_state(&path, config),
+            )
+        })
+        .collect::<Vec<_>>();
+    open_database(config)?
+        .record_applied(relative_path, &states)
+        .map_err(io::Error::other)
+}
+
+/// Whether what is at `relative_path` is what [`apply_change`] left there, in which case the live
+/// detection skips the event for it, since it is not a local change. Paths that are still being
+/// written, and paths inside of a directory that was removed and that are still gone, count as
+/// applied. A path that was changed after it was written does not.
+pub fn is_applied(
+    relative_path: &path::Path,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<bool> {
+    let states = open_database(config)?
+        .applied_states(relative_path.to_str().unwrap())
+        .map_err(io::Error::other)?;
+    if states
+        .iter()
+        .any(|(_, state)| *state == client_database::AppliedState::Writing)
+    {
+        return Ok(true);
+    }
+    let removed = client_database::AppliedState::Written {
+        storage: None,
+        symlink: None,
+    };
+    Ok(match states.first() {
+        Some((path, state)) if path::Path::new(path) == relative_path => {
+            *state == written_state(relative_path, config)
+        }
+        // The paths inside of a directory that still exists are recorded on their own
+        Some((_, state)) => *state == removed && written_state(relative_path, config) == removed,
+        None => false,
+    })
+}
+
+fn file_paths(
+    relative_path: &str,
+    file_type: client_database::Type,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<client_database::FilePaths> {
+    client_database::FilePaths::from_relative_path(
+        path::PathBuf::from(relative_path),
+        file_type,
+        client_database::FileLocation::StorageDir,
+        None,
+        config,
+    )
+}
+
+/// Writes the custom metadata of `file` from its storage file (or from its symlink directory, for
+/// directories), which is what the offline detection compares against. The server fields of any
+/// existing custom metadata are kept.
+fn write_custom_metadata(
+    file: &client_database::FilePaths,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let modified_path = match file.file_type() {
+        client_database::Type::Directory => file.symlink_dir_path(),
+        _ => file.storage_dir_path(),
+    };
+    let mut database = open_database(config)?;
+    let mut custom_metadata =
+        client_database::CustomMetadata::read_from_database(file, &database)?.unwrap_or_default();
+    custom_metadata.refresh(modified_path)?;
+    custom_metadata.write_to_database(file, &mut database)
+}
+
+/// Records `server_version` as the version at which the paths of `change` were last synced.
+fn mark_synced(
+    change: &data::ChangeEvent,
+    server_version: i32,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let mut database = open_database(config)?;
+    for path in change.paths() {
+        let file = file_paths(path, client_database::Type::File, config)?;
+        let mut custom_metadata =
+            match client_database::CustomMetadata::read_from_database(&file, &database)? {
+                Some(custom_metadata) => custom_metadata,
+                None => continue,
+            };
+        custom_metadata.set_synced_server_version(server_version);
+        custom_metadata.write_to_database(&file, &mut database)?;
+    }
+    Ok(())
+}
+
+/// Creates the directory at `relative_path` and any missing parents, in both the storage and
+/// symlink directories, with their custom metadata.
+fn create_directory(
+    relative_path: &path::Path,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    if relative_path.as_os_str().is_empty() {
+        return Ok(());
+    }
+    let directory = file_paths(
+        relative_path.to_str().unwrap(),
+        client_database::Type::Directory,
+        config,
+    )?;
+    if directory.storage_dir_path().is_dir()
+        && directory.symlink_dir_path().is_dir()
+        && client_database::CustomMetadata::read_from_database(&directory, &open_database(config)?)?
+            .is_some()
+    {
+        return Ok(());
+    }
+    if let Some(parent) = relative_path.parent() {
+        create_directory(parent, config)?;
+    }
+
+    fs::create_dir_all(directory.storage_dir_path())?;
+    fs::create_dir_all(directory.symlink_dir_path())?;
+    write_custom_metadata(&directory, config)
+}
+
+pub(super) fn create_parent_directory(
+    relative_path: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    match path::Path::new(relative_path).parent() {
+        Some(parent) => create_directory(parent, config),
+        None => Ok(()),
+    }
+}
+
+/// Moves the content received from the server (see
+/// [`relative_to_temporary`](client_database::relative_to_temporary)) into the storage directory
+/// and makes sure the file has a symlink and custom metadata.
+pub(super) fn write_file(
+    relative_path: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let file = file_paths(relative_path, client_database::Type::File, config)?;
+    let content_path = client_database::relative_to_temporary(file.relative_path(), config);
+    if !content_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Content of `{}` has not been received", relative_path),
+        ));
+    }
+
+    create_parent_directory(relative_path, config)?;
+    fs::rename(&content_path, file.storage_dir_path())?;
+    write_custom_metadata(&file, config)?;
+    if fs::read_link(file.symlink_dir_path()).is_err() {
+        symlink::symlink_file(file.storage_dir_path(), file.symlink_dir_path())?;
+    }
+    Ok(())
+}
+
+fn open_database(
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<client_database::ClientDatabase> {
+    client_database::ClientDatabase::open(&config.program_data_directory).map_err(io::Error::other)
+}
+
+/// Writes the received content of the file at `relative_path` (see [`write_file`]). In
+/// [`placeholders`](client_database::FileHandlerConfig::placeholders) mode, a file whose content
+/// has not been received becomes a placeholder of `size` bytes instead, and the content of a
+/// pinned file is requested.
+fn receive_file(
+    relative_path: &str,
+    size: u64,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let content_path = client_database::relative_to_temporary(relative_path.as_ref(), config);
+    if content_path.exists() || !config.placeholders {
+        write_file(relative_path, config)?;
+        return open_database(config)?
+            .remove_placeholder(relative_path)
+            .map_err(io::Error::other);
+    }
+
+    client_database::create_placeholder(relative_path, size, config)?;
+    if open_database(config)?
+        .is_pinned(relative_path)
+        .map_err(io::Error::other)?
+    {
+        client_database::request_content(relative_path, config)?;
+    }
+    Ok(())
+}
+
+fn remove_file(relative_path: &str, config: &client_database::FileHandlerConfig) -> io::Result<()> {
+    let file = file_paths(relative_path, client_database::Type::File, config)?;
+    if fs::read_link(file.symlink_dir_path()).is_ok() {
+        symlink::remove_symlink_file(file.symlink_dir_path())?;
+    }
+    let mut database = open_database(config)?;
+    client_database::CustomMetadata::remove_from_database(&file, &mut database)?;
+    if file.storage_dir_path().exists() {
+        fs::remove_file(file.storage_dir_path())?;
+    }
+    database
+        .forget_placeholders(relative_path)
+        .map_err(io::Error::other)
+}
+
+fn move_file(from: &str, to: &str, config: &client_database::FileHandlerConfig) -> io::Result<()> {
+    let from = file_paths(from, client_database::Type::File, config)?;
+    let to = file_paths(to, client_database::Type::File, config)?;
+    if !from.storage_dir_path().exists() {
+        let mut database = open_database(config)?;
+        let from_path = from.relative_path().to_str().unwrap();
+        let to_path = to.relative_path().to_str().unwrap();
+        if let Some(size) = database.placeholder(from_path).map_err(io::Error::other)? {
+            if fs::read_link(from.symlink_dir_path()).is_ok() {
+                symlink::remove_symlink_file(from.symlink_dir_path())?;
+            }
+            database
+                .move_placeholders(from_path, to_path)
+                .map_err(io::Error::other)?;
+            return client_database::create_placeholder(to_path, size, config);
+        }
+        // The file was deleted locally, so its content has to be received again
+        return write_file(to_path, config);
+    }
+
+    create_parent_directory(to.relative_path().to_str().unwrap(), config)?;
+    fs::rename(from.storage_dir_path(), to.storage_dir_path())?;
+    let mut database = open_database(config)?;
+    if client_database::CustomMetadata::read_from_database(&from, &database)?.is_some() {
+        database
+            .move_custom_metadata(
+                from.relative_path().to_str().unwrap(),
+                to.relative_path().to_str().unwrap(),
+            )
+            .map_err(io::Error::other)?;
+    } else {
+        write_custom_metadata(&to, config)?;
+    }
+    if fs::read_link(from.symlink_dir_path()).is_ok() {
+        symlink::remove_symlink_file(from.symlink_dir_path())?;
+    }
+    if fs::read_link(to.symlink_dir_path()).is_err() {
+        symlink::symlink_file(to.storage_dir_path(), to.symlink_dir_path())?;
+    }
+    Ok(())
+}
+
+/// Points every symlink inside of `dir` that points into `from` to the same path inside of `to`.
+fn repoint_symlinks(dir: &path::Path, from: &path::Path, to: &path::Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Ok(points_to) = fs::read_link(&path) {
+            if let Ok(relative_path) = points_to.strip_prefix(from) {
+                symlink::remove_symlink_file(&path)?;
+                symlink::symlink_file(to.join(relative_path), &path)?;
+            }
+        } else if path.is_dir() {
+            repoint_symlinks(&path, from, to)?;
+        }
+    }
+    Ok(())
+}
+
+fn move_directory(
+    from: &str,
+    to: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let from = file_paths(from, client_database::Type::Directory, config)?;
+    let to = file_paths(to, client_database::Type::Directory, config)?;
+
+    create_parent_directory(to.relative_path().to_str().unwrap(), config)?;
+    fs::rename(from.storage_dir_path(), to.storage_dir_path())?;
+    if from.symlink_dir_path().exists() {
+        fs::rename(from.symlink_dir_path(), to.symlink_dir_path())?;
+    } else {
+        fs::create_dir_all(to.symlink_dir_path())?;
+    }
+    // Symlinks point to absolute paths in the storage directory
+    repoint_symlinks(
+        to.symlink_dir_path(),
+        from.storage_dir_path(),
+        to.storage_dir_path(),
+    )?;
+    let mut database = open_database(config)?;
+    let from_path = from.relative_path().to_str().unwrap();
+    let to_path = to.relative_path().to_str().unwrap();
+    database
+        .move_custom_metadata(from_path, to_path)
+        .map_err(io::Error::other)?;
+    database
+        .move_placeholders(from_path, to_path)
+        .map_err(io::Error::other)?;
+    write_custom_metadata(&to, config)
+}
+
+fn remove_directory(
+    relative_path: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    let directory = file_paths(relative_path, client_database::Type::Directory, config)?;
+    if directory.symlink_dir_path().exists() {
+        fs::remove_dir_all(directory.symlink_dir_path())?;
+    }
+    if directory.storage_dir_path().exists() {
+        fs::remove_dir_all(directory.storage_dir_path())?;
+    }
+    let mut database = open_database(config)?;
+    client_database::CustomMetadata::remove_from_database(&directory, &mut database)?;
+    database
+        .forget_placeholders(relative_path)
+        .map_err(io::Error::other)
+}
+
+/// Applies a change received from the server to the storage and symlink directories and the
+/// custom metadata. Unlike [`change_events`](client_database::change_events), no local change is
+/// recorded, and everything is left in the state that the offline detection expects, so that
+/// the change is not sent back to the server. What is written at the paths of the change is
+/// recorded, so that the live detection ignores the events caused by applying it (see
+/// [`is_applied`]).
+///
+/// The content of created, modified and restored files must have been received into the
+/// temporary directory (see [`relative_to_temporary`](client_database::relative_to_temporary)),
+/// unless [`placeholders`](client_database::FileHandlerConfig::placeholders) are enabled.
+pub fn apply_change(
+    change: &data::ChangeEvent,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    debug!("`apply_change`: `{:?}`", change);
+    for path in change.paths() {
+        record_writing(path, config)?;
+    }
+    let result = write_change(change, config);
+    // Also after a failure, so that the paths do not stay recorded as being written
+    for path in change.paths() {
+        record_written(path, config)?;
+    }
+    result
+}
+
+/// The writes of [`apply_change`].
+fn write_change(
+    change: &data::ChangeEvent,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<()> {
+    match change {
+        data::ChangeEvent::File(file_event) => match file_event {
+            data::FileEvent::Create(file_create) => {
+                receive_file(file_create.path(), file_create.size(), config)
+            }
+            data::FileEvent::Modify(file_modify) => {
+                receive_file(file_modify.path(), file_modify.size(), config)
+            }
+            data::FileEvent::Move(file_move) => {
+                move_file(file_move.from_path(), file_move.to_path(), config)
+            }
+            data::FileEvent::Delete(file_delete) => remove_file(file_delete.path(), config),
+            data::FileEvent::UndoDelete(file_undo_delete) => {
+                receive_file(file_undo_delete.path(), file_undo_delete.size(), config)
+            }
+        },
+        data::ChangeEvent::Directory(directory_event) => match directory_event {
+            data::DirectoryEvent::Create(dir_create) => {
+                create_directory(path::Path::new(dir_create.path()), config)
+            }
+            data::DirectoryEvent::Move(dir_move) => {
+                move_directory(dir_move.from_path(), dir_move.to_path(), config)
+            }
+            data::DirectoryEvent::Delete(dir_delete) => remove_directory(dir_delete.path(), config),
+            data::DirectoryEvent::UndoDelete(dir_undo_delete) => {
+                create_directory(path::Path::new(dir_undo_delete.path()), config)
+            }
+        },
+        data::ChangeEvent::Symlink(symlink_event) => match symlink_event {
+            data::SymlinkEvent::Create(symlink_create) => {
+                create_parent_directory(symlink_create.path(), config)?;
+                let symlink_path = config.symlink_directory.join(symlink_create.path());
+                if fs::read_link(&symlink_path).is_ok() {
+                    symlink::remove_symlink_auto(&symlink_path)?;
+                }
+                symlink::symlink_auto(symlink_create.links_to(), symlink_path)
+            }
+            data::SymlinkEvent::Delete(symlink_delete) => {
+                let symlink_path = config.symlink_directory.join(symlink_delete.path());
+                if fs::read_link(&symlink_path).is_ok() {
+                    symlink::remove_symlink_auto(&symlink_path)?;
+                }
+                Ok(())
+            }
+        },
+    }
+}
+
+/// Path of the file whose local version is affected by `local_change`, if any.
+fn local_file_path(local_change: &data::ChangeEvent) -> Option<&str> {
+    match local_change {
+        data::ChangeEvent::File(file_event) => match file_event {
+            data::FileEvent::Create(file_create) => Some(file_create.path()),
+            data::FileEvent::Modify(file_modify) => Some(file_modify.path()),
+            data::FileEvent::Move(file_move) => Some(file_move.to_path()),
+            data::FileEvent::Delete(_) => None,
+            data::FileEvent::UndoDelete(file_undo_delete) => Some(file_undo_delete.path()),
+        },
+        _ => None,
+    }
+}
+
+/// `<name> (conflict from <device_name> <date>).<ext>`, inside of `directory`. A number is
+/// added if the name is already taken. Fails if `relative_path` has no file name, e.g. `..`.
+fn conflict_copy_path(
+    relative_path: &str,
+    directory: &path::Path,
+    device_name: &str,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<String> {
+    let relative_path = path::Path::new(relative_path);
+    let file_stem = match relative_path
+        .file_stem()
+        .or_else(|| relative_path.file_name())
+    {
+        Some(file_stem) => file_stem.to_string_lossy(),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no file name", relative_path.display()),
+            ))
+        }
+    };
+    let file_ext = match relative_path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => String::from(""),
+    };
+    let date = chrono::Local::now().format("%Y-%m-%d");
+
+    let mut k = 1;
+    loop {
+        let suffix = match k {
+            1 => String::from(""),
+            k => format!(" {}", k),
+        };
+        let file_name = format!(
+            "{} (conflict from {} {}{}){}",
+            file_stem, device_name, date, suffix, file_ext
+        );
+        let copy_path = directory.join(file_name);
+        if !config.storage_directory.join(&copy_path).exists() {
+            return Ok(copy_path.to_string_lossy().into_owned());
+        }
+        k += 1;
+    }
+}
+
+/// Copies the local version of `local_path` to a conflict copy (see [`conflict_copy_path`])
+/// and records it as a new local file. Returns the path of the copy.
+fn write_conflict_copy(
+    local_path: &str,
+    server_change: &data::ChangeEvent,
+    device_name: &str,
+    config: &client_database::FileHandlerConfig,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<String> {
+    let local_file = file_paths(local_path, client_database::Type::File, config)?;
+    // The copy must not be removed together with the directory it is in
+    let directory = match server_change {
+        data::ChangeEvent::Directory(data::DirectoryEvent::Delete(dir_delete))
+            if local_file.relative_path().starts_with(dir_delete.path()) =>
+        {
+            parent_or_root(path::Path::new(dir_delete.path()))
+        }
+        _ => parent_or_root(local_file.relative_path()),
+    };
+
+    let copy_path = conflict_copy_path(local_path, &directory, device_name, config)?;
+    let copy = file_paths(&copy_path, client_database::Type::File, config)?;
+    record_writing(&copy_path, config)?;
+    let result = fs::copy(local_file.storage_dir_path(), copy.storage_dir_path())
+        .and_then(|_| client_database::change_events::create_file(&copy, change_counter));
+    record_written(&copy_path, config)?;
+    result?;
+
+    Ok(copy_path)
+}
+
+/// Directory that `relative_path` is in, or the storage root if it has none.
+fn parent_or_root(relative_path: &path::Path) -> path::PathBuf {
+    relative_path
+        .parent()
+        .map(path::Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+/// Whether the local version of `local_file`, changed by `local_change`, differs from the
+/// server's version after `server_change`, i.e. whether it has to be kept as a conflict copy.
+/// The server's content is compared when it has been received; otherwise the server still has
+/// the synced content, which a local move does not change.
+fn differs_from_server(
+    local_file: &client_database::FilePaths,
+    local_change: &data::ChangeEvent,
+    server_change: &data::ChangeEvent,
+    config: &client_database::FileHandlerConfig,
+) -> io::Result<bool> {
+    let server_path = match server_change {
+        data::ChangeEvent::File(data::FileEvent::Create(file_create)) => Some(file_create.path()),
+        data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => Some(file_modify.path()),
+        data::ChangeEvent::File(data::FileEvent::UndoDelete(file_undo_delete)) => {
+            Some(file_undo_delete.path())
+        }
+        _ => None,
+    };
+    match server_path {
+        Some(server_path) => {
+            let content_path = client_database::relative_to_temporary(server_path.as_ref(), config);
+            if !content_path.is_file() {
+                return Ok(true);
+            }
+            Ok(
+                client_database::CustomMetadata::hash_of_file(&content_path)?
+                    != client_database::CustomMetadata::hash_of_file(
+                        local_file.storage_dir_path(),
+                    )?,
+            )
+        }
+        None => Ok(!matches!(
+            local_change,
+            data::ChangeEvent::File(data::FileEvent::Move(_))
+        )),
+    }
+}
+
+/// Whether every path of `local_change` is inside of the directory moved by `server_change`, in
+/// which case the local files are moved along with it and the change is rebased (see
+/// [`rebase_local_changes`](client_database::rebase_local_changes)) instead of conflicting.
+fn carried_by_move(local_change: &data::ChangeEvent, server_change: &data::ChangeEvent) -> bool {
+    let moved = match server_change {
+        data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => dir_move.from_path(),
+        _ => return false,
+    };
+    local_change.paths().into_iter().all(|path| {
+        path.strip_prefix(moved)
+            .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+/// Resolves the pending local changes that collide with `server_change`, before it is applied.
+/// The local version of each affected file is kept as a conflict copy, which is recorded as a
+/// new local file, unless it is the same as the server's (see [`differs_from_server`]). Local
+/// moves are undone so that the server change applies to the server's
+/// paths, and the colliding local changes are removed. Each conflict is recorded in the
+/// [`ClientDatabase`](client_database::ClientDatabase) for the user to review.
+fn resolve_conflicts(
+    change_id: i32,
+    server_change: &data::ChangeEvent,
+    device_name: &str,
+    config: &client_database::FileHandlerConfig,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<Vec<data::Conflict>> {
+    let mut database = client_database::ClientDatabase::open(&config.program_data_directory)
+        .map_err(io::Error::other)?;
+
+    let server_changes = [(change_id, server_change.clone())];
+    let mut conflict_copies: HashMap<String, String> = HashMap::new();
+    let mut conflicts = vec![];
+    for (change_number, local_change) in client_database::read_changes_in(&database)? {
+        let conflict = match data::find_conflicts(
+            std::slice::from_ref(&local_change),
+            &server_changes,
+        )
+        .pop()
+        {
+            Some(conflict) => conflict,
+            None => continue,
+        };
+        if carried_by_move(&local_change, server_change) {
+            continue;
+        }
+
+        let mut conflict_copy = None;
+        if let Some(local_path) = local_file_path(&local_change) {
+            let local_file = file_paths(local_path, client_database::Type::File, config)?;
+            if let Some(copy_path) = conflict_copies.get(local_path) {
+                conflict_copy = Some(copy_path.clone());
+            } else if local_file.storage_dir_path().is_file()
+                && differs_from_server(&local_file, &local_change, server_change, config)?
+            {
+                let copy_path = write_conflict_copy(
+                    local_path,
+                    server_change,
+                    device_name,
+                    config,
+                    change_counter,
+                )?;
+                conflict_copies.insert(local_path.to_string(), copy_path.clone());
+                conflict_copy = Some(copy_path);
+            }
+        }
+        if let data::ChangeEvent::File(data::FileEvent::Move(file_move)) = &local_change {
+            let from = file_paths(file_move.from_path(), client_database::Type::File, config)?;
+            let to = file_paths(file_move.to_path(), client_database::Type::File, config)?;
+            if !from.storage_dir_path().exists() && to.storage_dir_path().exists() {
+                move_file(file_move.to_path(), file_move.from_path(), config)?;
+            }
+        }
+
+        change_counter.remove(change_number as i64)?;
+        database
+            .record_conflict(&conflict, conflict_copy.as_deref())
+            .map_err(io::Error::other)?;
+        conflicts.push(conflict);
+    }
+    Ok(conflicts)
+}
+
+/// Applies `changes` (see [`apply_change`]) in order and updates the server version after each
+/// one, so that an interrupted run continues after the last applied change. Changes at or below
+/// the current server version have already been applied and are skipped.
+///
+/// Pending local changes that collide with a change are resolved first (see
+/// [`ClientDatabase::conflicts`](client_database::ClientDatabase::conflicts)); the local version
+/// of a file is kept as `<name> (conflict from <device_name> <date>).<ext>`. The remaining local
+/// changes are rebased over each change. Changes to paths that are ignored on this client (see
+/// [`IgnoreRules`](client_database::IgnoreRules)) or outside of the
+/// [`selective_sync`](client_database::FileHandlerConfig::selective_sync) subtrees are skipped.
+/// Returns the conflicts.
+pub fn apply_server_changes(
+    changes: &[(i32, data::ChangeEvent)],
+    device_name: &str,
+    config: &client_database::FileHandlerConfig,
+    server_version: &mut client_database::ServerVersion,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<Vec<data::Conflict>> {
+    let mut conflicts = vec![];
+    for (change_id, change) in changes {
+        if *change_id <= server_version.server_version() {
+            continue;
+        }
+        let is_dir = matches!(change, data::ChangeEvent::Directory(_));
+        let ignore_rules = client_database::IgnoreRules::new(config);
+        if change
+            .paths()
+            .into_iter()
+            .all(|path| ignore_rules.is_ignored(path::Path::new(path), is_dir))
+        {
+            debug!("Ignored server change: `{:?}`", change);
+            server_version.set(*change_id)?;
+            continue;
+        }
+        let change = match config.selective_sync.local_change(change) {
+            Some(change) => change,
+            None => {
+                debug!(
+                    "Server change outside of the synced subtrees: `{:?}`",
+                    change
+                );
+                server_version.set(*change_id)?;
+                continue;
+            }
+        };
+        let change = &change;
+        conflicts.extend(resolve_conflicts(
+            *change_id,
+            change,
+            device_name,
+            config,
+            change_counter,
+        )?);
+        apply_change(change, config)?;
+        mark_synced(change, *change_id, config)?;
+        client_database::rebase_local_changes(&[(*change_id, change.clone())], config)?;
+        server_version.set(*change_id)?;
+    }
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    use super::{apply_change, apply_server_changes, is_applied};
+    use crate::data::InnerEventTrait;
+    use crate::{client_database, client_detect_offline, data, testing_utils};
+
+    fn receive(content: &str, relative_path: &str, config: &client_database::FileHandlerConfig) {
+        let content_path = client_database::relative_to_temporary(relative_path.as_ref(), config);
+        fs::create_dir_all(content_path.parent().unwrap()).unwrap();
+        fs::write(content_path, content).unwrap();
+    }
+
+    #[test]
+    fn test_apply_server_changes() {
+        let (config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+
+        receive("a", "dir/a.txt", &config);
+        receive("b", "b.txt", &config);
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::DirectoryCreate::new("dir".to_string()).into()),
+            (2, data::FileCreate::new(1, "dir/a.txt".to_string()).into()),
+            (3, data::FileCreate::new(1, "b.txt".to_string()).into()),
+            (
+                4,
+                data::FileMove::new("b.txt".to_string(), "dir/sub/b.txt".to_string()).into(),
+            ),
+            (
+                5,
+                data::DirectoryMove::new("dir".to_string(), "moved".to_string()).into(),
+            ),
+            (6, data::DirectoryCreate::new("empty".to_string()).into()),
+            (7, data::DirectoryDelete::new("empty".to_string()).into()),
+        ];
+        let conflicts = apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(server_version.server_version(), 7);
+
+        assert_eq!(
+            fs::read_to_string(config.symlink_directory.join("moved/a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            fs::read_link(config.symlink_directory.join("moved/sub/b.txt")).unwrap(),
+            config.storage_directory.join("moved/sub/b.txt")
+        );
+        assert!(!config.symlink_directory.join("dir").exists());
+        assert!(!config.storage_directory.join("empty").exists());
+
+        // The live detection ignores what was just applied
+        let applied = |path: &str| is_applied(path::Path::new(path), &config).unwrap();
+        assert!(applied("moved"));
+        assert!(applied("moved/sub/b.txt"));
+        assert!(applied("dir"));
+        assert!(applied("dir/a.txt"));
+        assert!(!applied("moved2"));
+        assert!(!applied("other.txt"));
+
+        // Applying the same changes again does nothing
+        apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+
+        receive("aa", "moved/a.txt", &config);
+        apply_change(
+            &data::FileModify::new(2, "moved/a.txt".to_string()).into(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(config.symlink_directory.join("moved/a.txt")).unwrap(),
+            "aa"
+        );
+
+        // No echo changes are detected
+        client_detect_offline::detect_offline_changes(&config);
+        assert!(client_database::read_changes(&config).unwrap().is_empty());
+
+        // A local edit right after the change was applied is not ignored
+        assert!(applied("moved/a.txt"));
+        fs::write(config.symlink_directory.join("moved/a.txt"), "local").unwrap();
+        assert!(!applied("moved/a.txt"));
+
+        apply_change(
+            &data::FileDelete::new("moved/a.txt".to_string()).into(),
+            &config,
+        )
+        .unwrap();
+        assert!(!config.storage_directory.join("moved/a.txt").exists());
+        assert!(fs::read_link(config.symlink_directory.join("moved/a.txt")).is_err());
+
+        apply_change(
+            &data::SymlinkCreate::new("link".to_string(), "moved/sub".to_string()).into(),
+            &config,
+        )
+        .unwrap();
+        assert!(fs::read_link(config.symlink_directory.join("link")).is_ok());
+        apply_change(
+            &data::SymlinkDelete::new("link".to_string()).into(),
+            &config,
+        )
+        .unwrap();
+        assert!(fs::read_link(config.symlink_directory.join("link")).is_err());
+
+        let error = apply_change(
+            &data::FileUndoDelete::new(0, "missing.txt".to_string()).into(),
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    /// Syncs `a.txt` from the server, changes it locally with `local_change` and then applies
+    /// `server_change`. Returns the conflicts, the path of the conflict copy (if one was written)
+    /// and the pending local changes.
+    fn collide(
+        local_change: impl FnOnce(
+            &client_database::FileHandlerConfig,
+            &mut client_database::ChangeCounter,
+        ),
+        server_change: data::ChangeEvent,
+        server_content: Option<&str>,
+    ) -> (
+        client_database::FileHandlerConfig,
+        Vec<data::Conflict>,
+        Option<String>,
+        Vec<data::ChangeEvent>,
+    ) {
+        let (config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+
+        receive("server", "a.txt", &config);
+        let synced = [(1, data::FileCreate::new(6, "a.txt".to_string()).into())];
+        apply_server_changes(
+            &synced,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+
+        local_change(&config, &mut change_counter);
+
+        if let Some(server_content) = server_content {
+            receive(server_content, server_change.paths()[0], &config);
+        }
+        let conflicts = apply_server_changes(
+            &[(2, server_change)],
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+
+        let database =
+            client_database::ClientDatabase::open(&config.program_data_directory).unwrap();
+        let records = database.conflicts().unwrap();
+        assert_eq!(records.len(), conflicts.len());
+        let conflict_copy = records[0].conflict_copy().map(str::to_string);
+        let local_changes = client_database::read_changes(&config)
+            .unwrap()
+            .into_iter()
+            .map(|(_, change)| change)
+            .collect();
+        (config, conflicts, conflict_copy, local_changes)
+    }
+
+    fn modify_locally(
+        relative_path: &str,
+        config: &client_database::FileHandlerConfig,
+        change_counter: &mut client_database::ChangeCounter,
+    ) {
+        let file = client_database::FilePaths::from_relative_path(
+            relative_path.into(),
+            client_database::Type::File,
+            client_database::FileLocation::StorageDir,
+            None,
+            config,
+        )
+        .unwrap();
+        fs::write(file.symlink_dir_path(), "local").unwrap();
+        client_database::change_events::modify_file(&file, change_counter).unwrap();
+    }
+
+    #[test]
+    fn test_conflict_copies() {
+        let date = chrono::Local::now().format("%Y-%m-%d");
+        let copy_of = |stem: &str| format!("{} (conflict from laptop {}).txt", stem, date);
+        let read = |config: &client_database::FileHandlerConfig, relative_path: &str| {
+            fs::read_to_string(config.symlink_directory.join(relative_path)).unwrap()
+        };
+
+        // Modify/modify: the server's version replaces the file
+        let (config, conflicts, conflict_copy, local_changes) = collide(
+            |config, change_counter| modify_locally("a.txt", config, change_counter),
+            data::FileModify::new(7, "a.txt".to_string()).into(),
+            Some("server2"),
+        );
+        let conflict_copy = conflict_copy.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflict_copy, copy_of("a"));
+        assert_eq!(read(&config, "a.txt"), "server2");
+        assert_eq!(read(&config, &conflict_copy), "local");
+        assert_eq!(
+            local_changes,
+            vec![data::FileCreate::new(0, conflict_copy.clone()).into()]
+        );
+
+        // Modify/delete
+        let (config, _, conflict_copy, local_changes) = collide(
+            |config, change_counter| modify_locally("a.txt", config, change_counter),
+            data::FileDelete::new("a.txt".to_string()).into(),
+            None,
+        );
+        let conflict_copy = conflict_copy.unwrap();
+        assert!(!config.storage_directory.join("a.txt").exists());
+        assert_eq!(read(&config, &conflict_copy), "local");
+        assert_eq!(local_changes.len(), 1);
+
+        // Move/move: the file ends up at the server's path
+        let (config, conflicts, conflict_copy, local_changes) = collide(
+            |config, change_counter| {
+                symlink::remove_symlink_file(config.symlink_directory.join("a.txt")).unwrap();
+                symlink::symlink_file(
+                    config.storage_directory.join("a.txt"),
+                    config.symlink_directory.join("b.txt"),
+                )
+                .unwrap();
+                client_database::change_events::move_file(
+                    &"a.txt".into(),
+                    &"b.txt".into(),
+                    config,
+                    change_counter,
+                    client_database::Type::Symlink,
+                )
+                .unwrap();
+            },
+            data::FileMove::new("a.txt".to_string(), "c.txt".to_string()).into(),
+            None,
+        );
+        // The content is the same on both sides, so only the conflict is recorded
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflict_copy, None);
+        assert_eq!(read(&config, "c.txt"), "server");
+        assert!(!config.storage_directory.join("a.txt").exists());
+        assert!(!config.storage_directory.join("b.txt").exists());
+        assert!(local_changes.is_empty());
+
+        // Create/create
+        let (config, _, conflict_copy, local_changes) = collide(
+            |config, change_counter| {
+                let file = client_database::FilePaths::from_relative_path(
+                    "new.txt".into(),
+                    client_database::Type::File,
+                    client_database::FileLocation::StorageDir,
+                    None,
+                    config,
+                )
+                .unwrap();
+                fs::write(file.storage_dir_path(), "local").unwrap();
+                client_database::change_events::create_file(&file, change_counter).unwrap();
+            },
+            data::FileCreate::new(6, "new.txt".to_string()).into(),
+            Some("server"),
+        );
+        let conflict_copy = conflict_copy.unwrap();
+        assert_eq!(conflict_copy, copy_of("new"));
+        assert_eq!(read(&config, "new.txt"), "server");
+        assert_eq!(read(&config, &conflict_copy), "local");
+        assert_eq!(local_changes[0].inner_event(), data::InnerEvent::Create);
+
+        // The conflict copies are not detected as changes again
+        client_detect_offline::detect_offline_changes(&config);
+        assert_eq!(client_database::read_changes(&config).unwrap().len(), 1);
+
+        // Modify/modify to the same content: no conflict copy is needed
+        let (config, conflicts, conflict_copy, local_changes) = collide(
+            |config, change_counter| modify_locally("a.txt", config, change_counter),
+            data::FileModify::new(5, "a.txt".to_string()).into(),
+            Some("local"),
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflict_copy, None);
+        assert_eq!(read(&config, "a.txt"), "local");
+        assert!(local_changes.is_empty());
+
+        // Changes inside of a directory moved by the server are carried along
+        let (config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+        receive("server", "docs/a.txt", &config);
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::DirectoryCreate::new("docs".to_string()).into()),
+            (2, data::FileCreate::new(6, "docs/a.txt".to_string()).into()),
+        ];
+        apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        modify_locally("docs/a.txt", &config, &mut change_counter);
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![(
+            3,
+            data::DirectoryMove::new("docs".to_string(), "documents".to_string()).into(),
+        )];
+        let conflicts = apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(read(&config, "documents/a.txt"), "local");
+        assert_eq!(
+            client_database::read_changes(&config)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(
+                1,
+                data::FileModify::new(0, "documents/a.txt".to_string()).into()
+            )]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::{fs, io, path, time};
+
+use log::{debug, info};
+
+use super::{
+    create_placeholder, read_changes_in, ClientDatabase, CustomMetadata, CustomMetadataType,
+    FileHandlerConfig, FileLocation, FilePaths, HashPolicy, TransferDirection, Type,
+};
+
+fn open_database(config: &FileHandlerConfig) -> io::Result<ClientDatabase> {
+    ClientDatabase::open(&config.program_data_directory).map_err(io::Error::other)
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Whether `path` is `changed` or inside of it.
+fn in_subtree(path: &str, changed: &str) -> bool {
+    match path.strip_prefix(changed) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Every file with content in the storage directory, relative to it, with its size.
+fn stored_files(
+    dir: &path::Path,
+    config: &FileHandlerConfig,
+    files: &mut Vec<(String, u64)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_symlink() || CustomMetadataType::is_custom_metadata(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            stored_files(&path, config, files)?;
+        } else {
+            let relative_path = path
+                .strip_prefix(&config.storage_directory)
+                .map_err(io::Error::other)?;
+            files.push((
+                relative_path.to_string_lossy().to_string(),
+                path.metadata()?.len(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the stored file at `relative_path` is known to be the content that was last synced
+/// with the server. Files without custom metadata or a
+/// [`synced_server_version`](CustomMetadata::synced_server_version), and files whose content was
+/// changed since the last scan (compared as the offline detection does, following the
+/// [`hash_policy`](FileHandlerConfig::hash_policy)), are not.
+fn is_synced_content(
+    relative_path: &str,
+    database: &ClientDatabase,
+    config: &FileHandlerConfig,
+) -> io::Result<bool> {
+    let file = FilePaths::from_relative_path(
+        relative_path.into(),
+        Type::File,
+        FileLocation::StorageDir,
+        None,
+        config,
+    )?;
+    let custom_metadata = match CustomMetadata::read_from_database(&file, database)? {
+        Some(custom_metadata) => custom_metadata,
+        None => return Ok(false),
+    };
+    if custom_metadata.synced_server_version().is_none() {
+        return Ok(false);
+    }
+
+    let stat_modified =
+        custom_metadata.is_modified(&CustomMetadata::stat(file.storage_dir_path())?);
+    let content_modified = match (config.hash_policy, custom_metadata.hash()) {
+        (HashPolicy::Never, _) | (_, None) => stat_modified,
+        (HashPolicy::WhenChanged, _) if !stat_modified => false,
+        (_, Some(hash)) => CustomMetadata::hash_of_file(file.storage_dir_path())? != hash,
+    };
+    Ok(!content_modified)
+}
+
+/// Records that the file at `relative_path` was accessed now, which keeps it from being evicted
+/// before files that were accessed longer ago (see [`evict`]).
+pub fn record_access(relative_path: &str, config: &FileHandlerConfig) -> io::Result<()> {
+    open_database(config)?
+        .set_last_accessed(relative_path, now())
+        .map_err(io::Error::other)
+}
+
+/// Total size of the content in the storage directory.
+pub fn cache_size(config: &FileHandlerConfig) -> io::Result<u64> {
+    let mut files = vec![];
+    stored_files(&config.storage_directory, config, &mut files)?;
+    Ok(files.iter().map(|(_, size)| size).sum())
+}
+
+/// Evicts the least recently used files from the storage directory until it fits in the
+/// [`cache_budget`](FileHandlerConfig::cache_budget). Evicted files become placeholders (see
+/// [`create_placeholder`]), so they stay in the symlink directory and can be downloaded again.
+///
+/// Only fully synced files are evicted: never pinned files, files that are being uploaded, files
+/// with local changes that have not been sent to the server or files edited since they were last
+/// scanned (see [`is_synced_content`]). Files are ordered by the last
+/// [`record_access`], or else by their last modified time. Returns the evicted files.
+pub fn evict(config: &FileHandlerConfig) -> io::Result<Vec<String>> {
+    let budget = match config.cache_budget {
+        Some(budget) => budget,
+        None => return Ok(vec![]),
+    };
+    let mut files = vec![];
+    stored_files(&config.storage_directory, config, &mut files)?;
+    let mut cache_size: u64 = files.iter().map(|(_, size)| size).sum();
+    if cache_size <= budget {
+        return Ok(vec![]);
+    }
+    info!(
+        "Storage directory uses {} bytes of a {} byte budget, evicting files",
+        cache_size, budget
+    );
+
+    let database = open_database(config)?;
+    let changed_paths = read_changes_in(&database)?
+        .into_iter()
+        .flat_map(|(_, change)| {
+            change
+                .paths()
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .chain(
+            database
+                .transfers()
+                .map_err(io::Error::other)?
+                .into_iter()
+                .filter(|transfer| transfer.direction() == TransferDirection::Upload)
+                .map(|transfer| transfer.path().to_string()),
+        )
+        .collect::<Vec<_>>();
+
+    let mut candidates = vec![];
+    for (relative_path, size) in files {
+        if database
+            .is_pinned(&relative_path)
+            .map_err(io::Error::other)?
+            || changed_paths
+                .iter()
+                .any(|changed| in_subtree(&relative_path, changed))
+            || !is_synced_content(&relative_path, &database, config)?
+        {
+            continue;
+        }
+        let last_accessed = match database
+            .last_accessed(&relative_path)
+            .map_err(io::Error::other)?
+        {
+            Some(last_accessed) => last_accessed,
+            None => super::CustomMetadata::last_modified_of_file(
+                &config.storage_directory.join(&relative_path),
+            )?,
+        };
+        candidates.push((last_accessed, relative_path, size));
+    }
+    candidates.sort();
+    drop(database);
+
+    let mut evicted = vec![];
+    for (_, relative_path, size) in candidates {
+        if cache_size <= budget {
+            break;
+        }
+        debug!("Evicting `{}` ({} bytes)", relative_path, size);
+        create_placeholder(&relative_path, size, config)?;
+        cache_size -= size;
+        evicted.push(relative_path);
+    }
+    Ok(evicted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{cache_size, evict};
+    use crate::{client_database, client_detect_offline, data, testing_utils};
+
+    #[test]
+    fn test_evict() {
+        let (mut config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+
+        let mut changes: Vec<(i32, data::ChangeEvent)> = vec![];
+        for (change_id, path) in ["a.txt", "b.txt", "c.txt", "d.txt"].iter().enumerate() {
+            let content_path = client_database::relative_to_temporary(path.as_ref(), &config);
+            fs::create_dir_all(content_path.parent().unwrap()).unwrap();
+            fs::write(content_path, "123456").unwrap();
+            changes.push((
+                change_id as i32 + 1,
+                data::FileCreate::new(6, path.to_string()).into(),
+            ));
+        }
+        client_database::apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+
+        let mut database =
+            client_database::ClientDatabase::open(&config.program_data_directory).unwrap();
+        for (last_accessed, path) in ["a.txt", "b.txt", "c.txt", "d.txt"].iter().enumerate() {
+            database
+                .set_last_accessed(path, last_accessed as u64)
+                .unwrap();
+        }
+        client_database::pin("b.txt", &config).unwrap();
+        fs::write(config.symlink_directory.join("c.txt"), "654321").unwrap();
+        client_detect_offline::detect_offline_changes(&config);
+        assert_eq!(client_database::read_changes(&config).unwrap().len(), 1);
+
+        // No budget
+        assert!(evict(&config).unwrap().is_empty());
+
+        config.cache_budget = Some(10);
+        assert_eq!(
+            evict(&config).unwrap(),
+            vec!["a.txt".to_string(), "d.txt".to_string()]
+        );
+        assert_eq!(cache_size(&config).unwrap(), 12);
+        assert_eq!(database.placeholder("a.txt").unwrap(), Some(6));
+        assert!(fs::read_link(config.symlink_directory.join("a.txt")).is_ok());
+        assert!(!config.storage_directory.join("a.txt").exists());
+
+        // Evicted files are not deleted files
+        client_detect_offline::detect_offline_changes(&config);
+        assert_eq!(client_database::read_changes(&config).unwrap().len(), 1);
+
+        // Files edited since the last scan, and files that were never synced, are kept
+        let content_path = client_database::relative_to_temporary("e.txt".as_ref(), &config);
+        fs::write(content_path, "123456").unwrap();
+        client_database::apply_server_changes(
+            &[(5, data::FileCreate::new(6, "e.txt".to_string()).into())],
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        fs::write(config.storage_directory.join("e.txt"), "abcdef").unwrap();
+
+        let unsynced = client_database::FilePaths::from_relative_path(
+            "f.txt".into(),
+            client_database::Type::File,
+            client_database::FileLocation::StorageDir,
+            None,
+            &config,
+        )
+        .unwrap();
+        fs::write(unsynced.storage_dir_path(), "123456").unwrap();
+        client_database::CustomMetadata::of_file(unsynced.storage_dir_path())
+            .unwrap()
+            .write_to_database(&unsynced, change_counter.database())
+            .unwrap();
+        symlink::symlink_file(unsynced.storage_dir_path(), unsynced.symlink_dir_path()).unwrap();
+
+        config.cache_budget = Some(0);
+        assert!(evict(&config).unwrap().is_empty());
+        assert_eq!(cache_size(&config).unwrap(), 24);
+    }
+}
@@ -1,61 +1,88 @@
-use std::{fs, path};
+use std::{io, path};
 
 use log::info;
 
+use super::ClientDatabase;
+
 /// Local change counter
-/// Stores how many changes have been made to the local files.
-/// Used primarily for creating unique file names.
-#[derive(Debug, Clone)]
+/// Stores how many changes have been made to the local files and records each change in the
+/// [`ClientDatabase`], numbered by the counter.
+#[derive(Debug)]
 pub struct ChangeCounter {
-    counter_path: path::PathBuf,
+    database: ClientDatabase,
     change_count: i64,
-    change_directory: path::PathBuf,
 }
 
 impl ChangeCounter {
-    pub fn init(program_data_directory: &path::PathBuf) -> Self {
+    pub fn init(program_data_directory: &path::Path) -> io::Result<Self> {
         info!("Initializing change counter");
-        let counter_path = program_data_directory.join("change_count");
-        let change_count = if !counter_path.exists() {
-            fs::write(&counter_path, "0").unwrap();
-            0
-        } else {
-            fs::read_to_string(&counter_path)
-                .unwrap()
-                .parse::<i64>()
-                .unwrap()
-        };
-
-        let change_dir = program_data_directory.join("changes");
-        if !change_dir.exists() {
-            fs::create_dir(&change_dir).unwrap();
-        }
-
-        Self {
-            counter_path,
+        let database = ClientDatabase::open(program_data_directory).map_err(io::Error::other)?;
+        let change_count = database.change_count().map_err(io::Error::other)?;
+
+        Ok(Self {
+            database,
             change_count,
-            change_directory: program_data_directory.join("changes"),
-        }
+        })
     }
 
-    pub fn increment(&mut self) -> i64 {
-        self.change_count += 1;
-        fs::write(&self.counter_path, self.change_count.to_string()).unwrap();
-        self.change_count
+    /// Stores `change` under the next change number, which is returned. The change count and
+    /// the change are updated in the same transaction.
+    pub fn record(&mut self, change: &str) -> io::Result<i64> {
+        self.change_count = self
+            .database
+            .record_change(change)
+            .map_err(io::Error::other)?;
+        Ok(self.change_count)
     }
 
-    pub fn next_path(&mut self) -> path::PathBuf {
-        let change_count = self.increment();
-        self.change_directory.join(format!("{}.tmp", change_count))
+    /// Stores the intent to make `change` before its steps are applied, so that they can be
+    /// completed or rolled back after a crash (see
+    /// [`change_events::recover`](super::change_events::recover)). Returns the id of the intent.
+    pub fn begin_intent(&mut self, change: &str) -> io::Result<i64> {
+        self.database.begin_intent(change).map_err(io::Error::other)
     }
 
-    pub fn set_count(&mut self, count: i64) -> i64 {
-        self.change_count = count;
-        fs::write(&self.counter_path, self.change_count.to_string()).unwrap();
-        self.change_count
+    /// Records the change of the intent `id` once all of its steps are applied. Returns the
+    /// change number.
+    pub fn complete_intent(&mut self, id: i64) -> io::Result<i64> {
+        self.change_count = self
+            .database
+            .complete_intent(id)
+            .map_err(io::Error::other)?;
+        Ok(self.change_count)
+    }
+
+    /// Forgets the intent `id` without recording its change, once its steps are rolled back.
+    pub fn abandon_intent(&mut self, id: i64) -> io::Result<()> {
+        self.database.abandon_intent(id).map_err(io::Error::other)
+    }
+
+    /// Every intent that was begun but neither completed nor abandoned.
+    pub fn intents(&self) -> io::Result<Vec<(i64, String)>> {
+        self.database.intents().map_err(io::Error::other)
+    }
+
+    /// Removes every recorded change, e.g. once they have been sent to the server. The change
+    /// count is kept.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.database.clear_changes().map_err(io::Error::other)
+    }
+
+    /// Removes a single recorded change, e.g. one that was superseded by a change from the
+    /// server.
+    pub fn remove(&mut self, change_number: i64) -> io::Result<()> {
+        self.database
+            .remove_change(change_number)
+            .map_err(io::Error::other)
     }
 
     pub fn change_count(&self) -> i64 {
         self.change_count
     }
+
+    /// The database that the changes are recorded in, e.g. to update the
+    /// [`CustomMetadata`](super::CustomMetadata) of a file together with its change.
+    pub fn database(&mut self) -> &mut ClientDatabase {
+        &mut self.database
+    }
 }
@@ -1,30 +1,41 @@
-use std::fs;
+use std::{fs, io};
 
 use log::debug;
 
-use crate::client_database::{self, custom_metadata};
+use crate::{client_database, data};
 
 pub fn create_dir(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     debug!("`create_dir`: `{}`", file.relative_path().display());
+    let create_change = client_database::format_change(
+        &data::DirectoryCreate::new(file.relative_path().to_str().unwrap().to_string()).into(),
+    );
+    let intent = change_counter.begin_intent(&create_change)?;
+    create_dir_steps(file, change_counter.database())?;
     {
-        // Create directory in storage directory
-        fs::create_dir(file.storage_dir_path()).unwrap();
+        // Make `create dir` change event
+        change_counter.complete_intent(intent)?;
     }
+    Ok(())
+}
+
+/// The steps of [`create_dir`], skipping those that are already done.
+pub(super) fn create_dir_steps(
+    file: &client_database::FilePaths,
+    database: &mut client_database::ClientDatabase,
+) -> io::Result<()> {
     {
-        // Create custom metadata file in storage directory
-        let last_modified =
-            custom_metadata::CustomMetadata::last_modified_of_file(file.storage_dir_path())
-                .unwrap();
-        let custom_metadata_file = custom_metadata::CustomMetadata::new(last_modified);
-        custom_metadata_file.write_to_file(file).unwrap();
+        // Create directory in storage directory
+        if !file.storage_dir_path().is_dir() {
+            fs::create_dir(file.storage_dir_path())?;
+        }
     }
     {
-        // Make `create dir` change event
-        dbg!(file.storage_dir_path());
-        let create_change = format!("create_dir\n{}", file.relative_path().to_str().unwrap());
-        fs::write(change_counter.next_path(), create_change).unwrap();
+        // Create the custom metadata
+        let custom_metadata = client_database::CustomMetadata::of_file(file.storage_dir_path())?;
+        custom_metadata.write_to_database(file, database)?;
     }
+    Ok(())
 }
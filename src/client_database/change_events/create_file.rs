@@ -1,30 +1,47 @@
-use std::fs;
+use std::{fs, io};
 
 use log::debug;
 
-use crate::client_database;
+use crate::{client_database, data};
 
 /// This function assumes that the real file is currently located in the storage directory.
 pub fn create_file(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     debug!("`create_file`: `{}`", file.relative_path().display());
+    let create_change = client_database::format_change(
+        &data::FileCreate::new(
+            fs::metadata(file.storage_dir_path())?.len(),
+            file.relative_path().to_str().unwrap().to_string(),
+        )
+        .into(),
+    );
+    let intent = change_counter.begin_intent(&create_change)?;
+    create_file_steps(file, change_counter.database())?;
     {
-        // Create a custom metadata file
-        let last_modified =
-            client_database::CustomMetadata::last_modified_of_file(&file.storage_dir_path())
-                .unwrap();
-        let custom_metadata_file = client_database::CustomMetadata::new(last_modified);
-        custom_metadata_file.write_to_file(&file).unwrap();
+        // Add a `create file` change
+        change_counter.complete_intent(intent)?;
     }
+    Ok(())
+}
+
+/// The steps of [`create_file`], skipping those that are already done. The symlink is created
+/// before the custom metadata, so that there is never custom metadata without a symlink.
+pub(super) fn create_file_steps(
+    file: &client_database::FilePaths,
+    database: &mut client_database::ClientDatabase,
+) -> io::Result<()> {
     {
         // Create a symlink in the symlink directory
-        symlink::symlink_file(&file.storage_dir_path(), &file.symlink_dir_path()).unwrap();
+        if fs::read_link(file.symlink_dir_path()).is_err() {
+            symlink::symlink_file(file.storage_dir_path(), file.symlink_dir_path())?;
+        }
     }
     {
-        // Add a `create file` change
-        let create_change = format!("create_file\n{}", file.relative_path().to_str().unwrap());
-        fs::write(change_counter.next_path(), create_change).unwrap();
+        // Create the custom metadata
+        let custom_metadata = client_database::CustomMetadata::of_file(file.storage_dir_path())?;
+        custom_metadata.write_to_database(file, database)?;
     }
+    Ok(())
 }
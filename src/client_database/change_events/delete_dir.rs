@@ -1,35 +1,46 @@
-use std::fs;
+use std::{fs, io};
 
 use log::debug;
 
-use crate::client_database;
+use crate::{client_database, data};
 
 pub fn delete_dir(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     debug!("`delete_dir`: `{}`", file.relative_path().display());
+    let delete_change = client_database::format_change(
+        &data::DirectoryDelete::new(file.relative_path().to_str().unwrap().to_string()).into(),
+    );
+    let intent = change_counter.begin_intent(&delete_change)?;
+    delete_dir_steps(file, change_counter.database())?;
     {
-        // Delete custom metadata if exists
-        if file.custom_metadata_path().exists() {
-            fs::remove_file(file.custom_metadata_path()).unwrap();
-        }
+        // Add delete directory change
+        change_counter.complete_intent(intent)?;
+    }
+    Ok(())
+}
+
+/// The steps of [`delete_dir`], skipping those that are already done.
+pub(super) fn delete_dir_steps(
+    file: &client_database::FilePaths,
+    database: &mut client_database::ClientDatabase,
+) -> io::Result<()> {
+    {
+        // Delete the custom metadata of the directory and of everything inside of it
+        client_database::CustomMetadata::remove_from_database(file, database)?;
     }
     {
         // Delete directory in symlink if exists
         if file.symlink_dir_path().exists() {
-            fs::remove_dir_all(file.symlink_dir_path()).unwrap();
+            fs::remove_dir_all(file.symlink_dir_path())?;
         }
     }
     {
         // Delete directory in storage if exists
         if file.storage_dir_path().exists() {
-            fs::remove_dir_all(file.storage_dir_path()).unwrap();
+            fs::remove_dir_all(file.storage_dir_path())?;
         }
     }
-    {
-        // Add delete directory change
-        let delete_change = format!("delete_dir\n{}", file.relative_path().display());
-        fs::write(change_counter.next_path(), delete_change).unwrap();
-    }
+    Ok(())
 }
@@ -1,38 +1,49 @@
-use std::fs;
+use std::{fs, io};
 
 use log::debug;
 
-use crate::client_database;
+use crate::{client_database, data};
 
 pub fn delete_file(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     debug!("`delete_file`: `{}`", file.relative_path().display());
-    // Delete symlink
+    let delete_change = client_database::format_change(
+        &data::FileDelete::new(file.relative_path().to_str().unwrap().to_string()).into(),
+    );
+    let intent = change_counter.begin_intent(&delete_change)?;
+    delete_file_steps(file, change_counter.database())?;
     {
-        let symlink_path = file.symlink_dir_path();
-        if fs::read_link(&symlink_path).is_ok() {
-            symlink::remove_symlink_file(symlink_path).unwrap();
-        }
+        // Add file delete change
+        change_counter.complete_intent(intent)?;
+    }
+    Ok(())
+}
+
+/// The steps of [`delete_file`], skipping those that are already done. The custom metadata is
+/// deleted before the symlink, so that there is never custom metadata without a symlink.
+pub(super) fn delete_file_steps(
+    file: &client_database::FilePaths,
+    database: &mut client_database::ClientDatabase,
+) -> io::Result<()> {
+    {
+        // Delete custom metadata if exists
+        client_database::CustomMetadata::remove_from_database(file, database)?;
     }
     {
-        // Delete custom metadata file if exists
-        let custom_metadata_file = file.custom_metadata_path();
-        if custom_metadata_file.exists() {
-            fs::remove_file(custom_metadata_file).unwrap();
+        // Delete symlink
+        let symlink_path = file.symlink_dir_path();
+        if fs::read_link(symlink_path).is_ok() {
+            symlink::remove_symlink_file(symlink_path)?;
         }
     }
     {
         // Delete real file if exists
         let real_file = file.storage_dir_path();
         if real_file.exists() {
-            fs::remove_file(real_file).unwrap();
+            fs::remove_file(real_file)?;
         }
     }
-    {
-        // Add file delete change
-        let delete_change = format!("delete_file\n{}", file.relative_path().to_str().unwrap());
-        fs::write(change_counter.next_path(), delete_change).unwrap();
-    }
+    Ok(())
 }
@@ -5,6 +5,8 @@ mod delete_file;
 mod modify_dir;
 mod modify_file;
 mod move_file;
+mod recover;
+mod undo_delete;
 
 pub use create_dir::create_dir;
 pub use create_file::create_file;
@@ -13,3 +15,5 @@ pub use delete_file::delete_file;
 pub use modify_dir::modify_dir;
 pub use modify_file::modify_file;
 pub use move_file::move_file;
+pub use recover::recover;
+pub use undo_delete::undo_delete;
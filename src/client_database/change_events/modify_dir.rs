@@ -1,16 +1,22 @@
+use std::io;
+
 use log::debug;
 
 use crate::client_database;
 
-pub fn modify_dir(file: &client_database::FilePaths) {
+pub fn modify_dir(
+    file: &client_database::FilePaths,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<()> {
     debug!("`modify_dir`: `{}`", file.relative_path().display());
     {
         // Update custom metadata
-        let directory_last_modified =
-            client_database::CustomMetadata::last_modified_of_file(&file.symlink_dir_path())
-                .unwrap();
-        let mut custom_metadata = client_database::CustomMetadata::read_from_file(&file).unwrap();
-        custom_metadata.set_last_modified(directory_last_modified);
-        custom_metadata.write_to_file(&file).unwrap();
+        let database = change_counter.database();
+        let mut custom_metadata =
+            client_database::CustomMetadata::read_from_database(file, database)?
+                .unwrap_or_default();
+        custom_metadata.refresh(file.symlink_dir_path())?;
+        custom_metadata.write_to_database(file, database)?;
     }
+    Ok(())
 }
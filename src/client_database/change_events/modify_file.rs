@@ -1,20 +1,34 @@
-use std::fs;
+use std::{fs, io};
 
 use log::debug;
 
-use crate::client_database;
+use crate::{client_database, data};
 
 pub fn modify_file(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     debug!("`modify_file`: `{}`", file.relative_path().display());
-    let mut custom_metadata = client_database::CustomMetadata::read_from_file(file).unwrap();
-    let modified_change = format!("modify_file\n{}", file.relative_path().to_str().unwrap(),);
-    fs::write(change_counter.next_path(), modified_change).unwrap();
+    let modified_change = client_database::format_change(
+        &data::FileModify::new(
+            fs::metadata(file.storage_dir_path())?.len(),
+            file.relative_path().to_str().unwrap().to_string(),
+        )
+        .into(),
+    );
+    let intent = change_counter.begin_intent(&modified_change)?;
+    modify_file_steps(file, change_counter.database())?;
+    change_counter.complete_intent(intent)?;
+    Ok(())
+}
 
-    let real_last_modified =
-        client_database::CustomMetadata::last_modified_of_file(file.storage_dir_path()).unwrap();
-    custom_metadata.set_last_modified(real_last_modified);
-    custom_metadata.write_to_file(file).unwrap();
+/// The steps of [`modify_file`]: updates the custom metadata from the real file.
+pub(super) fn modify_file_steps(
+    file: &client_database::FilePaths,
+    database: &mut client_database::ClientDatabase,
+) -> io::Result<()> {
+    let mut custom_metadata =
+        client_database::CustomMetadata::read_from_database(file, database)?.unwrap_or_default();
+    custom_metadata.refresh(file.storage_dir_path())?;
+    custom_metadata.write_to_database(file, database)
 }
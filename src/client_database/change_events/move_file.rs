@@ -1,8 +1,8 @@
-use std::{fs, path};
+use std::{fs, io, path};
 
 use log::debug;
 
-use crate::client_database;
+use crate::{client_database, data};
 
 pub fn move_file(
     rel_from_path: &path::PathBuf,
@@ -10,21 +10,51 @@ pub fn move_file(
     file_handler_config: &client_database::FileHandlerConfig,
     change_counter: &mut client_database::ChangeCounter,
     skip_move: client_database::Type,
-) {
+) -> io::Result<()> {
     debug!(
         "`move_file` - from: `{}` to: `{}`",
         rel_from_path.display(),
         rel_to_path.display()
     );
+    let move_change = client_database::format_change(
+        &data::FileMove::new(
+            rel_from_path.to_str().unwrap().to_string(),
+            rel_to_path.to_str().unwrap().to_string(),
+        )
+        .into(),
+    );
+    let intent = change_counter.begin_intent(&move_change)?;
+    move_file_steps(
+        rel_from_path,
+        rel_to_path,
+        file_handler_config,
+        change_counter.database(),
+        skip_move,
+    )?;
+    {
+        // Add file move change
+        change_counter.complete_intent(intent)?;
+    }
+    Ok(())
+}
+
+/// The steps of [`move_file`], skipping those that are already done.
+pub(super) fn move_file_steps(
+    rel_from_path: &path::PathBuf,
+    rel_to_path: &path::PathBuf,
+    file_handler_config: &client_database::FileHandlerConfig,
+    database: &mut client_database::ClientDatabase,
+    skip_move: client_database::Type,
+) -> io::Result<()> {
     if skip_move != client_database::Type::Symlink {
         // Move symlink (delete, then create)
         let symlink_from_path = file_handler_config.symlink_directory.join(rel_from_path);
         let symlink_to_path = file_handler_config.symlink_directory.join(rel_to_path);
 
-        if symlink_from_path.exists() {
-            symlink::remove_symlink_file(&symlink_from_path).unwrap();
+        if fs::read_link(&symlink_from_path).is_ok() {
+            symlink::remove_symlink_file(&symlink_from_path)?;
         }
-        symlink::symlink_file(&symlink_to_path, &symlink_from_path).unwrap();
+        symlink::symlink_file(&symlink_to_path, &symlink_from_path)?;
     }
 
     if skip_move != client_database::Type::File {
@@ -33,29 +63,18 @@ pub fn move_file(
         let real_to_path = file_handler_config.storage_directory.join(rel_to_path);
 
         if real_from_path.exists() {
-            fs::rename(&real_from_path, &real_to_path).unwrap();
+            fs::rename(&real_from_path, &real_to_path)?;
         }
     }
 
     if skip_move != client_database::Type::CustomMetadata {
-        // Move custom metadata file (rename)
-        let custom_metadata_from_path =
-            client_database::relative_to_custom_metadata(rel_from_path, file_handler_config);
-        let custom_metadata_to_path =
-            client_database::relative_to_custom_metadata(rel_to_path, file_handler_config);
-
-        if custom_metadata_from_path.exists() {
-            fs::rename(&custom_metadata_from_path, &custom_metadata_to_path).unwrap();
-        }
-    }
-
-    {
-        // Add file move change
-        let move_change = format!(
-            "move_file\n{}\n{}",
-            rel_from_path.to_str().unwrap(),
-            rel_to_path.to_str().unwrap()
-        );
-        fs::write(change_counter.next_path(), move_change).unwrap();
+        // Move custom metadata
+        database
+            .move_custom_metadata(
+                &rel_from_path.to_string_lossy(),
+                &rel_to_path.to_string_lossy(),
+            )
+            .map_err(io::Error::other)?;
     }
+    Ok(())
 }
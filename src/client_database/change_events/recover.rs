@@ -0,0 +1,192 @@
+use std::{fs, io, path};
+
+use log::{info, warn};
+
+use super::{
+    create_dir::create_dir_steps, create_file::create_file_steps, delete_dir::delete_dir_steps,
+    delete_file::delete_file_steps, modify_file::modify_file_steps, move_file::move_file_steps,
+};
+use crate::client_database;
+
+/// Completes or rolls back every change event that was interrupted, e.g. by a crash, so that
+/// each one is either fully applied and recorded, or not applied at all. Should be called before
+/// detecting changes.
+///
+/// A change event is completed when its source still exists: the real file of a `create_file`
+/// or `modify_file`, or the directory in the symlink directory of a `create_dir`. Otherwise the
+/// steps that were applied are rolled back and no change is recorded. Deletes and moves are
+/// always completed.
+pub fn recover(
+    file_handler_config: &client_database::FileHandlerConfig,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<()> {
+    for (intent, change) in change_counter.intents()? {
+        let mut lines = change.lines();
+        let change_type = lines.next().unwrap_or_default();
+        let paths = lines.map(path::PathBuf::from).collect::<Vec<_>>();
+        let file_paths = |relative_path: &path::PathBuf, file_type| {
+            client_database::FilePaths::from_relative_path(
+                relative_path.clone(),
+                file_type,
+                client_database::FileLocation::StorageDir,
+                None,
+                file_handler_config,
+            )
+        };
+
+        let complete = match (change_type, paths.as_slice()) {
+            ("create_file", [relative_path]) => {
+                let file = file_paths(relative_path, client_database::Type::File)?;
+                if file.storage_dir_path().is_file() {
+                    create_file_steps(&file, change_counter.database())?;
+                    true
+                } else {
+                    client_database::CustomMetadata::remove_from_database(
+                        &file,
+                        change_counter.database(),
+                    )?;
+                    if fs::read_link(file.symlink_dir_path()).is_ok() {
+                        symlink::remove_symlink_file(file.symlink_dir_path())?;
+                    }
+                    false
+                }
+            }
+            ("create_dir", [relative_path]) => {
+                let file = file_paths(relative_path, client_database::Type::Directory)?;
+                if file.symlink_dir_path().is_dir() {
+                    create_dir_steps(&file, change_counter.database())?;
+                    true
+                } else {
+                    client_database::CustomMetadata::remove_from_database(
+                        &file,
+                        change_counter.database(),
+                    )?;
+                    if file.storage_dir_path().is_dir() {
+                        fs::remove_dir_all(file.storage_dir_path())?;
+                    }
+                    false
+                }
+            }
+            ("modify_file", [relative_path]) => {
+                let file = file_paths(relative_path, client_database::Type::File)?;
+                // A file that is gone is detected as deleted instead
+                let database = change_counter.database();
+                if file.storage_dir_path().is_file()
+                    && client_database::CustomMetadata::read_from_database(&file, database)?
+                        .is_some()
+                {
+                    modify_file_steps(&file, database)?;
+                    true
+                } else {
+                    false
+                }
+            }
+            ("delete_file", [relative_path]) => {
+                delete_file_steps(
+                    &file_paths(relative_path, client_database::Type::File)?,
+                    change_counter.database(),
+                )?;
+                true
+            }
+            ("delete_dir", [relative_path]) => {
+                delete_dir_steps(
+                    &file_paths(relative_path, client_database::Type::Directory)?,
+                    change_counter.database(),
+                )?;
+                true
+            }
+            ("move_file", [rel_from_path, rel_to_path]) => {
+                // Moves are detected from the symlink, which has already been moved
+                move_file_steps(
+                    rel_from_path,
+                    rel_to_path,
+                    file_handler_config,
+                    change_counter.database(),
+                    client_database::Type::Symlink,
+                )?;
+                true
+            }
+            ("undo_delete_file", [_]) | ("undo_delete_dir", [_]) => true,
+            _ => {
+                warn!("Abandoning unknown intent: {:?}", change);
+                false
+            }
+        };
+
+        match complete {
+            true => {
+                info!("Completing interrupted change: {:?}", change);
+                change_counter.complete_intent(intent)?;
+            }
+            false => {
+                info!("Rolling back interrupted change: {:?}", change);
+                change_counter.abandon_intent(intent)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::recover;
+    use crate::{client_database, testing_utils};
+
+    #[test]
+    fn test_recover() {
+        let (config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        let file_paths = |relative_path: &str| {
+            client_database::FilePaths::from_relative_path(
+                relative_path.into(),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                &config,
+            )
+            .unwrap()
+        };
+
+        // Interrupted before any step: completed
+        let a = file_paths("a.txt");
+        fs::write(a.storage_dir_path(), "a").unwrap();
+        change_counter.begin_intent("create_file\na.txt").unwrap();
+
+        // Interrupted after writing the custom metadata, but the file is gone: rolled back
+        let b = file_paths("b.txt");
+        fs::write(b.storage_dir_path(), "b").unwrap();
+        client_database::CustomMetadata::of_file(b.storage_dir_path())
+            .unwrap()
+            .write_to_database(&b, change_counter.database())
+            .unwrap();
+        fs::remove_file(b.storage_dir_path()).unwrap();
+        change_counter.begin_intent("create_file\nb.txt").unwrap();
+
+        // Interrupted after deleting the custom metadata: completed
+        let c = file_paths("c.txt");
+        fs::write(c.storage_dir_path(), "c").unwrap();
+        symlink::symlink_file(c.storage_dir_path(), c.symlink_dir_path()).unwrap();
+        change_counter.begin_intent("delete_file\nc.txt").unwrap();
+
+        recover(&config, &mut change_counter).unwrap();
+        assert!(change_counter.intents().unwrap().is_empty());
+        assert_eq!(
+            client_database::read_changes(&config)
+                .unwrap()
+                .into_iter()
+                .map(|(_, change)| client_database::format_change(&change))
+                .collect::<Vec<_>>(),
+            vec!["create_file\na.txt", "delete_file\nc.txt"]
+        );
+        assert_eq!(fs::read_to_string(a.symlink_dir_path()).unwrap(), "a");
+        assert!(testing_utils::has_custom_metadata(&a, &mut change_counter));
+        assert!(!testing_utils::has_custom_metadata(&b, &mut change_counter));
+        assert!(fs::read_link(c.symlink_dir_path()).is_err());
+        assert!(!c.storage_dir_path().exists());
+
+        // Nothing is left for the offline detection
+        crate::client_detect_offline::detect_offline_changes(&config);
+        assert_eq!(client_database::read_changes(&config).unwrap().len(), 2);
+    }
+}
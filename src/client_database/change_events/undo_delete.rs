@@ -0,0 +1,26 @@
+use std::{io, path};
+
+use log::debug;
+
+use crate::{client_database, data};
+
+/// Records that a deleted file or directory should be restored from the server's trash.
+/// Nothing is created locally, the content is received once the server has restored it.
+pub fn undo_delete(
+    rel_path: &path::Path,
+    is_dir: bool,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<()> {
+    debug!("`undo_delete`: `{}`", rel_path.display());
+    {
+        // Add undo delete change
+        let rel_path = rel_path.to_str().unwrap().to_string();
+        let undo_delete_change = client_database::format_change(&match is_dir {
+            true => data::DirectoryUndoDelete::new(rel_path).into(),
+            false => data::FileUndoDelete::new(0, rel_path).into(),
+        });
+        let intent = change_counter.begin_intent(&undo_delete_change)?;
+        change_counter.complete_intent(intent)?;
+    }
+    Ok(())
+}
@@ -1,15 +1,57 @@
-use super::FilePaths;
+use super::{ClientDatabase, FilePaths};
 
-use std::{fs, io::Write, path, time};
+use sha2::{Digest, Sha256};
+use std::{fs, io, path, time};
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+/// When the offline detection hashes the content of a file to decide whether it was modified.
+/// Files whose content is unchanged, e.g. because they were only touched or restored from a
+/// backup, are not reported as modified; only their metadata is updated.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashPolicy {
+    /// Hash every file, which also finds changes that keep the size and last modified time.
+    Always,
+    /// Only hash files whose size or last modified time differ from the custom metadata.
+    #[default]
+    WhenChanged,
+    /// Never hash; any difference in size or last modified time is a modification.
+    Never,
+}
+
+/// The metadata of a file or directory, stored in the [`ClientDatabase`]. Older versions kept
+/// it next to each file in a `.sc` sidecar file, in JSON (see
+/// [`import_custom_metadata`](ClientDatabase::import_custom_metadata)).
+///
+/// Older sidecar files only hold `last_modified`, so every other field is optional. Fields that
+/// are `None` are not compared (see [`is_modified`](Self::is_modified)) until the metadata is
+/// next written.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct CustomMetadata {
-    last_modified: u64,
+    /// Last modified time, in whole seconds since the UNIX epoch.
+    pub(super) last_modified: u64,
+    /// Sub-second part of the last modified time, in nanoseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) last_modified_nanos: Option<u32>,
+    /// Size of the file in bytes. `None` for directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) size: Option<u64>,
+    /// Hex SHA-256 of the content of the file. `None` for directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) hash: Option<String>,
+    /// Identity of the file on the server, which stays the same when it is moved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) server_file_id: Option<i32>,
+    /// The server version at which this copy was last synced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) synced_server_version: Option<i32>,
 }
 
 impl CustomMetadata {
     pub fn new(last_modified: u64) -> Self {
-        Self { last_modified }
+        Self {
+            last_modified,
+            ..Default::default()
+        }
     }
 
     pub fn set_last_modified(&mut self, new_last_modified: u64) {
@@ -19,6 +61,34 @@ impl CustomMetadata {
     pub fn last_modified(&self) -> u64 {
         self.last_modified
     }
+
+    pub fn last_modified_nanos(&self) -> Option<u32> {
+        self.last_modified_nanos
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    pub fn hash(&self) -> Option<&str> {
+        self.hash.as_deref()
+    }
+
+    pub fn server_file_id(&self) -> Option<i32> {
+        self.server_file_id
+    }
+
+    pub fn set_server_file_id(&mut self, server_file_id: i32) {
+        self.server_file_id = Some(server_file_id)
+    }
+
+    pub fn synced_server_version(&self) -> Option<i32> {
+        self.synced_server_version
+    }
+
+    pub fn set_synced_server_version(&mut self, synced_server_version: i32) {
+        self.synced_server_version = Some(synced_server_version)
+    }
 }
 
 // impl TryFrom<FilePaths<T>> for CustomMetadata {
@@ -30,21 +100,35 @@ impl CustomMetadata {
 // }
 
 impl CustomMetadata {
-    pub fn write_to_file(&self, file_paths: &FilePaths) -> Result<(), std::io::Error> {
-        let custom_metadata_path = file_paths.custom_metadata_path();
-        let contents = serde_json::to_string(&self)?;
+    pub fn write_to_database(
+        &self,
+        file_paths: &FilePaths,
+        database: &mut ClientDatabase,
+    ) -> Result<(), std::io::Error> {
+        database
+            .set_custom_metadata(&file_paths.relative_path().to_string_lossy(), self)
+            .map_err(io::Error::other)
+    }
 
-        let mut file = fs::File::create(custom_metadata_path)?;
-        file.set_len(0)?;
-        file.write(contents.as_bytes())?;
-        Ok(())
+    /// The custom metadata of `file_paths`, or `None` if it has none, e.g. because it is not
+    /// tracked yet.
+    pub fn read_from_database(
+        file_paths: &FilePaths,
+        database: &ClientDatabase,
+    ) -> Result<Option<Self>, std::io::Error> {
+        database
+            .custom_metadata(&file_paths.relative_path().to_string_lossy())
+            .map_err(io::Error::other)
     }
 
-    pub fn read_from_file(file_paths: &FilePaths) -> Result<Self, std::io::Error> {
-        let custom_metadata_path = file_paths.custom_metadata_path();
-        let bytes = fs::read(custom_metadata_path)?;
-        let custom_metadata = serde_json::from_slice(&bytes)?;
-        Ok(custom_metadata)
+    /// Removes the custom metadata of `file_paths`, and of anything inside of it.
+    pub fn remove_from_database(
+        file_paths: &FilePaths,
+        database: &mut ClientDatabase,
+    ) -> Result<(), std::io::Error> {
+        database
+            .remove_custom_metadata(&file_paths.relative_path().to_string_lossy())
+            .map_err(io::Error::other)
     }
 
     pub fn last_modified_of_file(fp: &path::PathBuf) -> Result<u64, std::io::Error> {
@@ -61,4 +145,107 @@ impl CustomMetadata {
             .as_secs();
         Ok(modified)
     }
+
+    pub fn hash_of_file(fp: &path::Path) -> Result<String, std::io::Error> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut fs::File::open(fp)?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// The last modified time and size of `fp`, without reading its content.
+    pub fn stat(fp: &path::Path) -> Result<Self, std::io::Error> {
+        let metadata = fp.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(time::UNIX_EPOCH)
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            last_modified: modified.as_secs(),
+            last_modified_nanos: Some(modified.subsec_nanos()),
+            size: metadata.is_file().then_some(metadata.len()),
+            ..Default::default()
+        })
+    }
+
+    /// The metadata of `fp`, including the hash of its content.
+    pub fn of_file(fp: &path::Path) -> Result<Self, std::io::Error> {
+        let mut custom_metadata = Self::stat(fp)?;
+        if fp.is_file() {
+            custom_metadata.hash = Some(Self::hash_of_file(fp)?);
+        }
+        Ok(custom_metadata)
+    }
+
+    /// Updates the local fields from `fp` (see [`of_file`](Self::of_file)). The server fields are
+    /// kept.
+    pub fn refresh(&mut self, fp: &path::Path) -> Result<(), std::io::Error> {
+        *self = Self {
+            server_file_id: self.server_file_id,
+            synced_server_version: self.synced_server_version,
+            ..Self::of_file(fp)?
+        };
+        Ok(())
+    }
+
+    /// Whether `current` (e.g. from [`stat`](Self::stat)) differs from this stored metadata in
+    /// last modified time or size. Fields missing from either side are not compared.
+    pub fn is_modified(&self, current: &CustomMetadata) -> bool {
+        let differs =
+            |a: Option<u64>, b: Option<u64>| matches!((a, b), (Some(a), Some(b)) if a != b);
+        self.last_modified != current.last_modified
+            || differs(
+                self.last_modified_nanos.map(u64::from),
+                current.last_modified_nanos.map(u64::from),
+            )
+            || differs(self.size, current.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::CustomMetadata;
+    use crate::testing_utils;
+
+    #[test]
+    fn test_custom_metadata() {
+        let (config, _) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        let path = config.storage_directory.join("a.txt");
+        fs::write(&path, "hello").unwrap();
+
+        // Sidecar files of older versions
+        let legacy: CustomMetadata = serde_json::from_str(r#"{"last_modified":1234}"#).unwrap();
+        assert_eq!(legacy, CustomMetadata::new(1234));
+        assert_eq!(legacy.last_modified_nanos(), None);
+
+        let mut custom_metadata = CustomMetadata::of_file(&path).unwrap();
+        assert_eq!(custom_metadata.size(), Some(5));
+        assert_eq!(
+            custom_metadata.hash(),
+            Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+        );
+        custom_metadata.set_server_file_id(7);
+        custom_metadata.set_synced_server_version(3);
+        let json = serde_json::to_string(&custom_metadata).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CustomMetadata>(&json).unwrap(),
+            custom_metadata
+        );
+
+        // Only the seconds of a legacy sidecar are compared
+        let current = CustomMetadata::stat(&path).unwrap();
+        assert!(!custom_metadata.is_modified(&current));
+        assert!(!CustomMetadata::new(current.last_modified()).is_modified(&current));
+        assert!(CustomMetadata::new(current.last_modified() - 1).is_modified(&current));
+
+        // Edits within the same second are detected by the nanoseconds or the size
+        fs::write(&path, "hello, world").unwrap();
+        assert!(custom_metadata.is_modified(&CustomMetadata::stat(&path).unwrap()));
+
+        custom_metadata.refresh(&path).unwrap();
+        assert_eq!(custom_metadata.size(), Some(12));
+        assert_eq!(custom_metadata.server_file_id(), Some(7));
+        assert_eq!(custom_metadata.synced_server_version(), Some(3));
+    }
 }
@@ -0,0 +1,1058 @@
+use std::{fs, path};
+
+use log::info;
+use rusqlite::{params, OptionalExtension};
+
+use super::{CustomMetadata, CustomMetadataType, FileHandlerConfig};
+use crate::data;
+
+/// Name of the database file inside of the `program_data_directory`.
+pub const DATABASE_FILE: &str = "client.sqlite";
+
+const CREATE_TABLES: &str = r#"
+CREATE TABLE IF NOT EXISTS state (
+    key TEXT PRIMARY KEY NOT NULL,
+    value INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS local_changes (
+    change_number INTEGER PRIMARY KEY NOT NULL,
+    change TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS file_metadata (
+    path TEXT PRIMARY KEY NOT NULL,
+    last_modified INTEGER NOT NULL,
+    last_modified_nanos INTEGER,
+    size INTEGER,
+    hash TEXT,
+    server_file_id INTEGER,
+    synced_server_version INTEGER
+);
+CREATE TABLE IF NOT EXISTS transfers (
+    path TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    transferred INTEGER NOT NULL,
+    temporary_path TEXT NOT NULL,
+    PRIMARY KEY (path, direction)
+);
+CREATE TABLE IF NOT EXISTS conflicts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL,
+    conflict_copy TEXT,
+    change_event_id INTEGER NOT NULL,
+    change TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS placeholders (
+    path TEXT PRIMARY KEY NOT NULL,
+    size INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pinned (
+    path TEXT PRIMARY KEY NOT NULL
+);
+CREATE TABLE IF NOT EXISTS file_access (
+    path TEXT PRIMARY KEY NOT NULL,
+    last_accessed INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS intents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    change TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS applied_paths (
+    path TEXT PRIMARY KEY NOT NULL,
+    writing INTEGER NOT NULL,
+    storage_size INTEGER,
+    storage_modified INTEGER,
+    symlink_size INTEGER,
+    symlink_modified INTEGER
+);
+"#;
+
+/// Version of the schema created by [`CREATE_TABLES`], stored in the `user_version` pragma.
+const SCHEMA_VERSION: i64 = 1;
+
+/// How long to wait for a lock held by another connection before failing with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+const CHANGE_COUNT: &str = "change_count";
+const SERVER_VERSION: &str = "server_version";
+const CUSTOM_METADATA_IMPORTED: &str = "custom_metadata_imported";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+impl TransferDirection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransferDirection::Upload => "upload",
+            TransferDirection::Download => "download",
+        }
+    }
+
+    fn from_str(direction: &str) -> Self {
+        match direction {
+            "upload" => TransferDirection::Upload,
+            _ => TransferDirection::Download,
+        }
+    }
+}
+
+/// Size and modification time (in nanoseconds since the UNIX epoch) of a path on disk, without
+/// following symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskState {
+    pub size: u64,
+    pub modified: i64,
+}
+
+/// What the client itself left at a path, e.g. when applying a change from the server (see
+/// [`ClientDatabase::record_applied`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppliedState {
+    /// The path is still being written.
+    Writing,
+    /// The path was written, leaving these states in the storage and symlink directories
+    /// (`None` where nothing is at the path).
+    Written {
+        storage: Option<DiskState>,
+        symlink: Option<DiskState>,
+    },
+}
+
+impl AppliedState {
+    fn from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Self> {
+        let disk_state = |size: Option<i64>, modified: Option<i64>| {
+            size.zip(modified).map(|(size, modified)| DiskState {
+                size: size as u64,
+                modified,
+            })
+        };
+        Ok(match row.get::<_, bool>(first)? {
+            true => AppliedState::Writing,
+            false => AppliedState::Written {
+                storage: disk_state(row.get(first + 1)?, row.get(first + 2)?),
+                symlink: disk_state(row.get(first + 3)?, row.get(first + 4)?),
+            },
+        })
+    }
+}
+
+/// A file that is being sent to or received from the server. Transfers that are still in the
+/// database after a restart were interrupted and can be resumed from `transferred` bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    path: String,
+    direction: TransferDirection,
+    size: u64,
+    transferred: u64,
+    temporary_path: path::PathBuf,
+}
+
+impl Transfer {
+    pub fn new(
+        path: String,
+        direction: TransferDirection,
+        size: u64,
+        temporary_path: path::PathBuf,
+    ) -> Self {
+        Self {
+            path,
+            direction,
+            size,
+            transferred: 0,
+            temporary_path,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn direction(&self) -> TransferDirection {
+        self.direction
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    pub fn temporary_path(&self) -> &path::Path {
+        &self.temporary_path
+    }
+}
+
+/// A local change that collided with a change received from the server, kept until the user
+/// has reviewed it. `conflict_copy` is the path of the copy of the local version, if there was
+/// one (see [`apply_server_changes`](super::apply_server_changes)).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConflictRecord {
+    id: i64,
+    conflict: data::Conflict,
+    conflict_copy: Option<String>,
+}
+
+impl ConflictRecord {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn conflict(&self) -> &data::Conflict {
+        &self.conflict
+    }
+
+    pub fn conflict_copy(&self) -> Option<&str> {
+        self.conflict_copy.as_deref()
+    }
+}
+
+/// Embedded SQLite database of the client, stored in the `program_data_directory`. Holds the
+/// local changes, the change count, the server version, the [`CustomMetadata`] of each file and
+/// the state of in-flight transfers. Every update is made in a single transaction, so a crash
+/// never leaves a partly recorded change.
+///
+/// The tables are created when the database is opened for the first time, when the files used
+/// by older versions (`change_count`, `server_version` and the `changes` directory) are also
+/// imported and removed. Their `.sc` sidecar files are imported with
+/// [`import_custom_metadata`](Self::import_custom_metadata). Connections wait for each other's
+/// locks for up to [`BUSY_TIMEOUT`].
+#[derive(Debug)]
+pub struct ClientDatabase {
+    connection: rusqlite::Connection,
+}
+
+impl ClientDatabase {
+    pub fn open(program_data_directory: &path::Path) -> anyhow::Result<Self> {
+        let mut connection =
+            rusqlite::Connection::open(program_data_directory.join(DATABASE_FILE))?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            connection.execute_batch(CREATE_TABLES)?;
+            import_program_data(program_data_directory, &mut connection)?;
+            connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(Self { connection })
+    }
+
+    fn state(&self, key: &str) -> rusqlite::Result<i64> {
+        let value = self
+            .connection
+            .query_row("SELECT value FROM state WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(value.unwrap_or(0))
+    }
+
+    pub fn change_count(&self) -> rusqlite::Result<i64> {
+        self.state(CHANGE_COUNT)
+    }
+
+    /// Stores `change` (in the format read by [`parse_change`](super::parse_change)) under the
+    /// next change number, which is returned.
+    pub fn record_change(&mut self, change: &str) -> rusqlite::Result<i64> {
+        let transaction = self.connection.transaction()?;
+        let change_number = insert_change(&transaction, change)?;
+        transaction.commit()?;
+        Ok(change_number)
+    }
+
+    /// Stores the intent to make `change`, before any of its steps are applied to the files.
+    /// Returns the id of the intent.
+    pub fn begin_intent(&mut self, change: &str) -> rusqlite::Result<i64> {
+        self.connection
+            .execute("INSERT INTO intents (change) VALUES (?1)", [change])?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Every intent that was neither completed nor abandoned, e.g. because of a crash, with its
+    /// id, in the order they were begun.
+    pub fn intents(&self) -> rusqlite::Result<Vec<(i64, String)>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, change FROM intents ORDER BY id")?;
+        let intents = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+        intents
+    }
+
+    /// Removes the intent `id` and records its change (see [`record_change`](Self::record_change))
+    /// in the same transaction. Returns the change number.
+    pub fn complete_intent(&mut self, id: i64) -> rusqlite::Result<i64> {
+        let transaction = self.connection.transaction()?;
+        let change: String =
+            transaction.query_row("SELECT change FROM intents WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })?;
+        let change_number = insert_change(&transaction, &change)?;
+        transaction.execute("DELETE FROM intents WHERE id = ?1", [id])?;
+        transaction.commit()?;
+        Ok(change_number)
+    }
+
+    /// Removes the intent `id` without recording its change, once its steps are rolled back.
+    pub fn abandon_intent(&mut self, id: i64) -> rusqlite::Result<()> {
+        self.connection
+            .execute("DELETE FROM intents WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Every local change with its change number, in the order they were recorded.
+    pub fn changes(&self) -> rusqlite::Result<Vec<(i64, String)>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT change_number, change FROM local_changes ORDER BY change_number")?;
+        let changes = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+        changes
+    }
+
+    /// Removes every local change, e.g. once they have been sent to the server. The change count
+    /// is kept.
+    pub fn clear_changes(&mut self) -> rusqlite::Result<()> {
+        self.connection.execute("DELETE FROM local_changes", [])?;
+        Ok(())
+    }
+
+    /// Replaces every local change with `changes`, e.g. once they have been rebased. The change
+    /// count is kept.
+    pub fn replace_changes(&mut self, changes: &[(i64, String)]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM local_changes", [])?;
+        for (change_number, change) in changes {
+            transaction.execute(
+                "INSERT INTO local_changes (change_number, change) VALUES (?1, ?2)",
+                params![change_number, change],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Removes a single local change, e.g. one that was superseded by a change from the server.
+    pub fn remove_change(&mut self, change_number: i64) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "DELETE FROM local_changes WHERE change_number = ?1",
+            [change_number],
+        )?;
+        Ok(())
+    }
+
+    pub fn server_version(&self) -> rusqlite::Result<i32> {
+        Ok(self.state(SERVER_VERSION)? as i32)
+    }
+
+    pub fn set_server_version(&mut self, server_version: i32) -> rusqlite::Result<()> {
+        self.connection.execute(
+            r#"
+            INSERT INTO state (key, value) VALUES (?1, ?2)
+            ON CONFLICT (key) DO UPDATE SET value = ?2
+            "#,
+            params![SERVER_VERSION, server_version],
+        )?;
+        Ok(())
+    }
+
+    /// Metadata of the file or directory at `relative_path`.
+    pub fn custom_metadata(&self, relative_path: &str) -> rusqlite::Result<Option<CustomMetadata>> {
+        self.connection
+            .query_row(
+                r#"
+                SELECT last_modified, last_modified_nanos, size, hash, server_file_id,
+                synced_server_version
+                FROM file_metadata WHERE path = ?1
+                "#,
+                [relative_path],
+                |row| {
+                    Ok(CustomMetadata {
+                        last_modified: row.get::<_, i64>(0)? as u64,
+                        last_modified_nanos: row.get(1)?,
+                        size: row.get::<_, Option<i64>>(2)?.map(|size| size as u64),
+                        hash: row.get(3)?,
+                        server_file_id: row.get(4)?,
+                        synced_server_version: row.get(5)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// Every path with custom metadata, sorted.
+    pub fn custom_metadata_paths(&self) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT path FROM file_metadata ORDER BY path")?;
+        let paths = statement.query_map([], |row| row.get(0))?.collect();
+        paths
+    }
+
+    pub fn set_custom_metadata(
+        &mut self,
+        relative_path: &str,
+        custom_metadata: &CustomMetadata,
+    ) -> rusqlite::Result<()> {
+        set_custom_metadata(&self.connection, relative_path, custom_metadata)
+    }
+
+    /// Removes the metadata of `relative_path` and of anything inside of it.
+    pub fn remove_custom_metadata(&mut self, relative_path: &str) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "DELETE FROM file_metadata WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+            [relative_path],
+        )?;
+        Ok(())
+    }
+
+    /// Moves the metadata of `from` and of anything inside of it to `to`.
+    pub fn move_custom_metadata(&mut self, from: &str, to: &str) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM file_metadata WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+            [to],
+        )?;
+        transaction.execute(
+            r#"
+            UPDATE file_metadata SET path = ?2 || substr(path, length(?1) + 1)
+            WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'
+            "#,
+            [from, to],
+        )?;
+        transaction.commit()
+    }
+
+    /// Imports the `.sc` sidecar files that older versions kept next to each file in the storage
+    /// directory, in a single transaction, and removes them once it has been committed. Only
+    /// done once; later calls return without reading the storage directory. Sidecar files that
+    /// only hold `last_modified` are imported with every other field empty.
+    pub fn import_custom_metadata(
+        &mut self,
+        file_handler_config: &FileHandlerConfig,
+    ) -> anyhow::Result<()> {
+        if self.state(CUSTOM_METADATA_IMPORTED)? != 0 {
+            return Ok(());
+        }
+
+        let mut sidecars = vec![];
+        find_custom_metadata(&file_handler_config.storage_directory, &mut sidecars)?;
+        if !sidecars.is_empty() {
+            info!("Importing custom metadata into the client database");
+        }
+
+        let transaction = self.connection.transaction()?;
+        for sidecar in &sidecars {
+            let relative_path = CustomMetadataType::to_relative(sidecar, file_handler_config);
+            let custom_metadata: CustomMetadata = serde_json::from_slice(&fs::read(sidecar)?)?;
+            set_custom_metadata(
+                &transaction,
+                &relative_path.to_string_lossy(),
+                &custom_metadata,
+            )?;
+        }
+        transaction.execute(
+            r#"
+            INSERT INTO state (key, value) VALUES (?1, 1)
+            ON CONFLICT (key) DO UPDATE SET value = 1
+            "#,
+            [CUSTOM_METADATA_IMPORTED],
+        )?;
+        transaction.commit()?;
+
+        for sidecar in sidecars {
+            fs::remove_file(sidecar)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps `conflict` for the user to review. Returns the id of the record.
+    pub fn record_conflict(
+        &mut self,
+        conflict: &data::Conflict,
+        conflict_copy: Option<&str>,
+    ) -> rusqlite::Result<i64> {
+        let change = serde_json::to_string(conflict.change())
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.connection.execute(
+            r#"
+            INSERT INTO conflicts (path, conflict_copy, change_event_id, change)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+            params![
+                conflict.path(),
+                conflict_copy,
+                conflict.change_event_id(),
+                change
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Every conflict that has not been resolved, oldest first.
+    pub fn conflicts(&self) -> rusqlite::Result<Vec<ConflictRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, path, conflict_copy, change_event_id, change FROM conflicts ORDER BY id",
+        )?;
+        let conflicts = statement
+            .query_map([], |row| {
+                let change = serde_json::from_str(&row.get::<_, String>(4)?).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        4,
+                        rusqlite::types::Type::Text,
+                        e.into(),
+                    )
+                })?;
+                Ok(ConflictRecord {
+                    id: row.get(0)?,
+                    conflict: data::Conflict::new(row.get(1)?, row.get(3)?, change),
+                    conflict_copy: row.get(2)?,
+                })
+            })?
+            .collect();
+        conflicts
+    }
+
+    /// Should be called once the user has reviewed the conflict.
+    pub fn resolve_conflict(&mut self, id: i64) -> rusqlite::Result<()> {
+        self.connection
+            .execute("DELETE FROM conflicts WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Starts (or restarts) tracking `transfer`.
+    pub fn begin_transfer(&mut self, transfer: &Transfer) -> rusqlite::Result<()> {
+        self.connection.execute(
+            r#"
+            INSERT INTO transfers (path, direction, size, transferred, temporary_path)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (path, direction)
+            DO UPDATE SET size = ?3, transferred = ?4, temporary_path = ?5
+            "#,
+            params![
+                transfer.path,
+                transfer.direction.as_str(),
+                transfer.size as i64,
+                transfer.transferred as i64,
+                transfer.temporary_path.to_string_lossy()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_transfer(
+        &mut self,
+        path: &str,
+        direction: TransferDirection,
+        transferred: u64,
+    ) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "UPDATE transfers SET transferred = ?3 WHERE path = ?1 AND direction = ?2",
+            params![path, direction.as_str(), transferred as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn finish_transfer(
+        &mut self,
+        path: &str,
+        direction: TransferDirection,
+    ) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "DELETE FROM transfers WHERE path = ?1 AND direction = ?2",
+            params![path, direction.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Every transfer that has not finished.
+    pub fn transfers(&self) -> rusqlite::Result<Vec<Transfer>> {
+        let mut statement = self.connection.prepare(
+            r#"
+            SELECT path, direction, size, transferred, temporary_path FROM transfers
+            ORDER BY path, direction
+            "#,
+        )?;
+        let transfers = statement
+            .query_map([], |row| {
+                Ok(Transfer {
+                    path: row.get(0)?,
+                    direction: TransferDirection::from_str(&row.get::<_, String>(1)?),
+                    size: row.get::<_, i64>(2)? as u64,
+                    transferred: row.get::<_, i64>(3)? as u64,
+                    temporary_path: path::PathBuf::from(row.get::<_, String>(4)?),
+                })
+            })?
+            .collect();
+        transfers
+    }
+
+    /// Size of the file at `relative_path` if it is a placeholder, i.e. its content has not been
+    /// downloaded.
+    pub fn placeholder(&self, relative_path: &str) -> rusqlite::Result<Option<u64>> {
+        self.connection
+            .query_row(
+                "SELECT size FROM placeholders WHERE path = ?1",
+                [relative_path],
+                |row| Ok(row.get::<_, i64>(0)? as u64),
+            )
+            .optional()
+    }
+
+    /// Every placeholder with its size.
+    pub fn placeholders(&self) -> rusqlite::Result<Vec<(String, u64)>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT path, size FROM placeholders ORDER BY path")?;
+        let placeholders = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?
+            .collect();
+        placeholders
+    }
+
+    pub fn set_placeholder(&mut self, relative_path: &str, size: u64) -> rusqlite::Result<()> {
+        self.connection.execute(
+            r#"
+            INSERT INTO placeholders (path, size) VALUES (?1, ?2)
+            ON CONFLICT (path) DO UPDATE SET size = ?2
+            "#,
+            params![relative_path, size as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Marks the file at `relative_path` as downloaded. It stays pinned if it was.
+    pub fn remove_placeholder(&mut self, relative_path: &str) -> rusqlite::Result<()> {
+        self.connection
+            .execute("DELETE FROM placeholders WHERE path = ?1", [relative_path])?;
+        Ok(())
+    }
+
+    /// Removes the placeholders, pins and access times of `relative_path` and of anything inside
+    /// of it, e.g. once it has been deleted.
+    pub fn forget_placeholders(&mut self, relative_path: &str) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        for table in ["placeholders", "pinned", "file_access"] {
+            transaction.execute(
+                &format!(
+                    "DELETE FROM {} WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+                    table
+                ),
+                [relative_path],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Moves the placeholders, pins and access times of `from` and of anything inside of it to
+    /// `to`.
+    pub fn move_placeholders(&mut self, from: &str, to: &str) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        for table in ["placeholders", "pinned", "file_access"] {
+            transaction.execute(
+                &format!(
+                    "DELETE FROM {} WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+                    table
+                ),
+                [to],
+            )?;
+            transaction.execute(
+                &format!(
+                    r#"
+                    UPDATE {} SET path = ?2 || substr(path, length(?1) + 1)
+                    WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'
+                    "#,
+                    table
+                ),
+                [from, to],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Whether the file at `relative_path` is kept downloaded.
+    pub fn is_pinned(&self, relative_path: &str) -> rusqlite::Result<bool> {
+        self.connection
+            .query_row(
+                "SELECT 1 FROM pinned WHERE path = ?1",
+                [relative_path],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|pinned| pinned.is_some())
+    }
+
+    /// When the file at `relative_path` was last accessed, in seconds since the UNIX epoch.
+    pub fn last_accessed(&self, relative_path: &str) -> rusqlite::Result<Option<u64>> {
+        self.connection
+            .query_row(
+                "SELECT last_accessed FROM file_access WHERE path = ?1",
+                [relative_path],
+                |row| Ok(row.get::<_, i64>(0)? as u64),
+            )
+            .optional()
+    }
+
+    pub fn set_last_accessed(
+        &mut self,
+        relative_path: &str,
+        last_accessed: u64,
+    ) -> rusqlite::Result<()> {
+        self.connection.execute(
+            r#"
+            INSERT INTO file_access (path, last_accessed) VALUES (?1, ?2)
+            ON CONFLICT (path) DO UPDATE SET last_accessed = ?2
+            "#,
+            params![relative_path, last_accessed as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the records of `relative_path` and of everything inside of it with `states`,
+    /// which are what the client itself left at those paths.
+    pub fn record_applied(
+        &mut self,
+        relative_path: &str,
+        states: &[(String, AppliedState)],
+    ) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM applied_paths WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+            [relative_path],
+        )?;
+        for (path, state) in states {
+            let (writing, storage, symlink) = match state {
+                AppliedState::Writing => (true, None, None),
+                AppliedState::Written { storage, symlink } => (false, *storage, *symlink),
+            };
+            transaction.execute(
+                r#"
+                INSERT INTO applied_paths (
+                    path, writing, storage_size, storage_modified, symlink_size, symlink_modified
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (path) DO UPDATE SET
+                    writing = ?2,
+                    storage_size = ?3,
+                    storage_modified = ?4,
+                    symlink_size = ?5,
+                    symlink_modified = ?6
+                "#,
+                params![
+                    path,
+                    writing,
+                    storage.map(|state| state.size as i64),
+                    storage.map(|state| state.modified),
+                    symlink.map(|state| state.size as i64),
+                    symlink.map(|state| state.modified),
+                ],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// The records of [`record_applied`](Self::record_applied) for `relative_path` and for the
+    /// directories that it is inside of, the closest first.
+    pub fn applied_states(
+        &self,
+        relative_path: &str,
+    ) -> rusqlite::Result<Vec<(String, AppliedState)>> {
+        let mut statement = self.connection.prepare(
+            r#"
+            SELECT path, writing, storage_size, storage_modified, symlink_size, symlink_modified
+            FROM applied_paths
+            WHERE path = ?1 OR substr(?1, 1, length(path) + 1) = path || '/'
+            ORDER BY length(path) DESC
+            "#,
+        )?;
+        let states = statement
+            .query_map([relative_path], |row| {
+                Ok((row.get(0)?, AppliedState::from_row(row, 1)?))
+            })?
+            .collect();
+        states
+    }
+
+    pub fn set_pinned(&mut self, relative_path: &str, pinned: bool) -> rusqlite::Result<()> {
+        match pinned {
+            true => self.connection.execute(
+                "INSERT INTO pinned (path) VALUES (?1) ON CONFLICT (path) DO NOTHING",
+                [relative_path],
+            )?,
+            false => self
+                .connection
+                .execute("DELETE FROM pinned WHERE path = ?1", [relative_path])?,
+        };
+        Ok(())
+    }
+}
+
+/// Stores `change` under the next change number, which is returned.
+fn insert_change(transaction: &rusqlite::Transaction, change: &str) -> rusqlite::Result<i64> {
+    let change_number: i64 = transaction.query_row(
+        r#"
+        INSERT INTO state (key, value) VALUES (?1, 1)
+        ON CONFLICT (key) DO UPDATE SET value = value + 1
+        RETURNING value
+        "#,
+        [CHANGE_COUNT],
+        |row| row.get(0),
+    )?;
+    transaction.execute(
+        "INSERT INTO local_changes (change_number, change) VALUES (?1, ?2)",
+        params![change_number, change],
+    )?;
+    Ok(change_number)
+}
+
+fn set_custom_metadata(
+    connection: &rusqlite::Connection,
+    relative_path: &str,
+    custom_metadata: &CustomMetadata,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        r#"
+        INSERT INTO file_metadata (
+            path, last_modified, last_modified_nanos, size, hash, server_file_id,
+            synced_server_version
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (path) DO UPDATE SET
+            last_modified = ?2, last_modified_nanos = ?3, size = ?4, hash = ?5,
+            server_file_id = ?6, synced_server_version = ?7
+        "#,
+        params![
+            relative_path,
+            custom_metadata.last_modified as i64,
+            custom_metadata.last_modified_nanos,
+            custom_metadata.size.map(|size| size as i64),
+            custom_metadata.hash,
+            custom_metadata.server_file_id,
+            custom_metadata.synced_server_version
+        ],
+    )?;
+    Ok(())
+}
+
+/// Every `.sc` sidecar file inside of `dir`.
+fn find_custom_metadata(
+    dir: &path::Path,
+    sidecars: &mut Vec<path::PathBuf>,
+) -> std::io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_symlink() {
+            continue;
+        }
+        if path.is_dir() {
+            find_custom_metadata(&path, sidecars)?;
+        } else if CustomMetadataType::is_custom_metadata(&path) {
+            sidecars.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Imports the files that older versions kept in the `program_data_directory`, in a single
+/// transaction, and removes them once it has been committed.
+fn import_program_data(
+    program_data_directory: &path::Path,
+    connection: &mut rusqlite::Connection,
+) -> anyhow::Result<()> {
+    let count_path = program_data_directory.join("change_count");
+    let server_version_path = program_data_directory.join("server_version");
+    let change_directory = program_data_directory.join("changes");
+
+    if !(count_path.exists() || server_version_path.exists() || change_directory.exists()) {
+        return Ok(());
+    }
+    info!("Importing program data into the client database");
+
+    let read_number = |path: &path::Path| -> anyhow::Result<i64> {
+        match path.exists() {
+            true => Ok(fs::read_to_string(path)?.trim().parse::<i64>()?),
+            false => Ok(0),
+        }
+    };
+
+    let mut changes = vec![];
+    if change_directory.exists() {
+        let mut numbered_files = fs::read_dir(&change_directory)?
+            .map(|r| r.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|path| {
+                let change_number = path.file_stem()?.to_str()?.parse::<i64>().ok()?;
+                Some((change_number, path))
+            })
+            .collect::<Vec<_>>();
+        numbered_files.sort();
+        for (change_number, path) in numbered_files {
+            let change = fs::read_to_string(path)?;
+            // Blank files were used to mark the end of a program run
+            if !change.is_empty() {
+                changes.push((change_number, change));
+            }
+        }
+    }
+
+    let change_count = changes
+        .iter()
+        .map(|(change_number, _)| *change_number)
+        .fold(read_number(&count_path)?, i64::max);
+
+    let transaction = connection.transaction()?;
+    for (change_number, change) in changes {
+        transaction.execute(
+            "INSERT OR REPLACE INTO local_changes (change_number, change) VALUES (?1, ?2)",
+            params![change_number, change],
+        )?;
+    }
+    transaction.execute(
+        r#"
+        INSERT INTO state (key, value) VALUES (?1, ?2)
+        ON CONFLICT (key) DO UPDATE SET value = max(value, ?2)
+        "#,
+        params![CHANGE_COUNT, change_count],
+    )?;
+    if server_version_path.exists() {
+        transaction.execute(
+            r#"
+            INSERT INTO state (key, value) VALUES (?1, ?2)
+            ON CONFLICT (key) DO UPDATE SET value = ?2
+            "#,
+            params![SERVER_VERSION, read_number(&server_version_path)?],
+        )?;
+    }
+    transaction.commit()?;
+
+    for path in [&count_path, &server_version_path] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    if change_directory.exists() {
+        fs::remove_dir_all(&change_directory)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{ClientDatabase, Transfer, TransferDirection};
+    use crate::testing_utils;
+
+    #[test]
+    fn test_import_program_data() {
+        let (file_handler_config, change_counter) =
+            testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        drop(change_counter);
+        let program_data_directory = &file_handler_config.program_data_directory;
+        fs::remove_file(program_data_directory.join(super::DATABASE_FILE)).unwrap();
+
+        // Layout of older versions
+        fs::write(program_data_directory.join("change_count"), "3").unwrap();
+        fs::write(program_data_directory.join("server_version"), "17").unwrap();
+        fs::create_dir(program_data_directory.join("changes")).unwrap();
+        fs::write(
+            program_data_directory.join("changes/2.tmp"),
+            "create_dir\ndir",
+        )
+        .unwrap();
+        fs::write(program_data_directory.join("changes/3.tmp"), "").unwrap();
+        fs::write(
+            program_data_directory.join("changes/4.tmp"),
+            "create_file\ndir/a.txt",
+        )
+        .unwrap();
+        let storage_directory = &file_handler_config.storage_directory;
+        fs::create_dir(storage_directory.join("dir")).unwrap();
+        fs::write(
+            storage_directory.join(".dir.sc"),
+            r#"{"last_modified":1200}"#,
+        )
+        .unwrap();
+        fs::write(storage_directory.join("dir/a.txt"), "a").unwrap();
+        fs::write(
+            storage_directory.join("dir/.a.txt.sc"),
+            r#"{"last_modified":1234}"#,
+        )
+        .unwrap();
+        fs::write(storage_directory.join("dir/b.txt"), "b").unwrap();
+        fs::write(
+            storage_directory.join("dir/.b.txt.sc"),
+            r#"{"last_modified":1234,"last_modified_nanos":5,"size":1,"hash":"3e23","server_file_id":7,"synced_server_version":3}"#,
+        )
+        .unwrap();
+
+        let mut database = ClientDatabase::open(program_data_directory).unwrap();
+        database
+            .import_custom_metadata(&file_handler_config)
+            .unwrap();
+        assert!(!storage_directory.join("dir/.a.txt.sc").exists());
+        // Sidecar files are only imported once
+        fs::write(storage_directory.join("dir/.a.txt.sc"), "{}").unwrap();
+        database
+            .import_custom_metadata(&file_handler_config)
+            .unwrap();
+        assert!(storage_directory.join("dir/.a.txt.sc").exists());
+
+        assert_eq!(
+            database.custom_metadata_paths().unwrap(),
+            vec!["dir", "dir/a.txt", "dir/b.txt"]
+        );
+        let a = database.custom_metadata("dir/a.txt").unwrap().unwrap();
+        assert_eq!(a.last_modified(), 1234);
+        assert_eq!(a.hash(), None);
+        let b = database.custom_metadata("dir/b.txt").unwrap().unwrap();
+        assert_eq!(b.last_modified_nanos(), Some(5));
+        assert_eq!(b.size(), Some(1));
+        assert_eq!(b.hash(), Some("3e23"));
+        assert_eq!(b.server_file_id(), Some(7));
+        assert_eq!(b.synced_server_version(), Some(3));
+
+        database.move_custom_metadata("dir", "moved").unwrap();
+        assert!(database.custom_metadata("dir/b.txt").unwrap().is_none());
+        assert_eq!(database.custom_metadata("moved/b.txt").unwrap(), Some(b));
+        database.remove_custom_metadata("moved").unwrap();
+        assert!(database.custom_metadata_paths().unwrap().is_empty());
+
+        assert_eq!(
+            database.changes().unwrap(),
+            vec![
+                (2, "create_dir\ndir".to_string()),
+                (4, "create_file\ndir/a.txt".to_string())
+            ]
+        );
+        assert_eq!(database.change_count().unwrap(), 4);
+        assert_eq!(database.server_version().unwrap(), 17);
+        assert!(!program_data_directory.join("changes").exists());
+
+        assert_eq!(database.record_change("delete_dir\ndir").unwrap(), 5);
+
+        let transfer = Transfer::new(
+            "dir/a.txt".to_string(),
+            TransferDirection::Download,
+            10,
+            file_handler_config.temporary_directory.join("a.txt"),
+        );
+        database.begin_transfer(&transfer).unwrap();
+        database
+            .update_transfer("dir/a.txt", TransferDirection::Download, 4)
+            .unwrap();
+        let transfers = database.transfers().unwrap();
+        assert_eq!(transfers[0].transferred(), 4);
+        database
+            .finish_transfer("dir/a.txt", TransferDirection::Download)
+            .unwrap();
+        assert!(database.transfers().unwrap().is_empty());
+    }
+}
@@ -174,6 +174,12 @@ pub fn relative_to_symlink(fp: &path::PathBuf, config: &super::FileHandlerConfig
     symlink_path
 }
 
+/// Where the content of a file received from the server is written before it is moved into the
+/// storage directory.
+pub fn relative_to_temporary(fp: &path::Path, config: &super::FileHandlerConfig) -> path::PathBuf {
+    config.temporary_directory.join(fp)
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub enum FileLocation {
     StorageDir,
@@ -0,0 +1,261 @@
+use std::{cell::RefCell, collections::HashMap, fs, path};
+
+use super::FileHandlerConfig;
+
+/// Name of the files, in any directory of the symlink directory, that list the paths to ignore.
+pub const IGNORE_FILE: &str = ".hcsignore";
+
+/// A single gitignore-style pattern.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    pattern: String,
+    /// `!pattern`: un-ignores what a previous rule ignored.
+    negated: bool,
+    /// `pattern/`: only matches directories.
+    directory_only: bool,
+    /// A pattern that contains a `/` is matched against the whole path, relative to the directory
+    /// of the ignore file, rather than against the file name.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = line.strip_prefix('/').unwrap_or(line);
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern: pattern.to_string(),
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let path = match self.anchored {
+            true => path,
+            false => path.rsplit('/').next().unwrap(),
+        };
+        glob(self.pattern.as_bytes(), path.as_bytes())
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` and `?` match within a path component and `**`
+/// matches across components.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob(rest, text)
+                || text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| *c == b'/' && glob(rest, &text[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let component = text.iter().position(|c| *c == b'/').unwrap_or(text.len());
+            (0..=component).any(|i| glob(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => glob(rest, text),
+            _ => false,
+        },
+        [p, rest @ ..] => match text {
+            [c, text @ ..] if c == p => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+fn parse_rules(text: &str) -> Vec<Rule> {
+    text.lines().filter_map(Rule::parse).collect()
+}
+
+/// The paths that are not synced: the global
+/// [`ignore`](FileHandlerConfig::ignore) patterns of the client config followed by the
+/// [`IGNORE_FILE`]s, read from the symlink directory, of every directory above a path. Later
+/// rules take precedence, the same as in a `.gitignore`, and everything inside of an ignored
+/// directory is ignored. The ignore files themselves are never ignored, so that they are synced
+/// between devices.
+///
+/// The ignore files are read once, so a new [`IgnoreRules`] should be made when they may have
+/// changed.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    symlink_directory: path::PathBuf,
+    global: Vec<Rule>,
+    /// The rules of the ignore file in each directory, relative to the symlink directory.
+    directories: RefCell<HashMap<String, Vec<Rule>>>,
+}
+
+impl IgnoreRules {
+    pub fn new(config: &FileHandlerConfig) -> Self {
+        Self {
+            symlink_directory: config.symlink_directory.clone(),
+            global: parse_rules(&config.ignore.join("\n")),
+            directories: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn directory_rules(&self, directory: &str) -> Vec<Rule> {
+        self.directories
+            .borrow_mut()
+            .entry(directory.to_string())
+            .or_insert_with(|| {
+                let ignore_file = self.symlink_directory.join(directory).join(IGNORE_FILE);
+                fs::read_to_string(ignore_file)
+                    .map(|text| parse_rules(&text))
+                    .unwrap_or_default()
+            })
+            .clone()
+    }
+
+    /// Whether the last rule that matches `components` ignores it.
+    fn matches(&self, components: &[&str], is_dir: bool) -> bool {
+        let mut ignored = false;
+        let path = components.join("/");
+        for rule in &self.global {
+            if rule.matches(&path, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+        for depth in 0..components.len() {
+            let directory = components[..depth].join("/");
+            let path = components[depth..].join("/");
+            for rule in self.directory_rules(&directory) {
+                if rule.matches(&path, is_dir) {
+                    ignored = !rule.negated;
+                }
+            }
+        }
+        ignored
+    }
+
+    /// Whether `relative_path`, relative to the symlink or storage directory, is ignored.
+    pub fn is_ignored(&self, relative_path: &path::Path, is_dir: bool) -> bool {
+        let components = relative_path
+            .components()
+            .filter_map(|component| match component {
+                path::Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if components.last() == Some(&IGNORE_FILE) {
+            return false;
+        }
+        (1..=components.len())
+            .any(|length| self.matches(&components[..length], is_dir || length < components.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+
+    use super::{IgnoreRules, IGNORE_FILE};
+    use crate::{client_database, client_detect_offline, data, testing_utils};
+
+    #[test]
+    fn test_ignore_rules() {
+        let (mut config, _) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        config.ignore = vec!["*.tmp".to_string(), "build/".to_string()];
+        fs::create_dir_all(config.symlink_directory.join("docs")).unwrap();
+        fs::write(
+            config.symlink_directory.join(IGNORE_FILE),
+            "# comment\n*.log\n/cache\nnotes/**/draft?.txt\n",
+        )
+        .unwrap();
+        fs::write(
+            config.symlink_directory.join("docs").join(IGNORE_FILE),
+            "!keep.log\n*.txt\n",
+        )
+        .unwrap();
+
+        let ignore_rules = IgnoreRules::new(&config);
+        let is_ignored =
+            |path: &str, is_dir: bool| ignore_rules.is_ignored(path::Path::new(path), is_dir);
+
+        assert!(is_ignored("a.tmp", false));
+        assert!(is_ignored("deep/dir/a.tmp", false));
+        assert!(is_ignored("build", true));
+        assert!(!is_ignored("build", false));
+        assert!(is_ignored("build/out/a.o", false));
+        assert!(is_ignored("a.log", false));
+        assert!(is_ignored("cache", true));
+        assert!(is_ignored("cache/a", false));
+        assert!(!is_ignored("docs/cache", true));
+        assert!(is_ignored("notes/draft1.txt", false));
+        assert!(is_ignored("notes/2023/05/draft2.txt", false));
+        assert!(!is_ignored("notes/draft10.txt", false));
+        assert!(!is_ignored("docs/keep.log", false));
+        assert!(is_ignored("docs/other.log", false));
+        assert!(is_ignored("docs/sub/a.txt", false));
+        assert!(!is_ignored("a.txt", false));
+        assert!(!is_ignored(IGNORE_FILE, false));
+        assert!(!is_ignored(&format!("docs/{}", IGNORE_FILE), false));
+    }
+
+    #[test]
+    fn test_ignored_paths_are_not_synced() {
+        let (config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        fs::write(
+            config.symlink_directory.join(IGNORE_FILE),
+            "*.log\nbuild/\n",
+        )
+        .unwrap();
+        fs::write(config.symlink_directory.join("a.log"), "log").unwrap();
+        fs::write(config.symlink_directory.join("a.txt"), "text").unwrap();
+        fs::create_dir(config.symlink_directory.join("build")).unwrap();
+        fs::write(config.symlink_directory.join("build").join("out"), "out").unwrap();
+
+        client_detect_offline::detect_offline_changes(&config);
+        let mut changes = client_database::read_changes(&config)
+            .unwrap()
+            .into_iter()
+            .map(|(_, change)| change)
+            .collect::<Vec<_>>();
+        changes.sort_by(|a, b| a.paths().cmp(&b.paths()));
+        assert_eq!(
+            changes,
+            vec![
+                data::FileCreate::new(0, IGNORE_FILE.to_string()).into(),
+                data::FileCreate::new(0, "a.txt".to_string()).into(),
+            ]
+        );
+        assert!(fs::read_link(config.symlink_directory.join("a.log")).is_err());
+        assert!(!config.storage_directory.join("build").exists());
+
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+        let server_changes: Vec<(i32, data::ChangeEvent)> =
+            vec![(1, data::FileCreate::new(3, "b.log".to_string()).into())];
+        client_database::apply_server_changes(
+            &server_changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        assert_eq!(server_version.server_version(), 1);
+        assert!(!config.symlink_directory.join("b.log").exists());
+    }
+}
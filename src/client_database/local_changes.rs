@@ -1,38 +1,36 @@
 use crate::{client_database, data};
-use std::{collections::LinkedList, fs};
+use std::{collections::LinkedList, fs, io};
 
+/// Returns every local change (see [`ClientDatabase`](client_database::ClientDatabase)) with
+/// its change number.
 pub fn read_changes(
     file_handler_config: &client_database::FileHandlerConfig,
-) -> LinkedList<(i32, data::ChangeEvent)> {
-    let change_dir = file_handler_config.program_data_directory.join("changes");
+) -> io::Result<LinkedList<(i32, data::ChangeEvent)>> {
+    let database =
+        client_database::ClientDatabase::open(&file_handler_config.program_data_directory)
+            .map_err(io::Error::other)?;
+    read_changes_in(&database)
+}
 
-    let mut all_changes = fs::read_dir(&change_dir)
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect::<Vec<_>>();
-    all_changes.sort_by_key(|f| f.file_name());
-
-    let mut changes = LinkedList::new();
-    for change in all_changes {
-        if change.path().metadata().unwrap().len() != 0 {
-            changes.push_back((
-                change.file_name().to_str().unwrap()
-                    [..change.file_name().to_str().unwrap().len() - 4]
-                    .parse::<i32>()
-                    .unwrap(),
-                client_database::local_changes::parse_change(
-                    &fs::read_to_string(&change.path()).unwrap(),
-                ),
-            ));
-        }
-    }
-    changes
+/// Like [`read_changes`], but reads from an open `database`.
+pub(crate) fn read_changes_in(
+    database: &client_database::ClientDatabase,
+) -> io::Result<LinkedList<(i32, data::ChangeEvent)>> {
+    Ok(database
+        .changes()
+        .map_err(io::Error::other)?
+        .into_iter()
+        .map(|(change_number, change)| {
+            (
+                change_number as i32,
+                client_database::local_changes::parse_change(&change),
+            )
+        })
+        .collect())
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs;
-
     use crate::{
         client_database,
         data::{self, InnerEventTrait},
@@ -109,11 +107,30 @@ mod test {
                 "hello".to_string()
             )))
         );
+
+        let change = "undo_delete_file\nhello.txt";
+        let change = super::parse_change(change);
+        assert_eq!(
+            change,
+            data::ChangeEvent::File(data::FileEvent::UndoDelete(data::FileUndoDelete::new(
+                0,
+                "hello.txt".to_string()
+            )))
+        );
+
+        let change = "undo_delete_dir\nhello";
+        let change = super::parse_change(change);
+        assert_eq!(
+            change,
+            data::ChangeEvent::Directory(data::DirectoryEvent::UndoDelete(
+                data::DirectoryUndoDelete::new("hello".to_string())
+            ))
+        );
     }
 
     fn write_changes(changes: &[&str], change_counter: &mut client_database::ChangeCounter) {
         for change in changes {
-            fs::write(&change_counter.next_path(), change).unwrap();
+            change_counter.record(change).unwrap();
         }
     }
 
@@ -129,7 +146,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
 
         assert_eq!(changes.len(), 3);
     }
@@ -148,7 +165,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
         let (chains, deleted_chains) = data::get_chains(changes);
 
         assert_eq!(chains.len(), 3);
@@ -169,7 +186,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
         let (_, mut deleted_chains) = data::get_chains(changes);
 
         assert_eq!(deleted_chains.front().unwrap().len(), 3);
@@ -189,7 +206,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
         let (mut chains, _) = data::get_chains(changes);
 
         assert_eq!(chains.get("1-1.txt").unwrap().len(), 3);
@@ -219,7 +236,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
         let (mut chains, _) = data::get_chains(changes);
 
         assert_eq!(chains.get("1-1.txt").unwrap().len(), 3);
@@ -249,7 +266,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
         let (_, mut deleted_chains) = data::get_chains(changes);
 
         assert_eq!(deleted_chains.front().unwrap().len(), 3);
@@ -257,6 +274,33 @@ mod test {
         assert_eq!(optimized.len(), 2);
     }
 
+    #[test]
+    fn test_optimize_undo_delete() {
+        let (file_handler_config, mut change_counter) =
+            testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+
+        let changes = [
+            "delete_file\n1.txt",
+            "undo_delete_file\n1.txt",
+            "modify_file\n1.txt",
+            "modify_file\n1.txt",
+            "undo_delete_file\n2.txt",
+            "delete_file\n2.txt",
+        ];
+        write_changes(&changes, &mut change_counter);
+
+        let changes = read_changes(&file_handler_config).unwrap();
+        let optimized = data::optimize_changes(changes);
+
+        assert_eq!(
+            optimized
+                .iter()
+                .map(|(_, change)| change.inner_event())
+                .collect::<Vec<_>>(),
+            vec![data::InnerEvent::Modify]
+        );
+    }
+
     #[test]
     fn test_lots() {
         let (file_handler_config, mut change_counter) =
@@ -274,7 +318,7 @@ mod test {
         ];
         write_changes(&changes, &mut change_counter);
 
-        let changes = read_changes(&file_handler_config);
+        let changes = read_changes(&file_handler_config).unwrap();
         let (mut chains, _) = data::get_chains(changes);
 
         assert_eq!(chains.get("e").unwrap().len(), 3);
@@ -289,6 +333,59 @@ mod test {
         let optimized = data::make_optimizations(chains.get_mut("b").unwrap());
         assert_eq!(optimized.len(), 1);
     }
+
+    #[test]
+    fn test_rebase_local_changes() {
+        let (file_handler_config, mut change_counter) =
+            testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+
+        let changes = [
+            "modify_file\ndocs/a.txt",
+            "delete_file\nold/b.txt",
+            "create_symlink\ndocs/link\na.txt",
+            "move_file\nold/e.txt\ne.txt",
+        ];
+        write_changes(&changes, &mut change_counter);
+        std::fs::write(file_handler_config.storage_directory.join("e.txt"), "hello").unwrap();
+        for change in changes {
+            assert_eq!(super::format_change(&super::parse_change(change)), change);
+        }
+
+        let server_changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (
+                1,
+                data::DirectoryMove::new("docs".to_string(), "documents".to_string()).into(),
+            ),
+            (2, data::DirectoryDelete::new("old".to_string()).into()),
+        ];
+        let rebased = super::rebase_local_changes(&server_changes, &file_handler_config).unwrap();
+        // The size of a file moved out of a deleted directory is read from the storage directory
+        assert_eq!(
+            rebased.last(),
+            Some(&(4, data::FileCreate::new(5, "e.txt".to_string()).into()))
+        );
+
+        let changes = read_changes(&file_handler_config)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    1,
+                    data::FileModify::new(0, "documents/a.txt".to_string()).into()
+                ),
+                (
+                    3,
+                    data::SymlinkCreate::new("documents/link".to_string(), "a.txt".to_string())
+                        .into()
+                ),
+                (4, data::FileCreate::new(0, "e.txt".to_string()).into()),
+            ]
+        );
+        assert_eq!(change_counter.record("create_dir\nnew").unwrap(), 5);
+    }
 }
 
 pub fn parse_change(text: &str) -> data::ChangeEvent {
@@ -338,6 +435,121 @@ pub fn parse_change(text: &str) -> data::ChangeEvent {
                 deleted_dir,
             )))
         }
+        "undo_delete_file" => {
+            let restored_file = text.lines().nth(1).unwrap().to_string();
+            data::ChangeEvent::File(data::FileEvent::UndoDelete(data::FileUndoDelete::new(
+                0,
+                restored_file,
+            )))
+        }
+        "undo_delete_dir" => {
+            let restored_dir = text.lines().nth(1).unwrap().to_string();
+            data::ChangeEvent::Directory(data::DirectoryEvent::UndoDelete(
+                data::DirectoryUndoDelete::new(restored_dir),
+            ))
+        }
+        "create_symlink" => {
+            let path = text.lines().nth(1).unwrap().to_string();
+            let links_to = text.lines().nth(2).unwrap().to_string();
+            data::ChangeEvent::Symlink(data::SymlinkEvent::Create(data::SymlinkCreate::new(
+                path, links_to,
+            )))
+        }
+        "delete_symlink" => {
+            let deleted_symlink = text.lines().nth(1).unwrap().to_string();
+            data::ChangeEvent::Symlink(data::SymlinkEvent::Delete(data::SymlinkDelete::new(
+                deleted_symlink,
+            )))
+        }
         _ => unimplemented!("Unimplemented change type: `{}`", first_line,),
     }
 }
+
+/// Inverse of [`parse_change`].
+pub fn format_change(change: &data::ChangeEvent) -> String {
+    match change {
+        data::ChangeEvent::File(file) => match file {
+            data::FileEvent::Create(file_create) => format!("create_file\n{}", file_create.path()),
+            data::FileEvent::Modify(file_modify) => format!("modify_file\n{}", file_modify.path()),
+            data::FileEvent::Move(file_move) => format!(
+                "move_file\n{}\n{}",
+                file_move.from_path(),
+                file_move.to_path()
+            ),
+            data::FileEvent::Delete(file_delete) => format!("delete_file\n{}", file_delete.path()),
+            data::FileEvent::UndoDelete(file_undo_delete) => {
+                format!("undo_delete_file\n{}", file_undo_delete.path())
+            }
+        },
+        data::ChangeEvent::Directory(dir) => match dir {
+            data::DirectoryEvent::Create(dir_create) => {
+                format!("create_dir\n{}", dir_create.path())
+            }
+            data::DirectoryEvent::Move(dir_move) => {
+                format!("move_dir\n{}\n{}", dir_move.from_path(), dir_move.to_path())
+            }
+            data::DirectoryEvent::Delete(dir_delete) => {
+                format!("delete_dir\n{}", dir_delete.path())
+            }
+            data::DirectoryEvent::UndoDelete(dir_undo_delete) => {
+                format!("undo_delete_dir\n{}", dir_undo_delete.path())
+            }
+        },
+        data::ChangeEvent::Symlink(symlink) => match symlink {
+            data::SymlinkEvent::Create(symlink_create) => format!(
+                "create_symlink\n{}\n{}",
+                symlink_create.path(),
+                symlink_create.links_to()
+            ),
+            data::SymlinkEvent::Delete(symlink_delete) => {
+                format!("delete_symlink\n{}", symlink_delete.path())
+            }
+        },
+    }
+}
+
+/// Rewrites the pending local changes so that they apply on top of `server_changes` (see
+/// [`rebase_changes`](data::rebase_changes)). Should be called before the local changes are
+/// sent to the server. Returns the rebased changes, with the size of each created or modified
+/// file read from the storage directory.
+pub fn rebase_local_changes(
+    server_changes: &[(i32, data::ChangeEvent)],
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> io::Result<Vec<(i32, data::ChangeEvent)>> {
+    let mut database =
+        client_database::ClientDatabase::open(&file_handler_config.program_data_directory)
+            .map_err(io::Error::other)?;
+    let changes = read_changes_in(&database)?.into_iter().collect();
+    let mut changes = data::rebase_changes(changes, server_changes);
+
+    let formatted = changes
+        .iter()
+        .map(|(change_number, change)| (*change_number as i64, format_change(change)))
+        .collect::<Vec<_>>();
+    database
+        .replace_changes(&formatted)
+        .map_err(io::Error::other)?;
+
+    for (_, change) in changes.iter_mut() {
+        let storage_path = file_handler_config
+            .storage_directory
+            .join(change.paths()[0]);
+        let size = match fs::metadata(&storage_path) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => continue,
+        };
+        *change = match change {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                data::FileCreate::new(size, file_create.path().to_string()).into()
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                data::FileModify::new(size, file_modify.path().to_string()).into()
+            }
+            data::ChangeEvent::File(data::FileEvent::UndoDelete(file_undo_delete)) => {
+                data::FileUndoDelete::new(size, file_undo_delete.path().to_string()).into()
+            }
+            _ => continue,
+        };
+    }
+    Ok(changes)
+}
@@ -0,0 +1,225 @@
+use std::{fs, io};
+
+use log::debug;
+
+use super::apply_changes::{create_parent_directory, write_file};
+use super::{relative_to_real, relative_to_symlink, relative_to_temporary};
+use super::{ClientDatabase, FileHandlerConfig, Transfer, TransferDirection};
+
+fn open_database(config: &FileHandlerConfig) -> io::Result<ClientDatabase> {
+    ClientDatabase::open(&config.program_data_directory).map_err(io::Error::other)
+}
+
+/// Makes the file at `relative_path` a placeholder of `size` bytes: a symlink in the symlink
+/// directory that points to a storage path that does not exist yet. Any local content is removed.
+///
+/// Placeholders are recorded in the [`ClientDatabase`] (see
+/// [`ClientDatabase::placeholder`]) so that the offline detection does not mistake them for
+/// deleted files.
+pub fn create_placeholder(
+    relative_path: &str,
+    size: u64,
+    config: &FileHandlerConfig,
+) -> io::Result<()> {
+    debug!("`create_placeholder`: `{}`", relative_path);
+    let storage_path = relative_to_real(&relative_path.into(), config);
+    let symlink_path = relative_to_symlink(&relative_path.into(), config);
+
+    create_parent_directory(relative_path, config)?;
+    if storage_path.exists() {
+        fs::remove_file(&storage_path)?;
+    }
+    if fs::read_link(&symlink_path).is_err() {
+        symlink::symlink_file(&storage_path, &symlink_path)?;
+    }
+    let mut database = open_database(config)?;
+    database
+        .remove_custom_metadata(relative_path)
+        .map_err(io::Error::other)?;
+    database
+        .set_placeholder(relative_path, size)
+        .map_err(io::Error::other)
+}
+
+/// Queues the download of the content of the placeholder at `relative_path`, e.g. when it is
+/// first accessed. Returns the download, or `None` if the file is not a placeholder. Once the
+/// content has been received into the temporary directory, it is moved into storage with
+/// [`hydrate`].
+pub fn request_content(
+    relative_path: &str,
+    config: &FileHandlerConfig,
+) -> io::Result<Option<Transfer>> {
+    let mut database = open_database(config)?;
+    let size = match database
+        .placeholder(relative_path)
+        .map_err(io::Error::other)?
+    {
+        Some(size) => size,
+        None => return Ok(None),
+    };
+    if let Some(transfer) = database
+        .transfers()
+        .map_err(io::Error::other)?
+        .into_iter()
+        .find(|transfer| {
+            transfer.path() == relative_path && transfer.direction() == TransferDirection::Download
+        })
+    {
+        return Ok(Some(transfer));
+    }
+
+    let transfer = Transfer::new(
+        relative_path.to_string(),
+        TransferDirection::Download,
+        size,
+        relative_to_temporary(relative_path.as_ref(), config),
+    );
+    database
+        .begin_transfer(&transfer)
+        .map_err(io::Error::other)?;
+    Ok(Some(transfer))
+}
+
+/// Keeps the file at `relative_path` downloaded, also after it is modified by the server. The
+/// content of a placeholder is requested (see [`request_content`]).
+pub fn pin(relative_path: &str, config: &FileHandlerConfig) -> io::Result<Option<Transfer>> {
+    open_database(config)?
+        .set_pinned(relative_path, true)
+        .map_err(io::Error::other)?;
+    request_content(relative_path, config)
+}
+
+pub fn unpin(relative_path: &str, config: &FileHandlerConfig) -> io::Result<()> {
+    open_database(config)?
+        .set_pinned(relative_path, false)
+        .map_err(io::Error::other)
+}
+
+/// Moves the content of the placeholder at `relative_path`, received into the temporary
+/// directory, into storage. Returns `false` if the file is not a placeholder.
+pub fn hydrate(relative_path: &str, config: &FileHandlerConfig) -> io::Result<bool> {
+    let mut database = open_database(config)?;
+    if database
+        .placeholder(relative_path)
+        .map_err(io::Error::other)?
+        .is_none()
+    {
+        return Ok(false);
+    }
+    debug!("`hydrate`: `{}`", relative_path);
+
+    write_file(relative_path, config)?;
+    database
+        .remove_placeholder(relative_path)
+        .map_err(io::Error::other)?;
+    database
+        .finish_transfer(relative_path, TransferDirection::Download)
+        .map_err(io::Error::other)?;
+    super::record_access(relative_path, config)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{hydrate, pin, request_content};
+    use crate::{client_database, client_detect_offline, data, testing_utils};
+
+    #[test]
+    fn test_placeholders() {
+        let (mut config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        config.placeholders = true;
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::DirectoryCreate::new("docs".to_string()).into()),
+            (2, data::FileCreate::new(5, "docs/a.txt".to_string()).into()),
+            (3, data::FileCreate::new(7, "docs/b.txt".to_string()).into()),
+            (4, data::FileCreate::new(9, "docs/c.txt".to_string()).into()),
+            (
+                5,
+                data::FileMove::new("docs/b.txt".to_string(), "b.txt".to_string()).into(),
+            ),
+            (6, data::FileCreate::new(3, "docs/d.txt".to_string()).into()),
+            (7, data::FileDelete::new("docs/d.txt".to_string()).into()),
+            // Restored files keep their size
+            (
+                8,
+                data::FileUndoDelete::new(3, "docs/d.txt".to_string()).into(),
+            ),
+        ];
+        client_database::apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        let database =
+            client_database::ClientDatabase::open(&config.program_data_directory).unwrap();
+        assert_eq!(
+            database.placeholders().unwrap(),
+            vec![
+                ("b.txt".to_string(), 7),
+                ("docs/a.txt".to_string(), 5),
+                ("docs/c.txt".to_string(), 9),
+                ("docs/d.txt".to_string(), 3)
+            ]
+        );
+        assert!(fs::read_link(config.symlink_directory.join("docs/a.txt")).is_ok());
+        assert!(!config.storage_directory.join("docs/a.txt").exists());
+
+        // Placeholders are not deleted files
+        client_detect_offline::detect_offline_changes(&config);
+        assert!(client_database::read_changes(&config).unwrap().is_empty());
+
+        // Local moves and deletes of placeholders are detected
+        fs::remove_file(config.symlink_directory.join("docs/c.txt")).unwrap();
+        fs::rename(
+            config.symlink_directory.join("b.txt"),
+            config.symlink_directory.join("docs/b.txt"),
+        )
+        .unwrap();
+        client_detect_offline::detect_offline_changes(&config);
+        let mut changes = client_database::read_changes(&config)
+            .unwrap()
+            .into_iter()
+            .map(|(_, change)| change)
+            .collect::<Vec<_>>();
+        changes.sort_by(|a, b| a.paths().cmp(&b.paths()));
+        assert_eq!(
+            changes,
+            vec![
+                data::FileMove::new("b.txt".to_string(), "docs/b.txt".to_string()).into(),
+                data::FileDelete::new("docs/c.txt".to_string()).into(),
+            ]
+        );
+        assert_eq!(database.placeholder("docs/b.txt").unwrap(), Some(7));
+        assert_eq!(database.placeholder("docs/c.txt").unwrap(), None);
+        change_counter.clear().unwrap();
+
+        // Pinning requests the content, which is moved into storage once received
+        let transfer = pin("docs/a.txt", &config).unwrap().unwrap();
+        assert_eq!(transfer.size(), 5);
+        assert_eq!(
+            request_content("docs/a.txt", &config).unwrap(),
+            Some(transfer.clone())
+        );
+        fs::create_dir_all(transfer.temporary_path().parent().unwrap()).unwrap();
+        fs::write(transfer.temporary_path(), "hello").unwrap();
+        assert!(hydrate("docs/a.txt", &config).unwrap());
+        assert!(!hydrate("docs/a.txt", &config).unwrap());
+        assert_eq!(
+            fs::read_to_string(config.symlink_directory.join("docs/a.txt")).unwrap(),
+            "hello"
+        );
+        assert!(database.transfers().unwrap().is_empty());
+        assert!(database.is_pinned("docs/a.txt").unwrap());
+
+        client_detect_offline::detect_offline_changes(&config);
+        assert!(client_database::read_changes(&config).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use std::fs;
+
+use crate::data;
+
+use super::FileHandlerConfig;
+
+/// Whether `path` is `subtree` or inside of it.
+fn in_subtree(path: &str, subtree: &str) -> bool {
+    match path.strip_prefix(subtree) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The subtrees that this device syncs. Paths are relative to the symlink directory.
+///
+/// A path is decided by the most specific (i.e. longest) `include` or `exclude` entry that
+/// contains it. Paths outside of every entry are synced when `include` is empty; otherwise only
+/// the directories leading to an included subtree are.
+///
+/// Excluded paths are neither detected as local changes nor downloaded, and server changes inside
+/// of them are skipped (see
+/// [`apply_server_changes`](super::apply_server_changes)). When a subtree is included again, the
+/// paths that are missing locally are given by [`catch_up_changes`].
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SelectiveSync {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl SelectiveSync {
+    pub fn is_synced(&self, path: &str) -> bool {
+        let include = self
+            .include
+            .iter()
+            .filter(|subtree| in_subtree(path, subtree))
+            .map(|subtree| subtree.len())
+            .max();
+        let exclude = self
+            .exclude
+            .iter()
+            .filter(|subtree| in_subtree(path, subtree))
+            .map(|subtree| subtree.len())
+            .max();
+        match (include, exclude) {
+            (Some(include), Some(exclude)) => include > exclude,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => {
+                self.include.is_empty()
+                    || self.include.iter().any(|subtree| in_subtree(subtree, path))
+            }
+        }
+    }
+
+    /// The part of a server `change` that applies to this device, or `None` if it is skipped.
+    /// A move out of the synced subtrees removes the local copy; a move into them creates it
+    /// (the content of a moved directory comes from [`catch_up_changes`]).
+    pub fn local_change(&self, change: &data::ChangeEvent) -> Option<data::ChangeEvent> {
+        let (from_path, to_path) = match change {
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                (file_move.from_path(), file_move.to_path())
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => {
+                (dir_move.from_path(), dir_move.to_path())
+            }
+            _ => {
+                return match change.paths().into_iter().all(|path| self.is_synced(path)) {
+                    true => Some(change.clone()),
+                    false => None,
+                }
+            }
+        };
+        let is_dir = matches!(change, data::ChangeEvent::Directory(_));
+        match (self.is_synced(from_path), self.is_synced(to_path), is_dir) {
+            (true, true, _) => Some(change.clone()),
+            (false, false, _) => None,
+            (true, false, false) => Some(data::FileDelete::new(from_path.to_string()).into()),
+            (true, false, true) => Some(data::DirectoryDelete::new(from_path.to_string()).into()),
+            (false, true, false) => Some(data::FileCreate::new(0, to_path.to_string()).into()),
+            (false, true, true) => Some(data::DirectoryCreate::new(to_path.to_string()).into()),
+        }
+    }
+}
+
+/// The changes that bring the synced subtrees up to date with `snapshot`: a create for every
+/// synced directory, file and symlink of the server that is missing locally, parents first. The
+/// content of the files must be downloaded into the temporary directory before the changes are
+/// applied with [`apply_change`](super::apply_change). Used after a subtree is included again.
+pub fn catch_up_changes(
+    snapshot: &data::Snapshot,
+    config: &FileHandlerConfig,
+) -> Vec<data::ChangeEvent> {
+    let missing = |path: &str| {
+        let symlink_path = config.symlink_directory.join(path);
+        !symlink_path.exists() && fs::read_link(&symlink_path).is_err()
+    };
+    let selective_sync = &config.selective_sync;
+
+    let directories = snapshot
+        .directories()
+        .iter()
+        .filter(|path| selective_sync.is_synced(path) && missing(path))
+        .map(|path| data::DirectoryCreate::new(path.clone()).into());
+    let files = snapshot
+        .files()
+        .iter()
+        .filter(|(path, _)| selective_sync.is_synced(path) && missing(path))
+        .map(|(path, size)| data::FileCreate::new(*size, path.clone()).into());
+    let symlinks = snapshot
+        .symlinks()
+        .iter()
+        .filter(|(path, _)| selective_sync.is_synced(path) && missing(path))
+        .map(|(path, links_to)| data::SymlinkCreate::new(path.clone(), links_to.clone()).into());
+    directories.chain(files).chain(symlinks).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{catch_up_changes, SelectiveSync};
+    use crate::{client_database, client_detect_offline, data, testing_utils};
+
+    #[test]
+    fn test_selective_sync() {
+        let selective_sync = SelectiveSync {
+            include: vec!["photos/2023".to_string(), "docs".to_string()],
+            exclude: vec!["photos/2023/raw".to_string()],
+        };
+        assert!(selective_sync.is_synced("docs"));
+        assert!(selective_sync.is_synced("docs/a.txt"));
+        assert!(!selective_sync.is_synced("docs2"));
+        assert!(selective_sync.is_synced("photos"));
+        assert!(!selective_sync.is_synced("photos/2022"));
+        assert!(selective_sync.is_synced("photos/2023/a.jpg"));
+        assert!(!selective_sync.is_synced("photos/2023/raw/a.raw"));
+        assert!(SelectiveSync::default().is_synced("anything"));
+
+        assert_eq!(
+            selective_sync.local_change(
+                &data::FileMove::new("docs/a.txt".to_string(), "b.txt".to_string()).into()
+            ),
+            Some(data::FileDelete::new("docs/a.txt".to_string()).into())
+        );
+        assert_eq!(
+            selective_sync.local_change(
+                &data::DirectoryMove::new("photos/2022".to_string(), "docs/2022".to_string())
+                    .into()
+            ),
+            Some(data::DirectoryCreate::new("docs/2022".to_string()).into())
+        );
+        assert_eq!(
+            selective_sync.local_change(&data::FileModify::new(0, "b.txt".to_string()).into()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_catch_up_changes() {
+        let (mut config, mut change_counter) = testing_utils::rm_dirs_ce_dirs_get_default_helpers();
+        config.selective_sync.exclude = vec!["photos".to_string()];
+        let mut server_version =
+            client_database::ServerVersion::init(&config.program_data_directory).unwrap();
+
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::DirectoryCreate::new("photos".to_string()).into()),
+            (
+                2,
+                data::FileCreate::new(3, "photos/a.jpg".to_string()).into(),
+            ),
+            (3, data::FileCreate::new(4, "b.txt".to_string()).into()),
+        ];
+        for (_, change) in &changes {
+            if let data::ChangeEvent::File(_) = change {
+                let path = change.paths()[0];
+                let content_path = client_database::relative_to_temporary(path.as_ref(), &config);
+                fs::create_dir_all(content_path.parent().unwrap()).unwrap();
+                fs::write(content_path, path).unwrap();
+            }
+        }
+        client_database::apply_server_changes(
+            &changes,
+            "laptop",
+            &config,
+            &mut server_version,
+            &mut change_counter,
+        )
+        .unwrap();
+        assert_eq!(server_version.server_version(), 3);
+        assert!(!config.symlink_directory.join("photos").exists());
+        assert!(config.symlink_directory.join("b.txt").exists());
+
+        // Local files in an excluded subtree are not uploaded
+        fs::create_dir(config.symlink_directory.join("photos")).unwrap();
+        fs::write(config.symlink_directory.join("photos/local.jpg"), "local").unwrap();
+        client_detect_offline::detect_offline_changes(&config);
+        assert!(client_database::read_changes(&config).unwrap().is_empty());
+        fs::remove_dir_all(config.symlink_directory.join("photos")).unwrap();
+
+        // Included again
+        config.selective_sync.exclude.clear();
+        let snapshot = data::Snapshot::from_changes(&changes);
+        let catch_up = catch_up_changes(&snapshot, &config);
+        assert_eq!(
+            catch_up,
+            vec![
+                data::DirectoryCreate::new("photos".to_string()).into(),
+                data::FileCreate::new(3, "photos/a.jpg".to_string()).into(),
+            ]
+        );
+        for change in &catch_up {
+            client_database::apply_change(change, &config).unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(config.symlink_directory.join("photos/a.jpg")).unwrap(),
+            "photos/a.jpg"
+        );
+        client_detect_offline::detect_offline_changes(&config);
+        assert!(client_database::read_changes(&config).unwrap().is_empty());
+    }
+}
@@ -14,7 +14,13 @@ pub fn run_live(file_handler_config: &client_database::FileHandlerConfig) {
     info!("Running live detect");
 
     let mut change_counter =
-        client_database::ChangeCounter::init(&file_handler_config.program_data_directory);
+        match client_database::ChangeCounter::init(&file_handler_config.program_data_directory) {
+            Ok(change_counter) => change_counter,
+            Err(e) => {
+                error!("Could not open the client database: {e}");
+                return;
+            }
+        };
     let data = (file_handler_config, &mut change_counter);
 
     let (tx, rx) = std::sync::mpsc::channel();
@@ -35,6 +41,39 @@ pub fn run_live(file_handler_config: &client_database::FileHandlerConfig) {
     }
 }
 
+/// Whether `path`, in the symlink or storage directory, is ignored (see
+/// [`IgnoreRules`](client_database::IgnoreRules)) or outside of the
+/// [`selective_sync`](client_database::FileHandlerConfig::selective_sync) subtrees. The rules are
+/// read for every event, so that changes to the `.hcsignore` files apply straight away.
+fn is_ignored(path: &path::Path, data: &super::Data) -> bool {
+    let relative_path = match path
+        .strip_prefix(&data.0.symlink_directory)
+        .or_else(|_| path.strip_prefix(&data.0.storage_directory))
+    {
+        Ok(relative_path) => relative_path,
+        Err(_) => return false,
+    };
+    !data.0.selective_sync.is_synced(relative_path.to_str().unwrap())
+        || client_database::IgnoreRules::new(data.0).is_ignored(relative_path, path.is_dir())
+}
+
+/// Whether `path`, in the symlink or storage directory, is still what
+/// [`apply_change`](client_database::apply_change) wrote there, in which case the event is not a
+/// local change.
+fn is_applied(path: &path::Path, data: &super::Data) -> bool {
+    let relative_path = match path
+        .strip_prefix(&data.0.symlink_directory)
+        .or_else(|_| path.strip_prefix(&data.0.storage_directory))
+    {
+        Ok(relative_path) => relative_path,
+        Err(_) => return false,
+    };
+    client_database::is_applied(relative_path, data.0).unwrap_or_else(|e| {
+        error!("Could not check whether `{}` was applied: {e}", path.display());
+        false
+    })
+}
+
 fn handle_event(
     event: notify::Result<notify::Event>,
     event_tracking: Arc<Mutex<HashMap<usize, path::PathBuf>>>,
@@ -45,6 +84,39 @@ fn handle_event(
 
     let in_shortcut = event.paths[0].starts_with(&data.0.symlink_directory);
 
+    if event.paths.iter().all(|path| is_applied(path, data)) {
+        return Ok(());
+    }
+
+    let ignored = event
+        .paths
+        .iter()
+        .map(|path| is_ignored(path, data))
+        .collect::<Vec<_>>();
+    match ignored.as_slice() {
+        // Moved out of an ignored or unsynced path
+        [true, false] => {
+            if in_shortcut && matches!(event.kind, notify::EventKind::Modify(_)) {
+                events::handle_create(
+                    &event.paths[1],
+                    event.paths[1].is_dir(),
+                    program_events,
+                    data,
+                );
+            }
+            return Ok(());
+        }
+        // Moved into an ignored or unsynced path
+        [false, true] => {
+            if in_shortcut && matches!(event.kind, notify::EventKind::Modify(_)) {
+                events::handle_delete(&event.paths[0], data);
+            }
+            return Ok(());
+        }
+        ignored if ignored.iter().all(|ignored| *ignored) => return Ok(()),
+        _ => (),
+    }
+
     match (in_shortcut, event.kind) {
         (true, notify::EventKind::Create(c)) => {
             let path = &event.paths[0];
@@ -17,10 +17,25 @@
 //!
 //! ## Cases when iterating through `B` (storage directory)
 //! - Real file exists **(7)**
-//! - Custom metadata file exists **(8)**
 //! - Directory exists **(9)**
 //! - Symlink exists **(10)**
 //!
+//! ## Cases when iterating through the custom metadata
+//! - Custom metadata exists **(8)**
+//!
+//! ## Cases for placeholders
+//! - Placeholder symlink removed **(11)**
+//!
+//! Paths ignored by the [`IgnoreRules`](client_database::IgnoreRules) (e.g. `.hcsignore` files)
+//! are skipped in both directories, along with everything inside of them. So are the subtrees
+//! excluded from [`selective_sync`](client_database::FileHandlerConfig::selective_sync).
+//!
+//! Before walking, the `.sc` sidecar files of older versions are imported into the
+//! [`ClientDatabase`](client_database::ClientDatabase) (see
+//! [`import_custom_metadata`](client_database::ClientDatabase::import_custom_metadata)), and
+//! change events that were interrupted (e.g. by a crash) are completed or rolled back, see
+//! [`recover`](client_database::change_events::recover).
+//!
 //! ## Walk symlink dir
 //! ### Case **`1`**
 //! Ignore for now
@@ -33,11 +48,16 @@
 //!
 //! i) Pass.
 //!
+//! **No, but it is a placeholder** (see [`create_placeholder`](client_database::create_placeholder))
+//!
+//! i) Pass. The content has not been downloaded. If the placeholder has been moved, add a `move`
+//! change.
+//!
 //! **No**
 //!
 //! i) Delete symlink.
 //!
-//! ii) Delete custom metadata file if exists.
+//! ii) Delete custom metadata if exists.
 //!
 //! iii) Add `delete` change.
 //!
@@ -51,7 +71,7 @@
 //!
 //! i) Move the real file to path of symlink `point to` in `B` directory.
 //!
-//! ii) Move custom metadata to path of symlink `point to` in `B` directory.
+//! ii) Move custom metadata to the path of symlink `point to`.
 //!
 //! iii) Add `move` change.
 //!
@@ -60,12 +80,12 @@
 //!
 //! ### Case **`4`**
 //! 1) Move the file to the `B` directory.
-//! 2) Create custom metadata file with the last modified time of the file.
-//! 3) Create a symlink in the `A` directory that points to the file in the `B` directory.
-//! 2) Add `create` change.
+//! 2) Create a symlink in the `A` directory that points to the file in the `B` directory.
+//! 3) Create custom metadata with the last modified time of the file.
+//! 4) Add `create` change.
 //!
 //! ### Case **`5`**
-//! 1) Check if directory and custom metadata file exists in `B` directory:
+//! 1) Check if directory exists in `B` directory and has custom metadata:
 //!
 //! **Yes**
 //!
@@ -75,7 +95,7 @@
 //!
 //! i) Create directory in `B` directory.
 //!
-//! ii) Create custom metadata file in `B` directory.
+//! ii) Create custom metadata.
 //!
 //! iii) Add `create` change.
 //!
@@ -84,7 +104,7 @@
 //!
 //! ## Walk real directory
 //! ### Case **`7`**
-//! 1) Check if custom metadata file exists `and` symlink in `A` exists:
+//! 1) Check if custom metadata exists `and` symlink in `A` exists:
 //!
 //! **Yes**
 //!
@@ -100,7 +120,7 @@
 //!
 //! iv) Continue.
 //!
-//! 2) Check if the last modified of the real file and custom metadata file are the same:
+//! 2) Check if the last modified of the real file and custom metadata are the same:
 //!
 //! **Yes**
 //!
@@ -110,8 +130,8 @@
 //!
 //! i) Add a `modified` change.
 //!
-//! ### Case **`8`**
-//! 1) Check if real file exists `and` symlink in `A` exists:
+//! ### Case **`9`**
+//! 1) Check if custom metadata exists `and` symlink in `A` exists:
 //!
 //! **Yes**
 //!
@@ -119,14 +139,20 @@
 //!
 //! **No**
 //!
-//! i) Delete symlink if exists.
+//! i) Delete custom metadata if exists.
 //!
-//! ii) Delete real file if exists.
+//! ii) Delete symlink file if exists.
 //!
 //! iii) Add `delete` change.
 //!
-//! ### Case **`9`**
-//! 1) Check if custom metadata file exists `and` symlink in `A` exists:
+//! ### Case **`10`**
+//! Ignore the file. Continue.
+//!
+//! ## Walk custom metadata
+//! ### Case **`8`**
+//! Once `B` has been walked, for the custom metadata of each path:
+//!
+//! 1) Check if real file exists `and` symlink in `A` exists:
 //!
 //! **Yes**
 //!
@@ -134,15 +160,23 @@
 //!
 //! **No**
 //!
-//! i) Delete custom metadata file if exists.
+//! i) Delete symlink if exists.
 //!
-//! ii) Delete symlink file if exists.
+//! ii) Delete real file if exists.
 //!
 //! iii) Add `delete` change.
 //!
-//! ### Case **`10`**
-//! Ignore the file. Continue.
+//! ## Placeholders
+//! ### Case **`11`**
+//! Placeholders have nothing in `B`, so once both directories have been walked, each placeholder
+//! whose symlink no longer exists:
+//!
+//! i) Add `delete` change, unless its parent directory was deleted.
 //!
+//! ii) Forget the placeholder.
+//!
+use std::io;
+
 use crate::client_database;
 
 mod storage_cases;
@@ -150,23 +184,63 @@ mod symlink_cases;
 mod walk_storage_dir;
 mod walk_symlink_dir;
 
+/// Logs a change that could not be recorded. Its steps that were applied are completed or
+/// rolled back the next time changes are detected (see
+/// [`recover`](client_database::change_events::recover)).
+fn log_error(result: io::Result<()>, relative_path: &std::path::Path) {
+    if let Err(err) = result {
+        log::error!(
+            "Could not record the change of `{}`: {}",
+            relative_path.display(),
+            err
+        );
+    }
+}
+
 pub fn detect_offline_changes(file_handler_config: &client_database::FileHandlerConfig) {
     let mut change_counter =
-        client_database::ChangeCounter::init(&file_handler_config.program_data_directory);
-
-    // client_database::make_blank_file(
-    //     &file_handler_config.program_data_directory,
-    //     change_counter.increment(),
-    // );
+        match client_database::ChangeCounter::init(&file_handler_config.program_data_directory) {
+            Ok(change_counter) => change_counter,
+            Err(err) => {
+                log::error!("Could not open the client database: {}", err);
+                return;
+            }
+        };
+    if let Err(err) = change_counter
+        .database()
+        .import_custom_metadata(file_handler_config)
+    {
+        log::error!("Could not import the custom metadata: {}", err);
+        return;
+    }
+    if let Err(err) =
+        client_database::change_events::recover(file_handler_config, &mut change_counter)
+    {
+        log::error!("Could not recover interrupted changes: {}", err);
+    }
+    let ignore_rules = client_database::IgnoreRules::new(file_handler_config);
 
     walk_symlink_dir::walk_symlink(
         &file_handler_config.symlink_directory,
         &file_handler_config,
+        &ignore_rules,
         &mut change_counter,
     );
     walk_storage_dir::walk_storage(
         &file_handler_config.storage_directory,
         &file_handler_config,
+        &ignore_rules,
         &mut change_counter,
     );
+    if let Err(err) = walk_storage_dir::walk_custom_metadata(
+        file_handler_config,
+        &ignore_rules,
+        &mut change_counter,
+    ) {
+        log::error!("Could not walk the custom metadata: {}", err);
+    }
+    if let Err(err) = symlink_cases::placeholders_removed(file_handler_config, &mut change_counter)
+    {
+        log::error!("Could not record the removed placeholders: {}", err);
+    }
 }
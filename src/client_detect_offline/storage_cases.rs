@@ -1,4 +1,4 @@
-use std::fs;
+use std::{fs, io};
 
 use log::trace;
 
@@ -7,38 +7,53 @@ use crate::client_database;
 /// Case 7
 pub fn real_file_exists(
     file: &client_database::FilePaths,
+    file_handler_config: &client_database::FileHandlerConfig,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     trace!(
         "Real file exists (storage): {}",
         file.relative_path().display()
     );
-    {
-        if !(file.custom_metadata_path().exists() && fs::read_link(file.symlink_dir_path()).is_ok())
-        {
-            client_database::change_events::delete_file(file, change_counter);
-            return;
-        }
-    }
+    let custom_metadata =
+        client_database::CustomMetadata::read_from_database(file, change_counter.database())?;
+    let mut custom_metadata = match custom_metadata {
+        Some(custom_metadata) if fs::read_link(file.symlink_dir_path()).is_ok() => custom_metadata,
+        _ => return client_database::change_events::delete_file(file, change_counter),
+    };
 
     {
-        // Check if last modified of file is the same as the last modified contained in the custom metadata
-        let file_last_modified =
-            client_database::CustomMetadata::last_modified_of_file(&file.storage_dir_path())
-                .unwrap();
-        let custom_metadata = client_database::CustomMetadata::read_from_file(&file).unwrap();
-
-        if file_last_modified != custom_metadata.last_modified() {
-            client_database::change_events::modify_file(file, change_counter);
+        // Check if last modified and size of file are the same as those contained in the custom metadata
+        let current = client_database::CustomMetadata::stat(file.storage_dir_path())?;
+        let stat_modified = custom_metadata.is_modified(&current);
+
+        // Then, depending on the hash policy, whether the content actually changed
+        let content_modified = match (file_handler_config.hash_policy, custom_metadata.hash()) {
+            (client_database::HashPolicy::Never, _) | (_, None) => stat_modified,
+            (client_database::HashPolicy::WhenChanged, _) if !stat_modified => false,
+            (_, Some(hash)) => {
+                client_database::CustomMetadata::hash_of_file(file.storage_dir_path())? != hash
+            }
+        };
+
+        if content_modified {
+            client_database::change_events::modify_file(file, change_counter)?;
+        } else if stat_modified {
+            trace!(
+                "Content unchanged, updating custom metadata: {}",
+                file.relative_path().display()
+            );
+            custom_metadata.refresh(file.storage_dir_path())?;
+            custom_metadata.write_to_database(file, change_counter.database())?;
         }
     }
+    Ok(())
 }
 
 /// Case 8
 pub fn custom_metadata_exists(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     trace!(
         "Custom metadata exists (storage): {}",
         file.relative_path().display()
@@ -59,38 +74,39 @@ pub fn custom_metadata_exists(
     };
 
     if is_dir && !(file.symlink_dir_path().exists() && file.storage_dir_path().exists()) {
-        client_database::change_events::delete_dir(file, change_counter);
+        client_database::change_events::delete_dir(file, change_counter)?;
     } else if !is_dir
         && !(file.storage_dir_path().exists() && fs::read_link(&file.symlink_dir_path()).is_ok())
     {
-        client_database::change_events::delete_file(file, change_counter)
+        client_database::change_events::delete_file(file, change_counter)?;
     }
+    Ok(())
 }
 
 /// Case 9
 pub fn directory_exists(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     trace!(
         "Directory exists (storage): {}",
         file.relative_path().display()
     );
-    if !(file.custom_metadata_path().exists() && file.symlink_dir_path().exists()) {
-        client_database::change_events::delete_dir(file, change_counter);
-        return;
-    }
+    let custom_metadata =
+        client_database::CustomMetadata::read_from_database(file, change_counter.database())?;
+    let custom_metadata = match custom_metadata {
+        Some(custom_metadata) if file.symlink_dir_path().exists() => custom_metadata,
+        _ => return client_database::change_events::delete_dir(file, change_counter),
+    };
 
     {
-        let directory_last_modified =
-            client_database::CustomMetadata::last_modified_of_file(&file.symlink_dir_path())
-                .unwrap();
-        let custom_metadata = client_database::CustomMetadata::read_from_file(&file).unwrap();
+        let current = client_database::CustomMetadata::stat(file.symlink_dir_path())?;
 
-        if directory_last_modified != custom_metadata.last_modified() {
-            client_database::change_events::modify_dir(file);
+        if custom_metadata.is_modified(&current) {
+            client_database::change_events::modify_dir(file, change_counter)?;
         }
     }
+    Ok(())
 }
 
 /// Case 10
@@ -102,9 +118,12 @@ pub fn symlink_exists() {
 
 #[cfg(test)]
 mod test {
-    use std::{fs, path};
+    use std::{fs, path, time};
 
-    use crate::{client_database, testing_utils::rm_dirs_ce_dirs_get_default_helpers};
+    use crate::{
+        client_database,
+        testing_utils::{has_custom_metadata, rm_dirs_ce_dirs_get_default_helpers},
+    };
 
     use super::*;
 
@@ -116,10 +135,6 @@ mod test {
             &path::PathBuf::from("file.txt"),
             &file_handler_config,
         );
-        let _custom_metadata_fp = client_database::relative_to_custom_metadata(
-            &path::PathBuf::from("file.txt"),
-            &file_handler_config,
-        );
         let symlink_path = client_database::relative_to_symlink(
             &path::PathBuf::from("file.txt"),
             &file_handler_config,
@@ -141,23 +156,26 @@ mod test {
         let custom_metadata = client_database::CustomMetadata::new(
             client_database::CustomMetadata::last_modified_of_file(&real_fp).unwrap(),
         );
-        custom_metadata.write_to_file(&file).unwrap();
+        custom_metadata
+            .write_to_database(&file, change_counter.database())
+            .unwrap();
 
         // call function
-        real_file_exists(&file, &mut change_counter);
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
 
-        assert!(file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&file, &mut change_counter));
         assert!(file.symlink_dir_path().exists());
         assert!(file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 0);
 
         // remove custom_metadata
-        fs::remove_file(file.custom_metadata_path()).unwrap();
+        client_database::CustomMetadata::remove_from_database(&file, change_counter.database())
+            .unwrap();
 
         // call function
-        real_file_exists(&file, &mut change_counter);
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
 
-        assert!(!file.custom_metadata_path().exists());
+        assert!(!has_custom_metadata(&file, &mut change_counter));
         assert!(!file.symlink_dir_path().exists());
         assert!(!file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 1);
@@ -176,17 +194,88 @@ mod test {
 
         // create custom_metadata
         let custom_metadata = client_database::CustomMetadata::new(0);
-        custom_metadata.write_to_file(&file).unwrap();
+        custom_metadata
+            .write_to_database(&file, change_counter.database())
+            .unwrap();
 
         // call function
-        real_file_exists(&file, &mut change_counter);
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
 
-        assert!(file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&file, &mut change_counter));
         assert!(file.symlink_dir_path().exists());
         assert!(file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 2);
     }
 
+    #[test]
+    fn test_real_file_exists_hash_policy() {
+        let (mut file_handler_config, mut change_counter) = rm_dirs_ce_dirs_get_default_helpers();
+
+        let real_fp = client_database::relative_to_real(
+            &path::PathBuf::from("file.txt"),
+            &file_handler_config,
+        );
+        let symlink_path = client_database::relative_to_symlink(
+            &path::PathBuf::from("file.txt"),
+            &file_handler_config,
+        );
+        fs::write(&real_fp, "Hello, world!").unwrap();
+        symlink::symlink_file(&real_fp, &symlink_path).unwrap();
+        let file = client_database::FilePaths::from_path_checked(
+            symlink_path.clone(),
+            &file_handler_config,
+        )
+        .unwrap();
+        client_database::CustomMetadata::of_file(&real_fp)
+            .unwrap()
+            .write_to_database(&file, change_counter.database())
+            .unwrap();
+
+        // touched
+        let touched = time::SystemTime::now() + time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&real_fp)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
+        assert!(change_counter.change_count() == 0);
+        let custom_metadata =
+            client_database::CustomMetadata::read_from_database(&file, change_counter.database())
+                .unwrap()
+                .unwrap();
+        assert!(
+            !custom_metadata.is_modified(&client_database::CustomMetadata::stat(&real_fp).unwrap())
+        );
+
+        // modified, keeping the size and last modified time
+        fs::write(&real_fp, "Hello, World!").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&real_fp)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
+        assert!(change_counter.change_count() == 0);
+
+        file_handler_config.hash_policy = client_database::HashPolicy::Always;
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
+        assert!(change_counter.change_count() == 1);
+
+        // touched, without hashing
+        file_handler_config.hash_policy = client_database::HashPolicy::Never;
+        fs::File::options()
+            .write(true)
+            .open(&real_fp)
+            .unwrap()
+            .set_modified(touched + time::Duration::from_secs(60))
+            .unwrap();
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
+        assert!(change_counter.change_count() == 2);
+    }
+
     #[test]
     fn test_custom_metadata_exists() {
         let (file_handler_config, mut change_counter) = rm_dirs_ce_dirs_get_default_helpers();
@@ -217,12 +306,14 @@ mod test {
         let custom_metadata = client_database::CustomMetadata::new(
             client_database::CustomMetadata::last_modified_of_file(&real_fp).unwrap(),
         );
-        custom_metadata.write_to_file(&file).unwrap();
+        custom_metadata
+            .write_to_database(&file, change_counter.database())
+            .unwrap();
 
         // call function
-        custom_metadata_exists(&file, &mut change_counter);
+        custom_metadata_exists(&file, &mut change_counter).unwrap();
 
-        assert!(file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&file, &mut change_counter));
         assert!(file.symlink_dir_path().exists());
         assert!(file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 0);
@@ -231,9 +322,9 @@ mod test {
         symlink::remove_symlink_file(&symlink_path).unwrap();
 
         // call function
-        custom_metadata_exists(&file, &mut change_counter);
+        custom_metadata_exists(&file, &mut change_counter).unwrap();
 
-        assert!(!file.custom_metadata_path().exists());
+        assert!(!has_custom_metadata(&file, &mut change_counter));
         assert!(!file.symlink_dir_path().exists());
         assert!(!file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 1);
@@ -262,12 +353,14 @@ mod test {
             client_database::CustomMetadata::last_modified_of_file(&real_fp).unwrap(),
         );
 
-        custom_metadata.write_to_file(&file).unwrap();
+        custom_metadata
+            .write_to_database(&file, change_counter.database())
+            .unwrap();
 
         // call function
-        custom_metadata_exists(&file, &mut change_counter);
+        custom_metadata_exists(&file, &mut change_counter).unwrap();
 
-        assert!(file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&file, &mut change_counter));
         assert!(file.symlink_dir_path().exists());
         assert!(file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 1);
@@ -276,9 +369,9 @@ mod test {
         fs::remove_dir(&symlink_path).unwrap();
 
         // call function
-        custom_metadata_exists(&file, &mut change_counter);
+        custom_metadata_exists(&file, &mut change_counter).unwrap();
 
-        assert!(!file.custom_metadata_path().exists());
+        assert!(!has_custom_metadata(&file, &mut change_counter));
         assert!(!file.symlink_dir_path().exists());
         assert!(!file.storage_dir_path().exists());
         assert!(change_counter.change_count() == 2);
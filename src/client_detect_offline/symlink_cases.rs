@@ -1,4 +1,4 @@
-use std::{fs, path};
+use std::{fs, io, path};
 
 use log::trace;
 
@@ -22,12 +22,15 @@ pub fn symlink_points_to_storage_dir(
     file: &client_database::FilePaths,
     file_handler_config: &client_database::FileHandlerConfig,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     trace!(
         "Symlink points to storage dir: {}",
         file.relative_path().display()
     );
     let points_to = file.points_to().unwrap();
+    let mut database =
+        client_database::ClientDatabase::open(&file_handler_config.program_data_directory)
+            .map_err(io::Error::other)?;
     if points_to.exists() {
         // Check if relative path of point_to and actual relative path of the symlink are the same
         let points_to_relative_path = points_to
@@ -42,11 +45,82 @@ pub fn symlink_points_to_storage_dir(
                 file_handler_config,
                 change_counter,
                 client_database::Type::Symlink,
-            );
+            )?;
+        }
+    } else if let Some(points_to_relative_path) =
+        placeholder_relative_path(points_to, &database, file_handler_config)?
+    {
+        // The content of a placeholder is not downloaded yet
+        let symlink_relative_path = file.relative_path();
+        if &points_to_relative_path != symlink_relative_path {
+            client_database::change_events::move_file(
+                &points_to_relative_path,
+                symlink_relative_path,
+                file_handler_config,
+                change_counter,
+                client_database::Type::Symlink,
+            )?;
+            database
+                .move_placeholders(
+                    points_to_relative_path.to_str().unwrap(),
+                    symlink_relative_path.to_str().unwrap(),
+                )
+                .map_err(io::Error::other)?;
+            symlink::remove_symlink_file(file.symlink_dir_path())?;
+            symlink::symlink_file(file.storage_dir_path(), file.symlink_dir_path())?;
         }
     } else {
-        client_database::change_events::delete_file(file, change_counter);
+        client_database::change_events::delete_file(file, change_counter)?;
+    }
+    Ok(())
+}
+
+/// The relative path of the placeholder that `points_to` is the storage path of, if any.
+fn placeholder_relative_path(
+    points_to: &path::Path,
+    database: &client_database::ClientDatabase,
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> io::Result<Option<path::PathBuf>> {
+    let relative_path = match points_to.strip_prefix(&file_handler_config.storage_directory) {
+        Ok(relative_path) => relative_path,
+        Err(_) => return Ok(None),
+    };
+    let placeholder = match relative_path.to_str() {
+        Some(path) => database.placeholder(path).map_err(io::Error::other)?,
+        None => None,
+    };
+    Ok(placeholder.map(|_| relative_path.to_path_buf()))
+}
+
+/// Case 11
+pub fn placeholders_removed(
+    file_handler_config: &client_database::FileHandlerConfig,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<()> {
+    let mut database =
+        client_database::ClientDatabase::open(&file_handler_config.program_data_directory)
+            .map_err(io::Error::other)?;
+    for (relative_path, _) in database.placeholders().map_err(io::Error::other)? {
+        let file = client_database::FilePaths::from_relative_path(
+            path::PathBuf::from(&relative_path),
+            client_database::Type::Symlink,
+            client_database::FileLocation::SymlinkDir,
+            None,
+            file_handler_config,
+        )?;
+        if fs::read_link(file.symlink_dir_path()).is_ok() {
+            continue;
+        }
+        trace!("Placeholder removed: {}", relative_path);
+        // A deleted parent directory is already recorded
+        if file.symlink_dir_path().parent().unwrap().is_dir() {
+            client_database::change_events::delete_file(&file, change_counter)?;
+        }
+        database
+            .forget_placeholders(&relative_path)
+            .map_err(io::Error::other)?;
     }
+    Ok(())
 }
 
 /// Case 3
@@ -60,7 +134,7 @@ pub fn real_file_exists(
     file: &client_database::FilePaths,
     file_handler_config: &client_database::FileHandlerConfig,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     trace!(
         "Real file exists (symlink): {}",
         file.relative_path().display()
@@ -96,11 +170,10 @@ pub fn real_file_exists(
     };
 
     // Move the file to the storage directory
-    fs::rename(file.symlink_dir_path(), &unique_storage_path).unwrap();
+    fs::rename(file.symlink_dir_path(), &unique_storage_path)?;
 
     let unique_file =
-        client_database::FilePaths::from_path_checked(unique_storage_path, file_handler_config)
-            .unwrap();
+        client_database::FilePaths::from_path_checked(unique_storage_path, file_handler_config)?;
     client_database::change_events::create_file(&unique_file, change_counter)
 }
 
@@ -108,15 +181,19 @@ pub fn real_file_exists(
 pub fn directory_exists(
     file: &client_database::FilePaths,
     change_counter: &mut client_database::ChangeCounter,
-) {
+) -> io::Result<()> {
     trace!(
         "Directory exists (symlink): {}",
         file.relative_path().display()
     );
-    // Check if directory and custom metadata exists in the storage directory
-    if !(file.storage_dir_path().exists() && file.custom_metadata_path().exists()) {
-        client_database::change_events::create_dir(file, change_counter);
+    // Check if the directory exists in the storage directory and has custom metadata
+    if !(file.storage_dir_path().exists()
+        && client_database::CustomMetadata::read_from_database(file, change_counter.database())?
+            .is_some())
+    {
+        client_database::change_events::create_dir(file, change_counter)?;
     }
+    Ok(())
 }
 
 /// Case 6
@@ -133,8 +210,11 @@ pub fn custom_metadata_exists(custom_metadata_in_symlink_path: &path::PathBuf) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{client_database, testing_utils::rm_dirs_ce_dirs_get_default_helpers};
-    use std::{fs, path};
+    use crate::{
+        client_database,
+        testing_utils::{has_custom_metadata, rm_dirs_ce_dirs_get_default_helpers},
+    };
+    use std::fs;
 
     #[test]
     fn test_custom_metadata_exists() {
@@ -168,10 +248,10 @@ mod tests {
             &file_handler_config,
         )
         .unwrap();
-        directory_exists(&file, &mut change_counter);
+        directory_exists(&file, &mut change_counter).unwrap();
 
         assert!(file.storage_dir_path().exists());
-        assert!(file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&file, &mut change_counter));
         assert!(file.symlink_dir_path().exists());
     }
 
@@ -198,7 +278,7 @@ mod tests {
         )
         .unwrap();
 
-        real_file_exists(&file, &file_handler_config, &mut change_counter);
+        real_file_exists(&file, &file_handler_config, &mut change_counter).unwrap();
 
         let unique_file = client_database::FilePaths::from_path_checked(
             file_handler_config.storage_directory.join("file (2).txt"),
@@ -208,7 +288,7 @@ mod tests {
         assert!(unique_file.is_ok());
         let unique_file = unique_file.unwrap();
         assert!(unique_file.storage_dir_path().exists());
-        assert!(unique_file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&unique_file, &mut change_counter));
     }
 
     #[test]
@@ -219,12 +299,16 @@ mod tests {
         let storage_file = &file_handler_config.storage_directory.join("file.txt");
         fs::write(&storage_file, "Hello World!").unwrap();
 
-        // create a custom metadata file
-        let custom_metadata_file = client_database::relative_to_custom_metadata(
-            &path::PathBuf::from("file.txt"),
+        // create the custom metadata
+        let stored_file = client_database::FilePaths::from_path_checked(
+            storage_file.clone(),
             &file_handler_config,
-        );
-        fs::write(&custom_metadata_file, "some metadata").unwrap();
+        )
+        .unwrap();
+        client_database::CustomMetadata::of_file(storage_file)
+            .unwrap()
+            .write_to_database(&stored_file, change_counter.database())
+            .unwrap();
 
         // make a symlink in a different location that points to the storage file (simulate a move)
         let symlink_file = &file_handler_config.symlink_directory.join("file1.txt");
@@ -237,11 +321,12 @@ mod tests {
         )
         .unwrap();
 
-        symlink_points_to_storage_dir(&file, &file_handler_config, &mut change_counter);
+        symlink_points_to_storage_dir(&file, &file_handler_config, &mut change_counter).unwrap();
 
         assert!(fs::read_link(&file.symlink_dir_path()).is_ok());
         assert!(file.storage_dir_path().exists());
-        assert!(file.custom_metadata_path().exists());
+        assert!(has_custom_metadata(&file, &mut change_counter));
+        assert!(!has_custom_metadata(&stored_file, &mut change_counter));
 
         // now delete the storage file (file1.txt - simulate a delete)
         let new_storage_file = &file_handler_config.storage_directory.join("file1.txt");
@@ -253,10 +338,11 @@ mod tests {
             &file_handler_config,
         )
         .unwrap();
-        symlink_points_to_storage_dir(&new_file, &file_handler_config, &mut change_counter);
+        symlink_points_to_storage_dir(&new_file, &file_handler_config, &mut change_counter)
+            .unwrap();
 
         assert!(!new_file.storage_dir_path().exists());
-        assert!(!new_file.custom_metadata_path().exists());
+        assert!(!has_custom_metadata(&new_file, &mut change_counter));
         assert!(!new_file.symlink_dir_path().exists());
     }
 }
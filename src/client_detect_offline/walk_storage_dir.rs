@@ -1,12 +1,13 @@
-use std::{fs, path};
+use std::{fs, io, path};
 
 use crate::client_database;
 
-use super::storage_cases;
+use super::{log_error, storage_cases};
 
 pub fn walk_storage(
     dir: &path::PathBuf,
     file_handler_config: &client_database::FileHandlerConfig,
+    ignore_rules: &client_database::IgnoreRules,
     change_counter: &mut client_database::ChangeCounter,
 ) {
     log::info!("Walking storage directory: {:?}", dir);
@@ -18,23 +19,47 @@ pub fn walk_storage(
         let file_paths =
             client_database::FilePaths::from_path_checked(path_buf.clone(), &file_handler_config)
                 .unwrap();
+        let is_dir = path_buf.is_dir() || file_paths.storage_dir_path().is_dir();
+        if ignore_rules.is_ignored(file_paths.relative_path(), is_dir) {
+            log::trace!("Ignored: {}", file_paths.relative_path().display());
+            continue;
+        }
+        if !file_handler_config
+            .selective_sync
+            .is_synced(file_paths.relative_path().to_str().unwrap())
+        {
+            log::trace!("Not synced: {}", file_paths.relative_path().display());
+            continue;
+        }
 
         match file_paths.file_type() {
             client_database::Type::File => {
                 // Case 7
-                storage_cases::real_file_exists(&file_paths, change_counter);
+                log_error(
+                    storage_cases::real_file_exists(
+                        &file_paths,
+                        file_handler_config,
+                        change_counter,
+                    ),
+                    file_paths.relative_path(),
+                );
             }
             client_database::Type::CustomMetadata => {
-                // Case 8
-                storage_cases::custom_metadata_exists(&file_paths, change_counter);
+                // A `.sc` sidecar file of an older version, which has already been imported
+                // (see `ClientDatabase::import_custom_metadata`)
+                log::trace!("Sidecar file: {}", file_paths.relative_path().display());
             }
             client_database::Type::Directory => {
                 // Case 9
-                storage_cases::directory_exists(&file_paths, change_counter);
+                log_error(
+                    storage_cases::directory_exists(&file_paths, change_counter),
+                    file_paths.relative_path(),
+                );
                 if file_paths.symlink_dir_path().exists() {
                     walk_storage(
                         file_paths.symlink_dir_path(),
                         file_handler_config,
+                        ignore_rules,
                         change_counter,
                     );
                 }
@@ -46,3 +71,50 @@ pub fn walk_storage(
         }
     }
 }
+
+/// Case 8 handled here, once the storage directory has been walked: the custom metadata of
+/// every path in the [`ClientDatabase`](client_database::ClientDatabase) is checked against the
+/// storage and symlink directories.
+pub fn walk_custom_metadata(
+    file_handler_config: &client_database::FileHandlerConfig,
+    ignore_rules: &client_database::IgnoreRules,
+    change_counter: &mut client_database::ChangeCounter,
+) -> io::Result<()> {
+    log::info!("Walking custom metadata");
+
+    let relative_paths = change_counter
+        .database()
+        .custom_metadata_paths()
+        .map_err(io::Error::other)?;
+    for relative_path in relative_paths {
+        let file_paths = client_database::FilePaths::from_relative_path(
+            path::PathBuf::from(&relative_path),
+            client_database::Type::CustomMetadata,
+            client_database::FileLocation::StorageDir,
+            None,
+            file_handler_config,
+        )?;
+        let is_dir =
+            file_paths.storage_dir_path().is_dir() || file_paths.symlink_dir_path().is_dir();
+        if ignore_rules.is_ignored(file_paths.relative_path(), is_dir)
+            || !file_handler_config.selective_sync.is_synced(&relative_path)
+        {
+            continue;
+        }
+        // The custom metadata of everything inside of a deleted directory is removed with it
+        if change_counter
+            .database()
+            .custom_metadata(&relative_path)
+            .map_err(io::Error::other)?
+            .is_none()
+        {
+            continue;
+        }
+
+        log_error(
+            storage_cases::custom_metadata_exists(&file_paths, change_counter),
+            file_paths.relative_path(),
+        );
+    }
+    Ok(())
+}
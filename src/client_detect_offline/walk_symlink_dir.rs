@@ -1,12 +1,13 @@
 use std::{fs, path};
 
-use super::symlink_cases;
+use super::{log_error, symlink_cases};
 use crate::client_database;
 
 /// Cases 1 -> 6 handled here. See `client_detect_offline` for an explanation of the cases.
 pub fn walk_symlink(
     dir: &path::PathBuf,
     file_handler_config: &client_database::FileHandlerConfig,
+    ignore_rules: &client_database::IgnoreRules,
     change_counter: &mut client_database::ChangeCounter,
 ) {
     log::info!("Walking symlink directory: {:?}", dir);
@@ -18,16 +19,39 @@ pub fn walk_symlink(
         let file_paths =
             client_database::FilePaths::from_path_checked(path_buf.clone(), &file_handler_config)
                 .unwrap();
+        let is_dir = path_buf.is_dir() || file_paths.storage_dir_path().is_dir();
+        if ignore_rules.is_ignored(file_paths.relative_path(), is_dir) {
+            log::trace!("Ignored: {}", file_paths.relative_path().display());
+            continue;
+        }
+        if !file_handler_config
+            .selective_sync
+            .is_synced(file_paths.relative_path().to_str().unwrap())
+        {
+            log::trace!("Not synced: {}", file_paths.relative_path().display());
+            continue;
+        }
 
         match (file_paths.file_location(), file_paths.file_type()) {
             (client_database::FileLocation::SymlinkDir, client_database::Type::Symlink) => {
                 // Case 1/2/3
-                let points_to = client_database::FilePaths::from_path_checked(
-                    file_paths.points_to().unwrap().clone(),
-                    &file_handler_config,
-                )
-                .unwrap();
-                match points_to.file_location() {
+                // The target may not exist (e.g. a deleted file or a placeholder), so only its
+                // location is looked at
+                let points_to = file_paths.points_to().unwrap();
+                let points_to_location = if client_database::StorageDir::in_storage_dir(
+                    points_to,
+                    file_handler_config,
+                ) {
+                    client_database::FileLocation::StorageDir
+                } else if client_database::SymlinkDir::in_symlink_dir(
+                    points_to,
+                    file_handler_config,
+                ) {
+                    client_database::FileLocation::SymlinkDir
+                } else {
+                    client_database::FileLocation::OtherDir
+                };
+                match points_to_location {
                     client_database::FileLocation::SymlinkDir => {
                         // Case 1
                         symlink_cases::symlink_points_to_symlink_dir(
@@ -37,10 +61,13 @@ pub fn walk_symlink(
                     }
                     client_database::FileLocation::StorageDir => {
                         // Case 2
-                        symlink_cases::symlink_points_to_storage_dir(
-                            &file_paths,
-                            file_handler_config,
-                            change_counter,
+                        log_error(
+                            symlink_cases::symlink_points_to_storage_dir(
+                                &file_paths,
+                                file_handler_config,
+                                change_counter,
+                            ),
+                            file_paths.relative_path(),
                         );
                     }
                     client_database::FileLocation::OtherDir => {
@@ -51,14 +78,25 @@ pub fn walk_symlink(
             }
             (client_database::FileLocation::SymlinkDir, client_database::Type::File) => {
                 // Case 4
-                symlink_cases::real_file_exists(&file_paths, file_handler_config, change_counter);
+                log_error(
+                    symlink_cases::real_file_exists(
+                        &file_paths,
+                        file_handler_config,
+                        change_counter,
+                    ),
+                    file_paths.relative_path(),
+                );
             }
             (client_database::FileLocation::SymlinkDir, client_database::Type::Directory) => {
                 // Case 5
-                symlink_cases::directory_exists(&file_paths, change_counter);
+                log_error(
+                    symlink_cases::directory_exists(&file_paths, change_counter),
+                    file_paths.relative_path(),
+                );
                 walk_symlink(
                     file_paths.symlink_dir_path(),
                     file_handler_config,
+                    ignore_rules,
                     change_counter,
                 );
             }
@@ -8,6 +8,16 @@
 //! - [`file`] : `5 <= data_uid <= 9
 //! - [`symlink`] : `10 <= data_uid <= 11`
 //! - ['directory'] : `12 <= data_uid <= 15`
+//! - [`Snapshot`](struct@Snapshot) : `data_uid = 16`
+//! - [`ListFileVersions`](struct@ListFileVersions) : `data_uid = 17`
+//! - [`FileVersions`](struct@FileVersions) : `data_uid = 18`
+//! - [`RestoreFileVersion`](struct@RestoreFileVersion) : `data_uid = 19`
+//! - [`UploadedChange`](struct@UploadedChange) : `data_uid = 20`
+//! - [`Conflict`](struct@Conflict) : `data_uid = 21`
+//! - [`StorageUsage`](struct@StorageUsage) : `data_uid = 22`
+//! - [`MissingContent`](struct@MissingContent) : `data_uid = 23`
+//! - [`SharedFolder`](struct@SharedFolder) : `data_uid = 24`
+//! - [`AccessDenied`](struct@AccessDenied) : `data_uid = 25`
 
 /// The `Data` trait is used to specify handle data types sent between the client and server.
 /// Using serde traits means that a different datatype can be used. For example, the TCP runtime
@@ -15,25 +25,41 @@
 /// websocket were to be used, bytes cannot be sent, so instead, it can be serialized into a json.
 pub trait Data: serde::Serialize + serde::Deserialize<'static> {}
 
+mod conflict;
 mod directory;
 mod error;
 mod file;
+mod file_versions;
 mod greeting;
+mod missing_content;
 mod optimize_changes;
+mod rebase_changes;
 mod server_version;
+mod shared_folder;
+mod snapshot;
+mod storage_usage;
 mod symlink_data;
 mod sync_client_to_server;
 mod sync_server_to_client;
+mod uploaded_change;
 
+pub use conflict::{find_conflicts, Conflict};
 pub use directory::*;
 pub use error::{Error, ErrorType};
 pub use file::*;
+pub use file_versions::{FileVersion, FileVersions, ListFileVersions, RestoreFileVersion};
 pub use greeting::Greeting;
+pub use missing_content::MissingContent;
 pub use optimize_changes::{get_chains, make_optimizations, merge_changes, optimize_changes};
+pub use rebase_changes::rebase_changes;
 pub use server_version::ServerVersion;
+pub use shared_folder::{AccessDenied, Permission, SharedFolder};
+pub use snapshot::Snapshot;
+pub use storage_usage::StorageUsage;
 pub use symlink_data::*;
 pub use sync_client_to_server::SyncClientToServer;
 pub use sync_server_to_client::SyncServerToClient;
+pub use uploaded_change::UploadedChange;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub enum Transmission<E, D> {
@@ -47,6 +73,13 @@ pub enum Transmission<E, D> {
     TransactionComplete,
     SkipCurrent,
     ChangeEvent(ChangeEvent),
+    Snapshot(Snapshot),
+    ListFileVersions(ListFileVersions),
+    FileVersions(FileVersions),
+    RestoreFileVersion(RestoreFileVersion),
+    UploadedChange(UploadedChange),
+    StorageUsage(StorageUsage),
+    MissingContent(MissingContent),
     Other(D),
 }
 
@@ -78,6 +111,31 @@ pub enum ChangeEvent {
     Symlink(SymlinkEvent),
 }
 
+impl ChangeEvent {
+    /// Every path affected by the change. Moves affect both the old and the new path.
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            ChangeEvent::File(file) => match file {
+                FileEvent::Create(file_create) => vec![file_create.path()],
+                FileEvent::Modify(file_modify) => vec![file_modify.path()],
+                FileEvent::Move(file_move) => vec![file_move.from_path(), file_move.to_path()],
+                FileEvent::Delete(file_delete) => vec![file_delete.path()],
+                FileEvent::UndoDelete(file_undo_delete) => vec![file_undo_delete.path()],
+            },
+            ChangeEvent::Directory(dir) => match dir {
+                DirectoryEvent::Create(dir_create) => vec![dir_create.path()],
+                DirectoryEvent::Move(dir_move) => vec![dir_move.from_path(), dir_move.to_path()],
+                DirectoryEvent::Delete(dir_delete) => vec![dir_delete.path()],
+                DirectoryEvent::UndoDelete(dir_undo_delete) => vec![dir_undo_delete.path()],
+            },
+            ChangeEvent::Symlink(symlink) => match symlink {
+                SymlinkEvent::Create(symlink_create) => vec![symlink_create.path()],
+                SymlinkEvent::Delete(symlink_delete) => vec![symlink_delete.path()],
+            },
+        }
+    }
+}
+
 impl InnerEventTrait for ChangeEvent {
     fn inner_event(&self) -> InnerEvent {
         match self {
@@ -0,0 +1,100 @@
+use super::{ChangeEvent, Data};
+
+/// A change sent by the client that overlaps with a change made by another device since the
+/// client last synced. `path` is the path of the client's change and `change_event_id` and
+/// `change` identify the competing change on the server.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Conflict {
+    path: String,
+    change_event_id: i32,
+    change: ChangeEvent,
+}
+
+impl Conflict {
+    pub fn new(path: String, change_event_id: i32, change: ChangeEvent) -> Self {
+        Self {
+            path,
+            change_event_id,
+            change,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn change_event_id(&self) -> i32 {
+        self.change_event_id
+    }
+
+    pub fn change(&self) -> &ChangeEvent {
+        &self.change
+    }
+}
+
+impl Data for Conflict {}
+
+/// Whether the two paths are the same, or one of them is inside of the other.
+fn paths_overlap(a: &str, b: &str) -> bool {
+    let (shorter, longer) = match a.len() <= b.len() {
+        true => (a, b),
+        false => (b, a),
+    };
+    longer.starts_with(shorter)
+        && (longer.len() == shorter.len() || longer.as_bytes()[shorter.len()] == b'/')
+}
+
+/// Compares each of the client's `changes` against the changes made on the server since the
+/// client last synced (`server_changes`). Returns a [`Conflict`] for each client change that
+/// overlaps with a server change, naming the most recent competing server change.
+pub fn find_conflicts(
+    changes: &[ChangeEvent],
+    server_changes: &[(i32, ChangeEvent)],
+) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+    for change in changes {
+        let competing = server_changes.iter().rev().find_map(|(id, server_change)| {
+            change.paths().into_iter().find_map(|path| {
+                server_change
+                    .paths()
+                    .into_iter()
+                    .any(|server_path| paths_overlap(path, server_path))
+                    .then(|| Conflict::new(path.to_string(), *id, server_change.clone()))
+            })
+        });
+        if let Some(conflict) = competing {
+            conflicts.push(conflict);
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_conflicts, Conflict};
+    use crate::data;
+
+    #[test]
+    fn test_find_conflicts() {
+        let server_changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (4, data::FileModify::new(1, "a.txt".to_string()).into()),
+            (5, data::DirectoryDelete::new("dir".to_string()).into()),
+            (6, data::FileModify::new(2, "a.txt".to_string()).into()),
+        ];
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileModify::new(3, "a.txt".to_string()).into(),
+            data::FileCreate::new(0, "dir/b.txt".to_string()).into(),
+            data::FileCreate::new(0, "dir2/b.txt".to_string()).into(),
+            data::FileMove::new("c.txt".to_string(), "dir".to_string()).into(),
+        ];
+
+        assert_eq!(
+            find_conflicts(&changes, &server_changes),
+            vec![
+                Conflict::new("a.txt".to_string(), 6, server_changes[2].1.clone()),
+                Conflict::new("dir/b.txt".to_string(), 5, server_changes[1].1.clone()),
+                Conflict::new("dir".to_string(), 5, server_changes[1].1.clone()),
+            ]
+        );
+    }
+}
@@ -1,5 +1,3 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, DirectoryEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
@@ -20,15 +18,6 @@ impl DirectoryCreate {
 
 impl Data for DirectoryCreate {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for DirectoryCreate {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            path: row.get("path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for DirectoryCreate {
     fn into(self) -> ChangeEvent {
         ChangeEvent::Directory(DirectoryEvent::Create(self))
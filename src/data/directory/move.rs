@@ -1,5 +1,3 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, DirectoryEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
@@ -25,16 +23,6 @@ impl DirectoryMove {
 
 impl Data for DirectoryMove {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for DirectoryMove {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            from_path: row.get("from_path"),
-            to_path: row.get("to_path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for DirectoryMove {
     fn into(self) -> ChangeEvent {
         ChangeEvent::Directory(DirectoryEvent::Move(self))
@@ -1,7 +1,15 @@
-use super::Data;
+use super::{AccessDenied, Conflict, Data, StorageUsage};
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub enum ErrorType<T> {
+    /// A change sent by the client affects a path that another device changed after the
+    /// client's `client_version`.
+    Conflict(Conflict),
+    /// Applying the client's changes would use more than the quota of the namespace. Contains
+    /// the usage that the changes would have resulted in.
+    QuotaExceeded(StorageUsage),
+    /// The client's changes write to a path that the user may not write to.
+    AccessDenied(AccessDenied),
     Other(Option<T>),
 }
 
@@ -14,6 +22,10 @@ impl<T> Error<T> {
     pub fn new(error_type: ErrorType<T>) -> Self {
         Self { error_type }
     }
+
+    pub fn error_type(&self) -> &ErrorType<T> {
+        &self.error_type
+    }
 }
 
 impl<T> Data for Error<T> where T: Data {}
@@ -1,5 +1,3 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, FileEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
@@ -29,16 +27,6 @@ impl FileCreate {
 
 impl Data for FileCreate {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for FileCreate {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            size: 0,
-            path: row.get("path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for FileCreate {
     fn into(self) -> ChangeEvent {
         ChangeEvent::File(FileEvent::Create(self))
@@ -1,5 +1,3 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, FileEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
@@ -20,15 +18,6 @@ impl FileDelete {
 
 impl Data for FileDelete {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for FileDelete {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            path: row.get("path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for FileDelete {
     fn into(self) -> ChangeEvent {
         ChangeEvent::File(FileEvent::Delete(self))
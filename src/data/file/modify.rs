@@ -1,5 +1,3 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, FileEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
@@ -29,16 +27,6 @@ impl FileModify {
 
 impl Data for FileModify {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for FileModify {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            size: 0,
-            path: row.get("path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for FileModify {
     fn into(self) -> ChangeEvent {
         ChangeEvent::File(FileEvent::Modify(self))
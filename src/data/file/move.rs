@@ -1,5 +1,3 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, FileEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
@@ -25,16 +23,6 @@ impl FileMove {
 
 impl Data for FileMove {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for FileMove {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            from_path: row.get("from_path"),
-            to_path: row.get("to_path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for FileMove {
     fn into(self) -> ChangeEvent {
         ChangeEvent::File(FileEvent::Move(self))
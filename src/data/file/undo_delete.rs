@@ -1,16 +1,20 @@
-use sqlx::Row;
-
 use crate::data::{ChangeEvent, Data, FileEvent};
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct FileUndoDelete {
+    size: u64,
     path: String,
 }
 
 impl FileUndoDelete {
-    pub fn new(path: String) -> Self {
-        Self { path }
+    pub fn new(size: u64, path: String) -> Self {
+        Self { size, path }
+    }
+
+    /// Size of the restored file.
+    pub fn size(&self) -> u64 {
+        self.size
     }
 
     pub fn path(&self) -> &str {
@@ -20,15 +24,6 @@ impl FileUndoDelete {
 
 impl Data for FileUndoDelete {}
 
-#[cfg(feature = "server")]
-impl From<sqlx::postgres::PgRow> for FileUndoDelete {
-    fn from(row: sqlx::postgres::PgRow) -> Self {
-        Self {
-            path: row.get("path"),
-        }
-    }
-}
-
 impl Into<ChangeEvent> for FileUndoDelete {
     fn into(self) -> ChangeEvent {
         ChangeEvent::File(FileEvent::UndoDelete(self))
@@ -0,0 +1,105 @@
+use super::Data;
+
+/// A previous version of a file that is retained by the server.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct FileVersion {
+    path: String,
+    version: i32,
+    size: u64,
+    /// Seconds since the UNIX epoch at which the version was retained.
+    created_at: i64,
+}
+
+impl FileVersion {
+    pub fn new(path: String, version: i32, size: u64, created_at: i64) -> Self {
+        Self {
+            path,
+            version,
+            size,
+            created_at,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn created_at(&self) -> i64 {
+        self.created_at
+    }
+}
+
+impl Data for FileVersion {}
+
+/// Sent by the client to ask for the retained versions of a file.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ListFileVersions {
+    path: String,
+}
+
+impl ListFileVersions {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Data for ListFileVersions {}
+
+/// The server's response to [`ListFileVersions`]. Ordered from oldest to newest.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct FileVersions {
+    path: String,
+    versions: Vec<FileVersion>,
+}
+
+impl FileVersions {
+    pub fn new(path: String, versions: Vec<FileVersion>) -> Self {
+        Self { path, versions }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn versions(&self) -> &[FileVersion] {
+        &self.versions
+    }
+}
+
+impl Data for FileVersions {}
+
+/// Sent by the client to restore a retained version of a file. The server responds with the
+/// resulting `FileModify` change.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct RestoreFileVersion {
+    path: String,
+    version: i32,
+}
+
+impl RestoreFileVersion {
+    pub fn new(path: String, version: i32) -> Self {
+        Self { path, version }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+}
+
+impl Data for RestoreFileVersion {}
@@ -1,19 +1,28 @@
 use super::Data;
 
 /// Entry point for both the client and server. Used to determine whether the client and server are out of sync.
+/// The `device_name` is used by the server to keep track of how far each device has synced.
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct Greeting {
     version: String,
+    device_name: String,
 }
 
 impl Greeting {
-    pub fn new(version: String) -> Self {
-        Self { version }
+    pub fn new(version: String, device_name: String) -> Self {
+        Self {
+            version,
+            device_name,
+        }
     }
 
     pub fn version(&self) -> &str {
         &self.version
     }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
 }
 
 impl Data for Greeting {}
@@ -0,0 +1,20 @@
+use super::Data;
+
+/// Sent by the server to ask clients to upload the content of files that the change log says
+/// should exist, but that are missing from the server's storage directory.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct MissingContent {
+    paths: Vec<String>,
+}
+
+impl MissingContent {
+    pub fn new(paths: Vec<String>) -> Self {
+        Self { paths }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+}
+
+impl Data for MissingContent {}
@@ -19,7 +19,9 @@ fn get_chain_path(change: &data::ChangeEvent) -> (String, Option<String>, bool)
                 false,
             ),
             data::FileEvent::Delete(file_delete) => (file_delete.path().to_string(), None, true),
-            data::FileEvent::UndoDelete(_) => unimplemented!(),
+            data::FileEvent::UndoDelete(file_undo_delete) => {
+                (file_undo_delete.path().to_string(), None, false)
+            }
         },
         data::ChangeEvent::Directory(dir) => match dir {
             data::DirectoryEvent::Create(dir_create) => {
@@ -31,12 +33,64 @@ fn get_chain_path(change: &data::ChangeEvent) -> (String, Option<String>, bool)
                 false,
             ),
             data::DirectoryEvent::Delete(dir_delete) => (dir_delete.path().to_string(), None, true),
-            data::DirectoryEvent::UndoDelete(_) => unimplemented!(),
+            data::DirectoryEvent::UndoDelete(dir_undo_delete) => {
+                (dir_undo_delete.path().to_string(), None, false)
+            }
+        },
+        data::ChangeEvent::Symlink(symlink) => match symlink {
+            data::SymlinkEvent::Create(symlink_create) => {
+                (symlink_create.path().to_string(), None, false)
+            }
+            data::SymlinkEvent::Delete(symlink_delete) => {
+                (symlink_delete.path().to_string(), None, true)
+            }
         },
-        data::ChangeEvent::Symlink(_) => unimplemented!("symlinks not implemented"),
     }
 }
 
+/// The path of a directory move or delete, whose changes also affect everything inside of it.
+fn directory_path(change: &data::ChangeEvent) -> Option<&str> {
+    match change {
+        data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => {
+            Some(dir_move.from_path())
+        }
+        data::ChangeEvent::Directory(data::DirectoryEvent::Delete(dir_delete)) => {
+            Some(dir_delete.path())
+        }
+        _ => None,
+    }
+}
+
+/// Removes the most recent chain in `deleted_chains` that ends in a delete undone by
+/// `undo_delete`, so that the undo delete can continue it.
+fn take_deleted_chain(
+    deleted_chains: &mut LinkedList<LinkedList<(i32, ChangeEvent)>>,
+    undo_delete: &data::ChangeEvent,
+) -> Option<LinkedList<(i32, ChangeEvent)>> {
+    let (path, _, _) = get_chain_path(undo_delete);
+    let i = deleted_chains.iter().rposition(|chain| {
+        let (_, delete) = chain.back().unwrap();
+        std::mem::discriminant(delete) == std::mem::discriminant(undo_delete)
+            && get_chain_path(delete) == (path.clone(), None, true)
+    })?;
+    let mut tail = deleted_chains.split_off(i);
+    let chain = tail.pop_front();
+    deleted_chains.append(&mut tail);
+    chain
+}
+
+/// Splits `changes` into chains of changes to the same file, directory or symlink, following it
+/// through moves. Returns the chains by their last path, and the chains that no longer have a
+/// path: those that end in a delete, and those that were finished by a move or delete of a
+/// directory above them.
+///
+/// Chains are kept per path, so a directory move or delete does not carry along the chains of
+/// the paths inside of the directory. Instead, when there are any, the chains of the directory
+/// and of everything inside of it are finished before the move or delete, which then starts a
+/// new chain. Otherwise the optimized history could, for example, create a file before its
+/// parent directory exists.
+///
+/// An undo delete continues the chain of the delete that it undoes.
 pub fn get_chains(
     mut changes: LinkedList<(i32, ChangeEvent)>,
 ) -> (
@@ -52,6 +106,28 @@ pub fn get_chains(
             None => break,
         };
         let (existing_path, new_path, is_delete) = get_chain_path(&change.1);
+        if let Some(directory) = directory_path(&change.1) {
+            let prefix = format!("{}/", directory);
+            let inside = chains
+                .keys()
+                .filter(|path| path.starts_with(&prefix))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !inside.is_empty() {
+                for path in inside.iter().chain([&existing_path]) {
+                    if let Some(chain) = chains.remove(path) {
+                        deleted_chains.push_back(chain);
+                    }
+                }
+            }
+        }
+        if change.1.inner_event() == data::InnerEvent::UndoDelete
+            && !chains.contains_key(&existing_path)
+        {
+            if let Some(chain) = take_deleted_chain(&mut deleted_chains, &change.1) {
+                chains.insert(existing_path.clone(), chain);
+            }
+        }
         if let Some(chain) = chains.get_mut(&existing_path) {
             chain.push_back(change);
         } else {
@@ -93,6 +169,26 @@ fn get_first_modify_change(
     None
 }
 
+/// The size of the file after the last create, modify or undo delete in `chain`.
+fn last_size(chain: &LinkedList<(i32, ChangeEvent)>) -> u64 {
+    chain
+        .iter()
+        .rev()
+        .find_map(|(_, change)| match change {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                Some(file_create.size())
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                Some(file_modify.size())
+            }
+            data::ChangeEvent::File(data::FileEvent::UndoDelete(file_undo_delete)) => {
+                Some(file_undo_delete.size())
+            }
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
 fn move_to_from_of_change(change: &(i32, ChangeEvent)) -> (String, String) {
     match &change.1 {
         data::ChangeEvent::File(data::FileEvent::Move(file_move)) => (
@@ -129,10 +225,37 @@ fn path_of_modify_change(change: &(i32, ChangeEvent)) -> String {
 /// [4] Y -> D = D
 /// [5] Y_1 -> Y_k = Y_k
 ///
+/// Creates and modifies that replace others carry the size of the last create or modify.
+///
 /// [6] M_1 -> M_k = M_1_from -> M_k_to
 /// [7] M_1 -> D = D @ M_1_from
 /// [8] M -> Y = M -> Y
+///
+/// Undo delete: U
+///
+/// [9] U -> D = null
+/// [10] U -> X = U -> optimized(X)
+/// [11] D -> U = null, e.g. C -> D -> U = C
 pub fn make_optimizations(chain: &mut LinkedList<(i32, ChangeEvent)>) -> Vec<(i32, ChangeEvent)> {
+    // [11]
+    let mut remaining = LinkedList::new();
+    while let Some(change) = chain.pop_front() {
+        if change.1.inner_event() == data::InnerEvent::UndoDelete
+            && remaining
+                .back()
+                .map(|(_, delete): &(i32, ChangeEvent)| delete.inner_event())
+                == Some(data::InnerEvent::Delete)
+        {
+            remaining.pop_back();
+        } else {
+            remaining.push_back(change);
+        }
+    }
+    *chain = remaining;
+
+    if chain.is_empty() {
+        return vec![];
+    }
     if chain.len() == 1 {
         return vec![chain.front().unwrap().clone()];
     }
@@ -140,9 +263,31 @@ pub fn make_optimizations(chain: &mut LinkedList<(i32, ChangeEvent)>) -> Vec<(i3
     let is_dir = match chain.front().unwrap().1 {
         data::ChangeEvent::File(_) => false,
         data::ChangeEvent::Directory(_) => true,
-        data::ChangeEvent::Symlink(_) => unimplemented!("symlinks not implemented"),
+        data::ChangeEvent::Symlink(_) => {
+            // Symlinks can only be created and deleted, so the only optimization is C -> D = null
+            if chain.front().unwrap().1.inner_event() == data::InnerEvent::Create
+                && chain.back().unwrap().1.inner_event() == data::InnerEvent::Delete
+            {
+                return vec![];
+            }
+            return chain.iter().cloned().collect();
+        }
     };
 
+    if chain.front().unwrap().1.inner_event() == data::InnerEvent::UndoDelete {
+        if chain.back().unwrap().1.inner_event() == data::InnerEvent::Delete {
+            // [9] U -> D = null
+            return vec![];
+        }
+        // [10] An undo delete restores the original path, so it cannot be merged with the
+        // changes that follow it
+        let undo_delete = chain.pop_front().unwrap();
+        let mut optimized = vec![undo_delete.clone()];
+        optimized.extend(make_optimizations(chain));
+        chain.push_front(undo_delete);
+        return optimized;
+    }
+
     if chain.front().unwrap().1.inner_event() == data::InnerEvent::Create {
         if chain.back().unwrap().1.inner_event() == data::InnerEvent::Delete {
             // [2] C -> D = null
@@ -154,7 +299,8 @@ pub fn make_optimizations(chain: &mut LinkedList<(i32, ChangeEvent)>) -> Vec<(i3
 
             let optimized_create = match is_dir {
                 false => data::ChangeEvent::File(data::FileEvent::Create(data::FileCreate::new(
-                    0, move_to,
+                    last_size(chain),
+                    move_to,
                 ))),
                 true => data::ChangeEvent::Directory(data::DirectoryEvent::Create(
                     data::DirectoryCreate::new(move_to),
@@ -225,13 +371,30 @@ pub fn make_optimizations(chain: &mut LinkedList<(i32, ChangeEvent)>) -> Vec<(i3
             let modify_change = modify_change.unwrap();
             let move_change = move_change.unwrap();
 
-            let move_from = path_of_modify_change(&modify_change);
+            let move_from = match &chain.front().unwrap().1 {
+                data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                    file_move.from_path().to_string()
+                }
+                _ => path_of_modify_change(chain.front().unwrap()),
+            };
             let (_, move_to) = move_to_from_of_change(&move_change);
 
+            // The modify stays on the same side of the move, so that it is made to a path
+            // that exists at that point
+            let last_modify_id = chain
+                .iter()
+                .rev()
+                .find(|(_, change)| change.inner_event() == data::InnerEvent::Modify)
+                .unwrap()
+                .0;
+            let (modify_id, modify_path) = match last_modify_id > move_change.0 {
+                true => (last_modify_id, move_to.clone()),
+                false => (modify_change.0, move_from.clone()),
+            };
             let optimized_modify = match is_dir {
                 false => data::ChangeEvent::File(data::FileEvent::Modify(data::FileModify::new(
-                    0,
-                    move_to.clone(),
+                    last_size(chain),
+                    modify_path,
                 ))),
                 true => unreachable!(),
             };
@@ -246,11 +409,13 @@ pub fn make_optimizations(chain: &mut LinkedList<(i32, ChangeEvent)>) -> Vec<(i3
 
             return vec![
                 (move_change.0, optimized_move),
-                (modify_change.0, optimized_modify),
+                (modify_id, optimized_modify),
             ];
-        } else if move_change.is_none() && !modify_change.is_none() {
-            let modify_change = modify_change.unwrap();
-            return vec![modify_change];
+        } else if let (None, Some(modify_change)) = (&move_change, &modify_change) {
+            let optimized_modify = data::ChangeEvent::File(data::FileEvent::Modify(
+                data::FileModify::new(last_size(chain), path_of_modify_change(modify_change)),
+            ));
+            return vec![(modify_change.0, optimized_modify)];
         } else if !move_change.is_none() && modify_change.is_none() {
             let move_change = move_change.unwrap();
 
@@ -307,8 +472,6 @@ pub fn merge_changes(
                     min_change_event_id = change_event_id;
                     min_change_event_id_index = i;
                 }
-            } else {
-                break;
             }
         }
 
@@ -348,3 +511,216 @@ pub fn optimize_changes(changes: LinkedList<(i32, ChangeEvent)>) -> Vec<(i32, Ch
 
     merged
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::LinkedList;
+
+    use super::optimize_changes;
+    use crate::data;
+
+    fn optimize(changes: Vec<data::ChangeEvent>) -> Vec<(i32, data::ChangeEvent)> {
+        optimize_changes(
+            changes
+                .into_iter()
+                .enumerate()
+                .map(|(i, change)| (i as i32 + 1, change))
+                .collect::<LinkedList<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_optimize_sizes() {
+        assert_eq!(
+            optimize(vec![
+                data::FileCreate::new(3, "a".to_string()).into(),
+                data::FileModify::new(5, "a".to_string()).into(),
+                data::FileMove::new("a".to_string(), "b".to_string()).into(),
+            ]),
+            vec![(3, data::FileCreate::new(5, "b".to_string()).into())]
+        );
+        assert_eq!(
+            optimize(vec![
+                data::FileModify::new(5, "a".to_string()).into(),
+                data::FileMove::new("a".to_string(), "b".to_string()).into(),
+                data::FileModify::new(7, "b".to_string()).into(),
+            ]),
+            vec![
+                (
+                    2,
+                    data::FileMove::new("a".to_string(), "b".to_string()).into()
+                ),
+                (3, data::FileModify::new(7, "b".to_string()).into()),
+            ]
+        );
+        assert_eq!(
+            optimize(vec![
+                data::FileMove::new("a".to_string(), "b".to_string()).into(),
+                data::FileModify::new(5, "b".to_string()).into(),
+                data::FileMove::new("b".to_string(), "c".to_string()).into(),
+            ]),
+            vec![
+                (2, data::FileModify::new(5, "a".to_string()).into()),
+                (
+                    3,
+                    data::FileMove::new("a".to_string(), "c".to_string()).into()
+                ),
+            ]
+        );
+        assert_eq!(
+            optimize(vec![
+                data::FileModify::new(5, "a".to_string()).into(),
+                data::FileModify::new(7, "a".to_string()).into(),
+            ]),
+            vec![(1, data::FileModify::new(7, "a".to_string()).into())]
+        );
+    }
+
+    #[test]
+    fn test_optimize_directory_moves() {
+        // Nothing inside of the directory changed
+        assert_eq!(
+            optimize(vec![
+                data::DirectoryCreate::new("d".to_string()).into(),
+                data::DirectoryMove::new("d".to_string(), "e".to_string()).into(),
+                data::FileCreate::new(1, "e/a".to_string()).into(),
+            ]),
+            vec![
+                (2, data::DirectoryCreate::new("e".to_string()).into()),
+                (3, data::FileCreate::new(1, "e/a".to_string()).into()),
+            ]
+        );
+
+        // The contents of the directory are created before it is moved
+        assert_eq!(
+            optimize(vec![
+                data::DirectoryCreate::new("d".to_string()).into(),
+                data::FileCreate::new(1, "d/a".to_string()).into(),
+                data::FileModify::new(2, "d/a".to_string()).into(),
+                data::DirectoryMove::new("d".to_string(), "e".to_string()).into(),
+                data::FileModify::new(3, "e/a".to_string()).into(),
+                data::DirectoryMove::new("e".to_string(), "f".to_string()).into(),
+            ]),
+            vec![
+                (1, data::DirectoryCreate::new("d".to_string()).into()),
+                (2, data::FileCreate::new(1, "d/a".to_string()).into()),
+                (3, data::FileModify::new(2, "d/a".to_string()).into()),
+                (
+                    4,
+                    data::DirectoryMove::new("d".to_string(), "e".to_string()).into()
+                ),
+                (5, data::FileModify::new(3, "e/a".to_string()).into()),
+                (
+                    6,
+                    data::DirectoryMove::new("e".to_string(), "f".to_string()).into()
+                ),
+            ]
+        );
+
+        // A file moved out of the directory before it is deleted is kept
+        assert_eq!(
+            optimize(vec![
+                data::DirectoryCreate::new("d".to_string()).into(),
+                data::FileCreate::new(1, "d/a".to_string()).into(),
+                data::FileMove::new("d/a".to_string(), "a".to_string()).into(),
+                data::DirectoryDelete::new("d".to_string()).into(),
+            ]),
+            vec![(3, data::FileCreate::new(1, "a".to_string()).into())]
+        );
+    }
+
+    #[test]
+    fn test_optimize_symlinks() {
+        assert_eq!(
+            optimize(vec![
+                data::SymlinkCreate::new("s".to_string(), "a".to_string()).into(),
+                data::SymlinkDelete::new("s".to_string()).into(),
+                data::SymlinkCreate::new("s".to_string(), "b".to_string()).into(),
+            ]),
+            vec![(
+                3,
+                data::SymlinkCreate::new("s".to_string(), "b".to_string()).into()
+            )]
+        );
+        assert_eq!(
+            optimize(vec![
+                data::SymlinkDelete::new("s".to_string()).into(),
+                data::SymlinkCreate::new("s".to_string(), "b".to_string()).into(),
+            ]),
+            vec![
+                (1, data::SymlinkDelete::new("s".to_string()).into()),
+                (
+                    2,
+                    data::SymlinkCreate::new("s".to_string(), "b".to_string()).into()
+                ),
+            ]
+        );
+
+        // A symlink inside of a directory that is moved
+        assert_eq!(
+            optimize(vec![
+                data::DirectoryCreate::new("d".to_string()).into(),
+                data::SymlinkCreate::new("d/s".to_string(), "a".to_string()).into(),
+                data::DirectoryMove::new("d".to_string(), "e".to_string()).into(),
+                data::SymlinkDelete::new("e/s".to_string()).into(),
+            ]),
+            vec![
+                (1, data::DirectoryCreate::new("d".to_string()).into()),
+                (
+                    2,
+                    data::SymlinkCreate::new("d/s".to_string(), "a".to_string()).into()
+                ),
+                (
+                    3,
+                    data::DirectoryMove::new("d".to_string(), "e".to_string()).into()
+                ),
+                (4, data::SymlinkDelete::new("e/s".to_string()).into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_optimize_create_delete_undo_delete() {
+        assert_eq!(
+            optimize(vec![
+                data::FileCreate::new(3, "a".to_string()).into(),
+                data::FileDelete::new("a".to_string()).into(),
+                data::FileUndoDelete::new(0, "a".to_string()).into(),
+            ]),
+            vec![(1, data::FileCreate::new(3, "a".to_string()).into())]
+        );
+        assert_eq!(
+            optimize(vec![
+                data::FileCreate::new(3, "a".to_string()).into(),
+                data::FileDelete::new("a".to_string()).into(),
+                data::FileUndoDelete::new(0, "a".to_string()).into(),
+                data::FileModify::new(5, "a".to_string()).into(),
+                data::FileMove::new("a".to_string(), "b".to_string()).into(),
+            ]),
+            vec![(5, data::FileCreate::new(5, "b".to_string()).into())]
+        );
+
+        // The directory is restored together with its contents
+        assert_eq!(
+            optimize(vec![
+                data::DirectoryCreate::new("d".to_string()).into(),
+                data::FileCreate::new(1, "d/a".to_string()).into(),
+                data::DirectoryDelete::new("d".to_string()).into(),
+                data::DirectoryUndoDelete::new("d".to_string()).into(),
+            ]),
+            vec![
+                (1, data::DirectoryCreate::new("d".to_string()).into()),
+                (2, data::FileCreate::new(1, "d/a".to_string()).into()),
+            ]
+        );
+
+        // Undoing the delete of a file that was not created in this history
+        assert_eq!(
+            optimize(vec![
+                data::FileDelete::new("a".to_string()).into(),
+                data::FileUndoDelete::new(0, "a".to_string()).into(),
+            ]),
+            vec![]
+        );
+    }
+}
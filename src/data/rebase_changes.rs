@@ -0,0 +1,263 @@
+use super::{
+    ChangeEvent, DirectoryCreate, DirectoryDelete, DirectoryEvent, DirectoryMove,
+    DirectoryUndoDelete, FileCreate, FileDelete, FileEvent, FileModify, FileMove, FileUndoDelete,
+    SymlinkCreate, SymlinkDelete, SymlinkEvent,
+};
+
+/// If `path` is `from` or inside of it, the same path under `to`.
+fn rewrite_path(path: &str, from: &str, to: &str) -> Option<String> {
+    match path.strip_prefix(from) {
+        Some("") => Some(to.to_string()),
+        Some(rest) if rest.starts_with('/') => Some(format!("{}{}", to, rest)),
+        _ => None,
+    }
+}
+
+/// Whether `path` is `deleted` or inside of it.
+fn is_deleted(path: &str, deleted: &str) -> bool {
+    rewrite_path(path, deleted, deleted).is_some()
+}
+
+/// `change` with each of its paths passed through `rewrite`.
+fn map_paths(change: &ChangeEvent, rewrite: impl Fn(&str) -> String) -> ChangeEvent {
+    match change {
+        ChangeEvent::File(file) => match file {
+            FileEvent::Create(file_create) => {
+                FileCreate::new(file_create.size(), rewrite(file_create.path())).into()
+            }
+            FileEvent::Modify(file_modify) => {
+                FileModify::new(file_modify.size(), rewrite(file_modify.path())).into()
+            }
+            FileEvent::Move(file_move) => {
+                FileMove::new(rewrite(file_move.from_path()), rewrite(file_move.to_path())).into()
+            }
+            FileEvent::Delete(file_delete) => FileDelete::new(rewrite(file_delete.path())).into(),
+            FileEvent::UndoDelete(file_undo_delete) => {
+                FileUndoDelete::new(file_undo_delete.size(), rewrite(file_undo_delete.path()))
+                    .into()
+            }
+        },
+        ChangeEvent::Directory(dir) => match dir {
+            DirectoryEvent::Create(dir_create) => {
+                DirectoryCreate::new(rewrite(dir_create.path())).into()
+            }
+            DirectoryEvent::Move(dir_move) => {
+                DirectoryMove::new(rewrite(dir_move.from_path()), rewrite(dir_move.to_path()))
+                    .into()
+            }
+            DirectoryEvent::Delete(dir_delete) => {
+                DirectoryDelete::new(rewrite(dir_delete.path())).into()
+            }
+            DirectoryEvent::UndoDelete(dir_undo_delete) => {
+                DirectoryUndoDelete::new(rewrite(dir_undo_delete.path())).into()
+            }
+        },
+        ChangeEvent::Symlink(symlink) => match symlink {
+            SymlinkEvent::Create(symlink_create) => SymlinkCreate::new(
+                rewrite(symlink_create.path()),
+                symlink_create.links_to().to_string(),
+            )
+            .into(),
+            SymlinkEvent::Delete(symlink_delete) => {
+                SymlinkDelete::new(rewrite(symlink_delete.path())).into()
+            }
+        },
+    }
+}
+
+/// What remains of the local `change` once the server has deleted `deleted`. `None` if the change
+/// is obsolete.
+fn rebase_over_delete(change: ChangeEvent, deleted: &str) -> Option<ChangeEvent> {
+    match &change {
+        ChangeEvent::File(FileEvent::Modify(file_modify))
+            if is_deleted(file_modify.path(), deleted) =>
+        {
+            // The file only exists locally now
+            Some(FileCreate::new(file_modify.size(), file_modify.path().to_string()).into())
+        }
+        // Whatever is moved or created inside of a deleted directory is deleted along with it
+        ChangeEvent::File(FileEvent::Move(file_move))
+            if is_deleted(file_move.to_path(), deleted) =>
+        {
+            match is_deleted(file_move.from_path(), deleted) {
+                true => None,
+                false => Some(FileDelete::new(file_move.from_path().to_string()).into()),
+            }
+        }
+        ChangeEvent::File(FileEvent::Move(file_move))
+            if is_deleted(file_move.from_path(), deleted) =>
+        {
+            // The size is not known here, see `rebase_local_changes`
+            Some(FileCreate::new(0, file_move.to_path().to_string()).into())
+        }
+        ChangeEvent::Directory(DirectoryEvent::Move(dir_move))
+            if is_deleted(dir_move.to_path(), deleted) =>
+        {
+            match is_deleted(dir_move.from_path(), deleted) {
+                true => None,
+                false => Some(DirectoryDelete::new(dir_move.from_path().to_string()).into()),
+            }
+        }
+        ChangeEvent::Directory(DirectoryEvent::Move(dir_move))
+            if is_deleted(dir_move.from_path(), deleted) =>
+        {
+            Some(DirectoryCreate::new(dir_move.to_path().to_string()).into())
+        }
+        ChangeEvent::File(FileEvent::Create(file_create))
+            if is_deleted(file_create.path(), deleted) =>
+        {
+            None
+        }
+        ChangeEvent::Directory(DirectoryEvent::Create(dir_create))
+            if is_deleted(dir_create.path(), deleted) =>
+        {
+            None
+        }
+        ChangeEvent::Symlink(SymlinkEvent::Create(symlink_create))
+            if is_deleted(symlink_create.path(), deleted) =>
+        {
+            None
+        }
+        ChangeEvent::File(FileEvent::Delete(file_delete))
+            if is_deleted(file_delete.path(), deleted) =>
+        {
+            None
+        }
+        ChangeEvent::Directory(DirectoryEvent::Delete(dir_delete))
+            if is_deleted(dir_delete.path(), deleted) =>
+        {
+            None
+        }
+        ChangeEvent::Symlink(SymlinkEvent::Delete(symlink_delete))
+            if is_deleted(symlink_delete.path(), deleted) =>
+        {
+            None
+        }
+        _ => Some(change),
+    }
+}
+
+/// Rebases the client's pending `changes`, which were made against the tree at the last sync,
+/// over the `server_changes` made since then, so that they can be sent to the server:
+/// - Paths are rewritten through every [`FileMove`] and [`DirectoryMove`].
+/// - Changes made obsolete by a [`FileDelete`], [`DirectoryDelete`] or [`SymlinkDelete`] are
+///   dropped (e.g. deleting what was already deleted, or creating something inside of a deleted
+///   directory) or converted: a modified file or a file or directory moved out of what was
+///   deleted becomes a [`FileCreate`] or [`DirectoryCreate`], and a file or directory moved into
+///   a deleted directory becomes a [`FileDelete`] or [`DirectoryDelete`] of its old path. The
+///   size of a [`FileCreate`] converted from a move is 0, since a move does not carry it.
+///
+/// The order and change numbers of the remaining changes are kept.
+pub fn rebase_changes(
+    changes: Vec<(i32, ChangeEvent)>,
+    server_changes: &[(i32, ChangeEvent)],
+) -> Vec<(i32, ChangeEvent)> {
+    let mut changes = changes;
+    for (_, server_change) in server_changes {
+        changes = match server_change {
+            ChangeEvent::File(FileEvent::Move(file_move)) => changes
+                .into_iter()
+                .map(|(id, change)| {
+                    let change = map_paths(&change, |path| match path == file_move.from_path() {
+                        true => file_move.to_path().to_string(),
+                        false => path.to_string(),
+                    });
+                    (id, change)
+                })
+                .collect(),
+            ChangeEvent::Directory(DirectoryEvent::Move(dir_move)) => changes
+                .into_iter()
+                .map(|(id, change)| {
+                    let change = map_paths(&change, |path| {
+                        rewrite_path(path, dir_move.from_path(), dir_move.to_path())
+                            .unwrap_or_else(|| path.to_string())
+                    });
+                    (id, change)
+                })
+                .collect(),
+            ChangeEvent::File(FileEvent::Delete(_))
+            | ChangeEvent::Directory(DirectoryEvent::Delete(_))
+            | ChangeEvent::Symlink(SymlinkEvent::Delete(_)) => {
+                let deleted = server_change.paths()[0];
+                changes
+                    .into_iter()
+                    .filter_map(|(id, change)| Some((id, rebase_over_delete(change, deleted)?)))
+                    .collect()
+            }
+            _ => changes,
+        };
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rebase_changes;
+    use crate::data;
+
+    #[test]
+    fn test_rebase_changes() {
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::FileModify::new(0, "docs/a.txt".to_string()).into()),
+            (
+                2,
+                data::FileMove::new("docs/b.txt".to_string(), "b.txt".to_string()).into(),
+            ),
+            (3, data::FileDelete::new("old/c.txt".to_string()).into()),
+            (4, data::FileModify::new(0, "old/d.txt".to_string()).into()),
+            (
+                5,
+                data::FileCreate::new(0, "docs2/e.txt".to_string()).into(),
+            ),
+            (6, data::FileModify::new(0, "f.txt".to_string()).into()),
+            (
+                7,
+                data::FileMove::new("h.txt".to_string(), "old/h.txt".to_string()).into(),
+            ),
+            (8, data::FileCreate::new(3, "old/i.txt".to_string()).into()),
+            (
+                9,
+                data::DirectoryMove::new("dir".to_string(), "old/dir".to_string()).into(),
+            ),
+            (
+                10,
+                data::FileMove::new("old/j.txt".to_string(), "old/k.txt".to_string()).into(),
+            ),
+        ];
+        let server_changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (
+                10,
+                data::DirectoryMove::new("docs".to_string(), "documents".to_string()).into(),
+            ),
+            (11, data::DirectoryDelete::new("old".to_string()).into()),
+            (
+                12,
+                data::FileMove::new("f.txt".to_string(), "g.txt".to_string()).into(),
+            ),
+            (13, data::FileDelete::new("g.txt".to_string()).into()),
+        ];
+
+        assert_eq!(
+            rebase_changes(changes, &server_changes),
+            vec![
+                (
+                    1,
+                    data::FileModify::new(0, "documents/a.txt".to_string()).into()
+                ),
+                (
+                    2,
+                    data::FileMove::new("documents/b.txt".to_string(), "b.txt".to_string()).into()
+                ),
+                (4, data::FileCreate::new(0, "old/d.txt".to_string()).into()),
+                (
+                    5,
+                    data::FileCreate::new(0, "docs2/e.txt".to_string()).into()
+                ),
+                (6, data::FileCreate::new(0, "g.txt".to_string()).into()),
+                // Moved into the deleted directory
+                (7, data::FileDelete::new("h.txt".to_string()).into()),
+                (9, data::DirectoryDelete::new("dir".to_string()).into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use super::Data;
+
+/// What a member of a shared folder may do inside of it.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// A folder that is shared with the user, and what they may do inside of it.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct SharedFolder {
+    path: String,
+    permission: Permission,
+}
+
+impl SharedFolder {
+    pub fn new(path: String, permission: Permission) -> Self {
+        Self { path, permission }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn permission(&self) -> Permission {
+        self.permission
+    }
+}
+
+impl Data for SharedFolder {}
+
+/// A change sent by the client affects `path`, which the user is not allowed to write to.
+/// `shared_folder` is set if `path` is inside of a folder that is shared with the user as
+/// [`Permission::ReadOnly`].
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct AccessDenied {
+    path: String,
+    shared_folder: Option<SharedFolder>,
+}
+
+impl AccessDenied {
+    pub fn new(path: String, shared_folder: Option<SharedFolder>) -> Self {
+        Self {
+            path,
+            shared_folder,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn shared_folder(&self) -> Option<&SharedFolder> {
+        self.shared_folder.as_ref()
+    }
+}
+
+impl Data for AccessDenied {}
@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{ChangeEvent, Data, DirectoryEvent, FileEvent, SymlinkEvent};
+
+/// The full tree (i.e. every live file, directory and symlink) at `server_version`.
+/// Built by replaying changes in order with [`Snapshot::apply_change`].
+///
+/// Deleted files and directories are kept in a trash so that an undo delete can be replayed.
+/// The trash is only needed for replaying, see [`Snapshot::clear_trash`].
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    server_version: i32,
+    /// Path of each file and its size.
+    files: BTreeMap<String, u64>,
+    directories: BTreeSet<String>,
+    /// Path of each symlink and the path that it links to.
+    symlinks: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    trashed_files: BTreeMap<String, u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    trashed_directories: BTreeMap<String, Snapshot>,
+}
+
+/// Whether `path` is inside of the directory `dir` (at any depth).
+fn in_directory(path: &str, dir: &str) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/'
+}
+
+/// Moves every key inside of `from_dir` to the same relative location inside of `to_dir`.
+fn move_keys<V>(map: &mut BTreeMap<String, V>, from_dir: &str, to_dir: &str) {
+    let moved = map
+        .keys()
+        .filter(|k| in_directory(k, from_dir))
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in moved {
+        let value = map.remove(&key).unwrap();
+        map.insert(format!("{}{}", to_dir, &key[from_dir.len()..]), value);
+    }
+}
+
+impl Snapshot {
+    pub fn new(server_version: i32) -> Self {
+        Self {
+            server_version,
+            ..Default::default()
+        }
+    }
+
+    /// Replays `changes` (which must be ordered by `change_event_id`) on top of an empty tree.
+    pub fn from_changes(changes: &[(i32, ChangeEvent)]) -> Self {
+        let mut snapshot = Self::new(0);
+        for (change_event_id, change) in changes {
+            snapshot.apply_change(*change_event_id, change);
+        }
+        snapshot
+    }
+
+    pub fn apply_change(&mut self, change_event_id: i32, change: &ChangeEvent) {
+        match change {
+            ChangeEvent::File(file_event) => match file_event {
+                FileEvent::Create(file_create) => {
+                    self.files
+                        .insert(file_create.path().to_string(), file_create.size());
+                }
+                FileEvent::Modify(file_modify) => {
+                    self.files
+                        .insert(file_modify.path().to_string(), file_modify.size());
+                }
+                FileEvent::Move(file_move) => {
+                    let size = self.files.remove(file_move.from_path()).unwrap_or(0);
+                    self.files.insert(file_move.to_path().to_string(), size);
+                }
+                FileEvent::Delete(file_delete) => {
+                    if let Some(size) = self.files.remove(file_delete.path()) {
+                        self.trashed_files
+                            .insert(file_delete.path().to_string(), size);
+                    }
+                }
+                FileEvent::UndoDelete(file_undo_delete) => {
+                    let size = self
+                        .trashed_files
+                        .remove(file_undo_delete.path())
+                        .unwrap_or(file_undo_delete.size());
+                    self.files.insert(file_undo_delete.path().to_string(), size);
+                }
+            },
+            ChangeEvent::Directory(dir_event) => match dir_event {
+                DirectoryEvent::Create(dir_create) => {
+                    self.directories.insert(dir_create.path().to_string());
+                }
+                DirectoryEvent::Move(dir_move) => {
+                    let (from_dir, to_dir) = (dir_move.from_path(), dir_move.to_path());
+                    self.directories.remove(from_dir);
+                    self.directories.insert(to_dir.to_string());
+
+                    let directories = std::mem::take(&mut self.directories);
+                    self.directories = directories
+                        .into_iter()
+                        .map(|d| match in_directory(&d, from_dir) {
+                            true => format!("{}{}", to_dir, &d[from_dir.len()..]),
+                            false => d,
+                        })
+                        .collect();
+                    move_keys(&mut self.files, from_dir, to_dir);
+                    move_keys(&mut self.symlinks, from_dir, to_dir);
+                }
+                DirectoryEvent::Delete(dir_delete) => {
+                    let dir = dir_delete.path();
+                    let mut trashed = Snapshot::new(change_event_id);
+                    self.directories.retain(|d| match in_directory(d, dir) {
+                        true => {
+                            trashed.directories.insert(d.clone());
+                            false
+                        }
+                        false => d != dir,
+                    });
+                    self.files.retain(|f, size| match in_directory(f, dir) {
+                        true => {
+                            trashed.files.insert(f.clone(), *size);
+                            false
+                        }
+                        false => true,
+                    });
+                    self.symlinks
+                        .retain(|s, links_to| match in_directory(s, dir) {
+                            true => {
+                                trashed.symlinks.insert(s.clone(), links_to.clone());
+                                false
+                            }
+                            false => true,
+                        });
+                    self.trashed_directories.insert(dir.to_string(), trashed);
+                }
+                DirectoryEvent::UndoDelete(dir_undo_delete) => {
+                    let dir = dir_undo_delete.path();
+                    self.directories.insert(dir.to_string());
+                    if let Some(trashed) = self.trashed_directories.remove(dir) {
+                        self.directories.extend(trashed.directories);
+                        self.files.extend(trashed.files);
+                        self.symlinks.extend(trashed.symlinks);
+                    }
+                }
+            },
+            ChangeEvent::Symlink(symlink_event) => match symlink_event {
+                SymlinkEvent::Create(symlink_create) => {
+                    self.symlinks.insert(
+                        symlink_create.path().to_string(),
+                        symlink_create.links_to().to_string(),
+                    );
+                }
+                SymlinkEvent::Delete(symlink_delete) => {
+                    self.symlinks.remove(symlink_delete.path());
+                }
+            },
+        }
+        self.server_version = change_event_id;
+    }
+
+    pub fn server_version(&self) -> i32 {
+        self.server_version
+    }
+
+    pub fn set_server_version(&mut self, server_version: i32) {
+        self.server_version = server_version;
+    }
+
+    /// Drops deleted files and directories, which are not part of the tree. Undo deletes of
+    /// anything deleted before this can no longer be replayed.
+    pub fn clear_trash(&mut self) {
+        self.trashed_files.clear();
+        self.trashed_directories.clear();
+    }
+
+    pub fn files(&self) -> &BTreeMap<String, u64> {
+        &self.files
+    }
+
+    pub fn directories(&self) -> &BTreeSet<String> {
+        &self.directories
+    }
+
+    pub fn symlinks(&self) -> &BTreeMap<String, String> {
+        &self.symlinks
+    }
+}
+
+impl Data for Snapshot {}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use crate::data;
+
+    #[test]
+    fn test_replay_changes() {
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::DirectoryCreate::new("dir".to_string()).into()),
+            (2, data::DirectoryCreate::new("dir/sub".to_string()).into()),
+            (
+                3,
+                data::FileCreate::new(5, "dir/sub/a.txt".to_string()).into(),
+            ),
+            (4, data::FileCreate::new(7, "dir2.txt".to_string()).into()),
+            (5, data::FileModify::new(9, "dir2.txt".to_string()).into()),
+            (
+                6,
+                data::DirectoryMove::new("dir".to_string(), "moved".to_string()).into(),
+            ),
+            (
+                7,
+                data::SymlinkCreate::new("link".to_string(), "moved/sub/a.txt".to_string()).into(),
+            ),
+        ];
+        let snapshot = Snapshot::from_changes(&changes);
+
+        assert_eq!(snapshot.server_version(), 7);
+        assert_eq!(
+            snapshot.directories().iter().collect::<Vec<_>>(),
+            vec!["moved", "moved/sub"]
+        );
+        assert_eq!(snapshot.files().get("moved/sub/a.txt"), Some(&5));
+        assert_eq!(snapshot.files().get("dir2.txt"), Some(&9));
+        assert_eq!(snapshot.symlinks().len(), 1);
+    }
+
+    #[test]
+    fn test_replay_delete_and_undo_delete() {
+        let changes: Vec<(i32, data::ChangeEvent)> = vec![
+            (1, data::DirectoryCreate::new("dir".to_string()).into()),
+            (2, data::FileCreate::new(5, "dir/a.txt".to_string()).into()),
+            (3, data::FileCreate::new(6, "b.txt".to_string()).into()),
+            (4, data::DirectoryDelete::new("dir".to_string()).into()),
+            (5, data::FileDelete::new("b.txt".to_string()).into()),
+        ];
+        let mut snapshot = Snapshot::from_changes(&changes);
+        assert!(snapshot.files().is_empty());
+        assert!(snapshot.directories().is_empty());
+
+        snapshot.apply_change(6, &data::DirectoryUndoDelete::new("dir".to_string()).into());
+        snapshot.apply_change(7, &data::FileUndoDelete::new(0, "b.txt".to_string()).into());
+        assert_eq!(snapshot.files().get("dir/a.txt"), Some(&5));
+        assert_eq!(snapshot.files().get("b.txt"), Some(&6));
+        assert!(snapshot.directories().contains("dir"));
+    }
+}
@@ -0,0 +1,38 @@
+use super::Data;
+
+/// Number of bytes stored by the server for a namespace, and the namespace's quota (`None`
+/// means that there is no limit).
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct StorageUsage {
+    namespace: String,
+    used: u64,
+    quota: Option<u64>,
+}
+
+impl StorageUsage {
+    pub fn new(namespace: String, used: u64, quota: Option<u64>) -> Self {
+        Self {
+            namespace,
+            used,
+            quota,
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.quota.is_some_and(|quota| self.used > quota)
+    }
+}
+
+impl Data for StorageUsage {}
@@ -0,0 +1,29 @@
+use super::{ChangeEvent, Data};
+
+/// A change sent by the client, tagged with the local change number it was recorded under
+/// (see [`ChangeCounter`](crate::client_database::ChangeCounter)). Together with the device id,
+/// the local change number lets the server recognise a change that is sent more than once.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct UploadedChange {
+    local_change_id: i64,
+    change: ChangeEvent,
+}
+
+impl UploadedChange {
+    pub fn new(local_change_id: i64, change: ChangeEvent) -> Self {
+        Self {
+            local_change_id,
+            change,
+        }
+    }
+
+    pub fn local_change_id(&self) -> i64 {
+        self.local_change_id
+    }
+
+    pub fn change(&self) -> &ChangeEvent {
+        &self.change
+    }
+}
+
+impl Data for UploadedChange {}
@@ -51,6 +51,21 @@ pub mod server {
         async fn receive_payload(&mut self, payload: T)
             -> Result<bool, Box<dyn std::error::Error>>;
 
+        /// Must be called by the runtime when the client sends `TransactionComplete`, i.e. once it
+        /// has synced every change up to `server_version`. Moves the sync cursor of the device
+        /// forward (see [`acknowledge_server_version`](crate::server_database::acknowledge_server_version)),
+        /// which is what bounds the compaction of the change log.
+        #[cfg(feature = "server_database")]
+        async fn transaction_complete(
+            &mut self,
+            device_id: i32,
+            server_version: i32,
+            db_pool: &sqlx::PgPool,
+        ) -> Result<(), sqlx::Error> {
+            crate::server_database::acknowledge_server_version(device_id, server_version, db_pool)
+                .await
+        }
+
         // /// Here, the payload (i.e. serialize `T`) is sent with the data_uid. This would then be received
         // /// by the client in `receive_payload`.
         // async fn send_payload(&mut self) -> T;
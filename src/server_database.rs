@@ -1,33 +1,178 @@
+mod audit;
+mod compact_changes;
+mod conflicts;
+mod devices;
+mod file_versions;
+mod fsck;
 mod get_changes;
 mod initialize;
 mod insert_change;
+mod insert_changes;
+mod quotas;
 mod server_version;
+mod shared_folders;
+mod snapshot;
 mod table_details;
+mod trash;
 
+pub use audit::{get_audit_log, AuditEntry, AuditQuery, ChangeOrigin};
+pub use compact_changes::{compact_change_log, compact_changes, CompactionReport};
+pub use conflicts::{check_conflicts, insert_uploaded_changes_checked};
+pub use devices::{
+    acknowledge_server_version, get_device, get_devices, get_oldest_cursor, get_stale_devices,
+    register_device, Device,
+};
+pub use file_versions::{
+    apply_version_retention, insert_file_modify, list_file_versions, restore_file_version,
+    retain_file_version,
+};
+pub use fsck::{check_storage, fulfil_content_request, get_content_requests, FsckReport};
 pub use get_changes::get_changes;
 pub use initialize::initialize_db;
-pub use insert_change::insert_change;
+pub use insert_change::{insert_change, insert_change_from, insert_uploaded_change, InsertError};
+pub use insert_changes::{insert_changes, insert_changes_from, insert_uploaded_changes};
+pub use quotas::{check_quota, get_storage_usage, namespace_of};
 pub use server_version::get_server_version;
+pub use shared_folders::{
+    add_shared_folder_member, check_access, create_shared_folder, get_changes_for_namespace,
+    get_shared_folders, remove_shared_folder_member,
+};
+pub use snapshot::{create_snapshot_checkpoint, get_snapshot};
 pub use table_details::{TableDetails, TableDetailsTrait, TABLES};
+pub use trash::{
+    list_trash, move_to_trash, purge_expired_trash, restore_from_trash, TrashEntry, TrashError,
+};
 
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 use sqlx::ConnectOptions;
+use std::collections::BTreeMap;
 use std::path;
 use std::str::FromStr;
 
+/// Name of the directory, inside of the storage directory, where previous versions of files are
+/// kept.
+pub const VERSIONS_DIRECTORY: &str = ".hcs_versions";
+
+/// Name of the directory, inside of the storage directory, where deleted files and directories
+/// are kept until they expire.
+pub const TRASH_DIRECTORY: &str = ".hcs_trash";
+
+/// Name of the directory, inside of the storage directory, where files that are not part of the
+/// change log are moved by [`check_storage`].
+pub const QUARANTINE_DIRECTORY: &str = ".hcs_quarantine";
+
 #[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct ServerFileHandlerConfig {
     storage_directory: path::PathBuf,
+    #[serde(default)]
+    version_retention: VersionRetention,
+    /// Number of days that deleted content is kept in the trash. `None` keeps it forever.
+    #[serde(default)]
+    trash_expiry_days: Option<u32>,
+    #[serde(default)]
+    quotas: Quotas,
 }
 
 impl ServerFileHandlerConfig {
     pub fn new(storage_directory: path::PathBuf) -> Self {
-        Self { storage_directory }
+        Self {
+            storage_directory,
+            version_retention: VersionRetention::default(),
+            trash_expiry_days: None,
+            quotas: Quotas::default(),
+        }
     }
 
     pub fn storage_directory(&self) -> &path::Path {
         self.storage_directory.as_path()
     }
+
+    pub fn versions_directory(&self) -> path::PathBuf {
+        self.storage_directory.join(VERSIONS_DIRECTORY)
+    }
+
+    pub fn version_retention(&self) -> &VersionRetention {
+        &self.version_retention
+    }
+
+    pub fn set_version_retention(&mut self, version_retention: VersionRetention) {
+        self.version_retention = version_retention;
+    }
+
+    pub fn trash_directory(&self) -> path::PathBuf {
+        self.storage_directory.join(TRASH_DIRECTORY)
+    }
+
+    pub fn quarantine_directory(&self) -> path::PathBuf {
+        self.storage_directory.join(QUARANTINE_DIRECTORY)
+    }
+
+    pub fn trash_expiry_days(&self) -> Option<u32> {
+        self.trash_expiry_days
+    }
+
+    pub fn set_trash_expiry_days(&mut self, trash_expiry_days: Option<u32>) {
+        self.trash_expiry_days = trash_expiry_days;
+    }
+
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.quotas = quotas;
+    }
+}
+
+/// Maximum number of bytes that each namespace (i.e. top level directory of the storage
+/// directory, see [`namespace_of`]) may use. Namespaces without an entry in `namespaces` use
+/// `default_bytes`. `None` means that there is no limit.
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Quotas {
+    default_bytes: Option<u64>,
+    #[serde(default)]
+    namespaces: BTreeMap<String, u64>,
+}
+
+impl Quotas {
+    pub fn new(default_bytes: Option<u64>, namespaces: BTreeMap<String, u64>) -> Self {
+        Self {
+            default_bytes,
+            namespaces,
+        }
+    }
+
+    pub fn quota(&self, namespace: &str) -> Option<u64> {
+        self.namespaces
+            .get(namespace)
+            .copied()
+            .or(self.default_bytes)
+    }
+}
+
+/// How many previous versions of each file are kept. A version is removed once it exceeds
+/// either limit. `None` means that there is no limit.
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct VersionRetention {
+    max_versions: Option<u32>,
+    max_age_days: Option<u32>,
+}
+
+impl VersionRetention {
+    pub fn new(max_versions: Option<u32>, max_age_days: Option<u32>) -> Self {
+        Self {
+            max_versions,
+            max_age_days,
+        }
+    }
+
+    pub fn max_versions(&self) -> Option<u32> {
+        self.max_versions
+    }
+
+    pub fn max_age_days(&self) -> Option<u32> {
+        self.max_age_days
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::data;
+
+/// The device, and the remote address of its connection, that a change was received from.
+/// If the namespace of the user of the device is set, changes are only inserted when the
+/// namespace may write them (see [`check_access`](super::check_access)).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeOrigin {
+    device_id: i32,
+    remote_address: Option<String>,
+    namespace: Option<String>,
+}
+
+impl ChangeOrigin {
+    pub fn new(device_id: i32, remote_address: Option<String>) -> Self {
+        Self {
+            device_id,
+            remote_address,
+            namespace: None,
+        }
+    }
+
+    pub fn device_id(&self) -> i32 {
+        self.device_id
+    }
+
+    pub fn remote_address(&self) -> Option<&str> {
+        self.remote_address.as_deref()
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn set_namespace(&mut self, namespace: String) {
+        self.namespace = Some(namespace);
+    }
+}
+
+/// A change together with where it came from. `device_id`, `device_name` and `remote_address`
+/// are `None` for changes made by the server itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    change_event_id: i32,
+    event_time: DateTime<Utc>,
+    device_id: Option<i32>,
+    device_name: Option<String>,
+    remote_address: Option<String>,
+    change: data::ChangeEvent,
+}
+
+impl AuditEntry {
+    pub fn change_event_id(&self) -> i32 {
+        self.change_event_id
+    }
+
+    pub fn event_time(&self) -> &DateTime<Utc> {
+        &self.event_time
+    }
+
+    pub fn device_id(&self) -> Option<i32> {
+        self.device_id
+    }
+
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    pub fn remote_address(&self) -> Option<&str> {
+        self.remote_address.as_deref()
+    }
+
+    pub fn change(&self) -> &data::ChangeEvent {
+        &self.change
+    }
+}
+
+/// Filters for [`get_audit_log`]. Every filter that is set must match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditQuery {
+    path_prefix: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    device_id: Option<i32>,
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only changes to `path_prefix` or anything inside of it. A trailing `/` is ignored.
+    pub fn set_path_prefix(&mut self, path_prefix: String) {
+        self.path_prefix = Some(path_prefix);
+    }
+
+    /// Only changes made at or after `from` and before `to`.
+    pub fn set_time_range(&mut self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) {
+        self.from = from;
+        self.to = to;
+    }
+
+    pub fn set_device_id(&mut self, device_id: i32) {
+        self.device_id = Some(device_id);
+    }
+
+    /// The path prefix without trailing `/`s, `None` if it matches every path.
+    fn normalized_path_prefix(&self) -> Option<&str> {
+        self.path_prefix
+            .as_deref()
+            .map(|prefix| prefix.trim_end_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+    }
+}
+
+/// `LIKE` pattern that matches everything inside of the directory `prefix`.
+fn inside_pattern(prefix: &str) -> String {
+    let escaped = prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("{}/%", escaped)
+}
+
+/// Condition that matches the `change_events` whose paths are `$4` or inside of it (`$5`, see
+/// [`inside_pattern`]).
+fn path_condition() -> String {
+    let selects = super::TABLES
+        .iter()
+        .flat_map(|table| {
+            table
+                .columns()
+                .iter()
+                .filter(|column| column.is_path())
+                .map(move |column| {
+                    format!(
+                        "SELECT change_event_id FROM {table} WHERE {column} = $4 OR {column} LIKE $5",
+                        table = table.table_name(),
+                        column = column.name()
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "($4::TEXT IS NULL OR change_events.id IN ({}))",
+        selects.join(" UNION ")
+    )
+}
+
+/// Lists the changes that match `query`, ordered by `change_event_id`.
+pub async fn get_audit_log(
+    query: &AuditQuery,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    let path_prefix = query.normalized_path_prefix();
+    let sql = format!(
+        r#"
+        SELECT change_events.id, event_time, device_id, devices.name, remote_address
+        FROM change_events
+        LEFT JOIN devices ON devices.id = change_events.device_id
+        WHERE ($1::TIMESTAMPTZ IS NULL OR event_time >= $1)
+        AND ($2::TIMESTAMPTZ IS NULL OR event_time < $2)
+        AND ($3::INTEGER IS NULL OR device_id = $3)
+        AND {}
+        ORDER BY change_events.id
+        "#,
+        path_condition()
+    );
+    let rows = sqlx::query(&sql)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.device_id)
+        .bind(path_prefix)
+        .bind(path_prefix.map(inside_pattern))
+        .fetch_all(db_pool)
+        .await?;
+
+    let (first, last): (i32, i32) = match (rows.first(), rows.last()) {
+        (Some(first), Some(last)) => (first.get(0), last.get(0)),
+        _ => return Ok(vec![]),
+    };
+    let mut changes = super::get_changes(first - 1, last, db_pool)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let mut entries = vec![];
+    for row in rows {
+        let change_event_id: i32 = row.get(0);
+        let change = match changes.remove(&change_event_id) {
+            Some(change) => change,
+            None => continue,
+        };
+        entries.push(AuditEntry {
+            change_event_id,
+            event_time: row.get(1),
+            device_id: row.get(2),
+            device_name: row.get(3),
+            remote_address: row.get(4),
+            change,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_audit_log, AuditQuery, ChangeOrigin};
+    use crate::{
+        data,
+        server_database::{
+            insert_change, insert_change_from, register_device, ServerFileHandlerConfig,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_get_audit_log() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        let laptop = register_device("test_audit_laptop", &db_pool)
+            .await
+            .unwrap();
+        let desktop = register_device("test_audit_desktop", &db_pool)
+            .await
+            .unwrap();
+
+        let laptop_origin = ChangeOrigin::new(laptop, Some("192.168.1.20:5000".to_string()));
+        insert_change_from(
+            data::DirectoryCreate::new("tax".to_string()).into(),
+            &ChangeOrigin::new(desktop, None),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        insert_change_from(
+            data::FileCreate::new(0, "tax2.txt".to_string()).into(),
+            &laptop_origin,
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        let delete_id = insert_change_from(
+            data::DirectoryDelete::new("tax".to_string()).into(),
+            &laptop_origin,
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        insert_change(
+            data::DirectoryUndoDelete::new("tax".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        let mut query = AuditQuery::new();
+        query.set_path_prefix("tax".to_string());
+        let entries = get_audit_log(&query, &db_pool).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].device_id(), None);
+
+        // The prefix is a directory, not a pattern
+        insert_change(
+            data::FileCreate::new(0, "tax/a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        query.set_path_prefix("tax/".to_string());
+        assert_eq!(get_audit_log(&query, &db_pool).await.unwrap().len(), 4);
+        query.set_path_prefix("ta_".to_string());
+        assert!(get_audit_log(&query, &db_pool).await.unwrap().is_empty());
+        query.set_path_prefix("tax".to_string());
+
+        query.set_device_id(laptop);
+        let entries = get_audit_log(&query, &db_pool).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].change_event_id(), delete_id);
+        assert_eq!(entries[0].device_name(), Some("test_audit_laptop"));
+        assert_eq!(entries[0].remote_address(), Some("192.168.1.20:5000"));
+
+        let mut query = AuditQuery::new();
+        query.set_time_range(
+            None,
+            Some(*entries[0].event_time() - chrono::Duration::days(1)),
+        );
+        assert!(get_audit_log(&query, &db_pool).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::LinkedList;
+
+use crate::data::{self, InnerEventTrait};
+
+use super::TableDetailsTrait;
+
+/// Summary of a single compaction run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionReport {
+    cutoff: i32,
+    changes_before: usize,
+    changes_after: usize,
+}
+
+impl CompactionReport {
+    pub fn cutoff(&self) -> i32 {
+        self.cutoff
+    }
+
+    pub fn changes_before(&self) -> usize {
+        self.changes_before
+    }
+
+    pub fn changes_after(&self) -> usize {
+        self.changes_after
+    }
+}
+
+/// Compacts every change up to and including the oldest cursor of all registered devices.
+/// Returns `None` if there are no registered devices, as there is no safe cutoff.
+pub async fn compact_change_log(
+    db_pool: &sqlx::PgPool,
+) -> Result<Option<CompactionReport>, sqlx::Error> {
+    match super::get_oldest_cursor(db_pool).await? {
+        Some(cutoff) => Ok(Some(compact_changes(cutoff, db_pool).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Rewrites all changes with `change_event_id <= cutoff` into the minimal equivalent set of
+/// changes using [`data::optimize_changes`]. Optimized changes keep the `change_event_id` of
+/// one of the changes they replace, so the ordering of the change log is preserved and changes
+/// after `cutoff` are left untouched.
+///
+/// `cutoff` must not be greater than the cursor of any device (see
+/// [`get_oldest_cursor`](super::get_oldest_cursor)), otherwise that device would receive a
+/// history that does not apply on top of what it has already synced.
+///
+/// Snapshot checkpoints at or before `cutoff` no longer match the compacted history and are
+/// removed, so snapshots at or after `cutoff` are replayed from the compacted changes.
+///
+/// An undo delete is folded together with the delete it undoes. Compaction stops before an undo
+/// delete whose delete is not within the compacted changes, the cutoff that was used is returned
+/// in the report.
+pub async fn compact_changes(
+    cutoff: i32,
+    db_pool: &sqlx::PgPool,
+) -> Result<CompactionReport, sqlx::Error> {
+    let mut changes = super::get_changes(0, cutoff, db_pool).await?;
+    let cutoff = match first_unmatched_undo_delete(&changes) {
+        Some(i) => {
+            let cutoff = changes[i].0 - 1;
+            changes.truncate(i);
+            cutoff
+        }
+        None => cutoff,
+    };
+
+    let changes_before = changes.len();
+    let optimized = data::optimize_changes(changes.into_iter().collect::<LinkedList<_>>());
+    let kept_ids = optimized.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+    let mut transaction = db_pool.begin().await?;
+
+    for table in super::TABLES {
+        sqlx::query(
+            format!(
+                "DELETE FROM {} WHERE change_event_id <= $1",
+                table.table_name()
+            )
+            .as_str(),
+        )
+        .bind(cutoff)
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM snapshot_checkpoints WHERE server_version <= $1")
+        .bind(cutoff)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query("DELETE FROM change_events WHERE id <= $1 AND NOT (id = ANY($2))")
+        .bind(cutoff)
+        .bind(&kept_ids)
+        .execute(&mut transaction)
+        .await?;
+
+    for (change_id, change) in optimized.iter() {
+        sqlx::query("UPDATE change_events SET change_type_id = $2 WHERE id = $1")
+            .bind(change_id)
+            .bind(change.table_details().change_type_id())
+            .execute(&mut transaction)
+            .await?;
+
+        super::insert_change::insert_change_details(*change_id, change, &mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(CompactionReport {
+        cutoff,
+        changes_before,
+        changes_after: optimized.len(),
+    })
+}
+
+/// The index of the first undo delete in `changes` that does not undo an earlier delete in
+/// `changes`. Such an undo delete cannot be folded into the history before it.
+fn first_unmatched_undo_delete(changes: &[(i32, data::ChangeEvent)]) -> Option<usize> {
+    let mut deletes = vec![];
+    changes
+        .iter()
+        .position(|(_, change)| match change.inner_event() {
+            data::InnerEvent::Delete => {
+                deletes.push(change);
+                false
+            }
+            data::InnerEvent::UndoDelete => {
+                match deletes.iter().rposition(|delete| {
+                    std::mem::discriminant(*delete) == std::mem::discriminant(change)
+                        && delete.paths() == change.paths()
+                }) {
+                    Some(i) => {
+                        deletes.remove(i);
+                        false
+                    }
+                    None => true,
+                }
+            }
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::compact_changes;
+    use crate::{
+        data,
+        server_database::{
+            get_changes, get_server_version, insert_change, ServerFileHandlerConfig,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_compact_changes() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileCreate::new(0, "a.txt".to_string()).into(),
+            data::FileModify::new(0, "a.txt".to_string()).into(),
+            data::FileMove::new("a.txt".to_string(), "b.txt".to_string()).into(),
+            data::FileCreate::new(0, "c.txt".to_string()).into(),
+            data::FileDelete::new("c.txt".to_string()).into(),
+            data::FileModify::new(0, "b.txt".to_string()).into(),
+        ];
+        let mut ids = vec![];
+        for change in changes {
+            ids.push(insert_change(change, &config, &db_pool).await.unwrap());
+        }
+        let cutoff = ids[4];
+
+        let report = compact_changes(cutoff, &db_pool).await.unwrap();
+        assert_eq!(report.changes_before(), 5);
+        assert_eq!(report.changes_after(), 1);
+
+        let changes = get_changes(0, get_server_version(&db_pool).await.unwrap(), &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (ids[2], data::FileCreate::new(0, "b.txt".to_string()).into()),
+                (ids[5], data::FileModify::new(0, "b.txt".to_string()).into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact_changes_through_undo_deletes() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileCreate::new(3, "a.txt".to_string()).into(),
+            data::FileDelete::new("a.txt".to_string()).into(),
+            data::FileUndoDelete::new(3, "a.txt".to_string()).into(),
+            data::FileModify::new(5, "a.txt".to_string()).into(),
+            data::FileDelete::new("a.txt".to_string()).into(),
+        ];
+        let mut ids = vec![];
+        for change in changes {
+            ids.push(insert_change(change, &config, &db_pool).await.unwrap());
+        }
+
+        // The undo delete is compacted together with the delete that it undoes
+        let report = compact_changes(ids[3], &db_pool).await.unwrap();
+        assert_eq!(report.cutoff(), ids[3]);
+        assert_eq!(report.changes_before(), 4);
+        assert_eq!(report.changes_after(), 2);
+        assert_eq!(
+            compact_changes(ids[4], &db_pool)
+                .await
+                .unwrap()
+                .changes_after(),
+            0
+        );
+
+        // The delete of this undo delete has been compacted away, so compaction stops before it
+        let undo_delete = insert_change(
+            data::FileUndoDelete::new(5, "a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        let report = compact_changes(undo_delete, &db_pool).await.unwrap();
+        assert_eq!(report.cutoff(), undo_delete - 1);
+        assert_eq!(report.changes_before(), 0);
+        let changes = get_changes(0, get_server_version(&db_pool).await.unwrap(), &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![(
+                undo_delete,
+                data::FileUndoDelete::new(5, "a.txt".to_string()).into()
+            )]
+        );
+    }
+}
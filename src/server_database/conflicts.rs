@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+
+use sqlx::Row;
+
+use crate::data;
+
+use super::{ChangeOrigin, InsertError, ServerFileHandlerConfig};
+
+/// Checks the changes sent by the device `device_id` against every change made on the server by
+/// other devices (or the server itself) after `client_version` (see
+/// [`SyncClientToServer`](data::SyncClientToServer)). Returns the changes that conflict, which
+/// should be rejected with [`ErrorType::Conflict`](data::ErrorType::Conflict) rather than
+/// inserted.
+///
+/// Use [`insert_uploaded_changes_checked`] to insert the changes, so that no competing change
+/// can be inserted between the check and the insert.
+pub async fn check_conflicts(
+    client_version: i32,
+    device_id: i32,
+    changes: &[data::ChangeEvent],
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<data::Conflict>, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    check_conflicts_in(client_version, device_id, changes, &mut transaction).await
+}
+
+async fn check_conflicts_in(
+    client_version: i32,
+    device_id: i32,
+    changes: &[data::ChangeEvent],
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<Vec<data::Conflict>, sqlx::Error> {
+    // The device's own changes (e.g. uploaded by an earlier sync that it did not hear back from)
+    // cannot compete with it
+    let other_ids: HashSet<i32> =
+        sqlx::query("SELECT id FROM change_events WHERE id > $1 AND device_id IS DISTINCT FROM $2")
+            .bind(client_version)
+            .bind(device_id)
+            .map(|row: sqlx::postgres::PgRow| row.get(0))
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .collect();
+    let server_version = match other_ids.iter().max() {
+        Some(server_version) => *server_version,
+        None => return Ok(vec![]),
+    };
+
+    let mut server_changes =
+        super::get_changes::get_changes_in(client_version, server_version, &mut *transaction)
+            .await?;
+    server_changes.retain(|(id, _)| other_ids.contains(id));
+
+    Ok(data::find_conflicts(changes, &server_changes))
+}
+
+/// Checks `uploaded_changes` for conflicts (see [`check_conflicts`]) and inserts them (see
+/// [`insert_uploaded_changes`](super::insert_uploaded_changes)) in a single transaction. Other
+/// changes cannot be inserted until the transaction is finished. Fails with
+/// [`InsertError::Conflict`] if there are any conflicts, in which case nothing is inserted.
+pub async fn insert_uploaded_changes_checked(
+    client_version: i32,
+    origin: &ChangeOrigin,
+    uploaded_changes: &[data::UploadedChange],
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<i32>, InsertError> {
+    let mut copies = super::file_versions::VersionCopies::default();
+    let mut transaction = db_pool.begin().await?;
+    sqlx::query("LOCK TABLE change_events IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut transaction)
+        .await?;
+
+    let changes = uploaded_changes
+        .iter()
+        .map(|uploaded_change| uploaded_change.change().clone())
+        .collect::<Vec<_>>();
+    let conflicts = check_conflicts_in(
+        client_version,
+        origin.device_id(),
+        &changes,
+        &mut transaction,
+    )
+    .await?;
+    if !conflicts.is_empty() {
+        return Err(InsertError::Conflict(conflicts));
+    }
+
+    let change_ids = super::insert_changes::insert_uploaded_changes_in(
+        origin,
+        uploaded_changes,
+        config,
+        &mut copies,
+        &mut transaction,
+    )
+    .await?;
+    copies.commit(transaction, config, db_pool).await?;
+
+    Ok(change_ids)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_conflicts, insert_uploaded_changes_checked};
+    use crate::{
+        data,
+        server_database::{
+            get_changes, insert_change, insert_change_from, register_device, ChangeOrigin,
+            InsertError, ServerFileHandlerConfig,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_check_conflicts() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        let device_id = register_device("test_check_conflicts", &db_pool)
+            .await
+            .unwrap();
+
+        let client_version = insert_change(
+            data::FileCreate::new(0, "a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        let competing_change: data::ChangeEvent =
+            data::FileModify::new(0, "a.txt".to_string()).into();
+        let competing_id = insert_change(competing_change.clone(), &config, &db_pool)
+            .await
+            .unwrap();
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileModify::new(2, "a.txt".to_string()).into(),
+            data::FileCreate::new(0, "b.txt".to_string()).into(),
+        ];
+        let conflicts = check_conflicts(client_version, device_id, &changes, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            conflicts,
+            vec![data::Conflict::new(
+                "a.txt".to_string(),
+                competing_id,
+                competing_change
+            )]
+        );
+
+        let conflicts = check_conflicts(competing_id, device_id, &changes, &db_pool)
+            .await
+            .unwrap();
+        assert!(conflicts.is_empty());
+
+        // The device's own changes do not conflict with it
+        insert_change_from(
+            data::FileModify::new(1, "b.txt".to_string()).into(),
+            &ChangeOrigin::new(device_id, None),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        let conflicts = check_conflicts(competing_id, device_id, &changes, &db_pool)
+            .await
+            .unwrap();
+        assert!(conflicts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_insert_uploaded_changes_checked() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        let laptop = register_device("test_insert_checked_laptop", &db_pool)
+            .await
+            .unwrap();
+        let desktop = register_device("test_insert_checked_desktop", &db_pool)
+            .await
+            .unwrap();
+
+        let uploaded_changes = vec![data::UploadedChange::new(
+            1,
+            data::FileCreate::new(0, "a.txt".to_string()).into(),
+        )];
+        let change_ids = insert_uploaded_changes_checked(
+            0,
+            &ChangeOrigin::new(laptop, None),
+            &uploaded_changes,
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        let result = insert_uploaded_changes_checked(
+            0,
+            &ChangeOrigin::new(desktop, None),
+            &uploaded_changes,
+            &config,
+            &db_pool,
+        )
+        .await;
+        match result {
+            Err(InsertError::Conflict(conflicts)) => {
+                assert_eq!(conflicts.len(), 1);
+                assert_eq!(conflicts[0].change_event_id(), change_ids[0]);
+            }
+            _ => panic!("expected a conflict, got {:?}", result),
+        }
+        assert_eq!(get_changes(0, 100, &db_pool).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+/// A client that has connected to the server at least once.
+/// `server_version` is the last server version that the device has acknowledged (i.e. the
+/// `change_event_id` it is guaranteed to have synced up to).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    device_id: i32,
+    name: String,
+    last_seen: DateTime<Utc>,
+    server_version: i32,
+}
+
+impl Device {
+    pub fn device_id(&self) -> i32 {
+        self.device_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn last_seen(&self) -> &DateTime<Utc> {
+        &self.last_seen
+    }
+
+    pub fn server_version(&self) -> i32 {
+        self.server_version
+    }
+}
+
+impl From<sqlx::postgres::PgRow> for Device {
+    fn from(row: sqlx::postgres::PgRow) -> Self {
+        Self {
+            device_id: row.get("id"),
+            name: row.get("name"),
+            last_seen: row.get("last_seen"),
+            server_version: row.get("server_version"),
+        }
+    }
+}
+
+/// Registers a device by name, or updates `last_seen` if it already exists.
+/// Should be called when the server receives a `Greeting`. Returns the `device_id`.
+pub async fn register_device(name: &str, db_pool: &sqlx::PgPool) -> Result<i32, sqlx::Error> {
+    let device_id: i32 = sqlx::query(
+        r#"
+        INSERT INTO devices (name)
+        VALUES ($1)
+        ON CONFLICT (name) DO UPDATE SET last_seen = NOW()
+        RETURNING id
+        "#,
+    )
+    .bind(name)
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(device_id)
+}
+
+/// Moves the sync cursor of a device forward. Called once the client has sent
+/// `TransactionComplete` (see
+/// [`HCSProtocol::transaction_complete`](crate::protocol::server::HCSProtocol::transaction_complete)).
+/// A cursor never moves backwards.
+pub async fn acknowledge_server_version(
+    device_id: i32,
+    server_version: i32,
+    db_pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE devices
+        SET server_version = GREATEST(server_version, $2), last_seen = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(device_id)
+    .bind(server_version)
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_device(name: &str, db_pool: &sqlx::PgPool) -> Result<Option<Device>, sqlx::Error> {
+    let device = sqlx::query("SELECT * FROM devices WHERE name = $1")
+        .bind(name)
+        .map(Device::from)
+        .fetch_optional(db_pool)
+        .await?;
+
+    Ok(device)
+}
+
+pub async fn get_devices(db_pool: &sqlx::PgPool) -> Result<Vec<Device>, sqlx::Error> {
+    let devices = sqlx::query("SELECT * FROM devices ORDER BY id")
+        .map(Device::from)
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(devices)
+}
+
+/// Returns every device that has not been seen since `last_seen_before`.
+pub async fn get_stale_devices(
+    last_seen_before: DateTime<Utc>,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<Device>, sqlx::Error> {
+    let devices = sqlx::query("SELECT * FROM devices WHERE last_seen < $1 ORDER BY last_seen")
+        .bind(last_seen_before)
+        .map(Device::from)
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(devices)
+}
+
+/// The oldest `server_version` still in use by any device. Every change up to (and including)
+/// this version has been synced by all devices, so it is the safe lower bound for compacting
+/// the change log. Returns `None` if no devices have been registered.
+pub async fn get_oldest_cursor(db_pool: &sqlx::PgPool) -> Result<Option<i32>, sqlx::Error> {
+    let oldest: Option<i32> = sqlx::query("SELECT MIN(server_version) FROM devices")
+        .map(|row: sqlx::postgres::PgRow| row.get(0))
+        .fetch_one(db_pool)
+        .await?;
+
+    Ok(oldest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{acknowledge_server_version, get_device, get_stale_devices, register_device};
+    use crate::{data, protocol::server::HCSProtocol, testing_utils::clear_tables_and_get_pool};
+
+    #[tokio::test]
+    async fn test_register_device() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+
+        let device_id = register_device("test_register_device", &db_pool)
+            .await
+            .unwrap();
+        let same_device_id = register_device("test_register_device", &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(device_id, same_device_id);
+
+        acknowledge_server_version(device_id, 10, &db_pool)
+            .await
+            .unwrap();
+        acknowledge_server_version(device_id, 5, &db_pool)
+            .await
+            .unwrap();
+
+        let device = get_device("test_register_device", &db_pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(device.server_version(), 10);
+
+        let stale = get_stale_devices(*device.last_seen(), &db_pool)
+            .await
+            .unwrap();
+        assert!(stale.iter().all(|d| d.name() != "test_register_device"));
+    }
+
+    struct Runtime;
+
+    #[async_trait::async_trait]
+    impl HCSProtocol<data::Greeting> for Runtime {
+        async fn greet(&mut self, payload: data::Greeting) -> data::Greeting {
+            payload
+        }
+
+        async fn receive_payload(
+            &mut self,
+            _payload: data::Greeting,
+        ) -> Result<bool, Box<dyn std::error::Error>> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transaction_complete() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+
+        let device_id = register_device("test_transaction_complete", &db_pool)
+            .await
+            .unwrap();
+        Runtime
+            .transaction_complete(device_id, 7, &db_pool)
+            .await
+            .unwrap();
+
+        let device = get_device("test_transaction_complete", &db_pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(device.server_version(), 7);
+    }
+}
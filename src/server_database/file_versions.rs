@@ -0,0 +1,519 @@
+use std::{collections::HashSet, fs, path};
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::data;
+
+use super::{ChangeOrigin, InsertError, ServerFileHandlerConfig};
+
+fn row_to_file_version(row: sqlx::postgres::PgRow) -> data::FileVersion {
+    let size: i64 = row.get("size");
+    let created_at: DateTime<Utc> = row.get("created_at");
+    data::FileVersion::new(
+        row.get("path"),
+        row.get("version"),
+        size as u64,
+        created_at.timestamp(),
+    )
+}
+
+/// Versions are stored by their id rather than their path, so that they are not affected by
+/// later moves of the file.
+fn version_path(id: i32, config: &ServerFileHandlerConfig) -> path::PathBuf {
+    config.versions_directory().join(id.to_string())
+}
+
+/// The content copied for the versions inserted as part of a transaction. The copies are
+/// removed when this is dropped, unless the transaction was committed with [`commit`](Self::commit).
+#[derive(Default)]
+pub(crate) struct VersionCopies {
+    copies: Vec<path::PathBuf>,
+}
+
+impl VersionCopies {
+    /// Copies the current content of `path` and inserts it as a new version as part of
+    /// `transaction`. Returns `None` if the file does not exist in the storage directory.
+    async fn insert(
+        &mut self,
+        path: &str,
+        config: &ServerFileHandlerConfig,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Option<data::FileVersion>, sqlx::Error> {
+        let storage_path = config.storage_directory().join(path);
+        if !storage_path.is_file() {
+            return Ok(None);
+        }
+        let size = storage_path.metadata()?.len();
+
+        let row = sqlx::query(
+            r#"
+            INSERT INTO file_versions (path, version, size)
+            SELECT $1, COALESCE(MAX(version), 0) + 1, $2 FROM file_versions WHERE path = $1
+            RETURNING *
+            "#,
+        )
+        .bind(path)
+        .bind(size as i64)
+        .fetch_one(&mut *transaction)
+        .await?;
+        let copy = version_path(row.get("id"), config);
+
+        fs::create_dir_all(config.versions_directory())?;
+        self.copies.push(copy.clone());
+        fs::copy(&storage_path, &copy)?;
+
+        Ok(Some(row_to_file_version(row)))
+    }
+
+    /// Keeps the current content of every file modified by a `FileModify` in `changes` as a new
+    /// version, before the new content is written to the storage directory.
+    pub(crate) async fn retain(
+        &mut self,
+        changes: &[&data::ChangeEvent],
+        config: &ServerFileHandlerConfig,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        let mut retained = HashSet::new();
+        for change in changes {
+            if let data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) = change {
+                // The content is only replaced once, by the last of the modifies
+                if retained.insert(file_modify.path()) {
+                    self.insert(file_modify.path(), config, &mut *transaction)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits `transaction`, keeping the copies, and removes the versions that exceed the
+    /// configured [`VersionRetention`](super::VersionRetention).
+    pub(crate) async fn commit(
+        mut self,
+        transaction: sqlx::Transaction<'_, sqlx::Postgres>,
+        config: &ServerFileHandlerConfig,
+        db_pool: &sqlx::PgPool,
+    ) -> Result<(), sqlx::Error> {
+        transaction.commit().await?;
+        if !std::mem::take(&mut self.copies).is_empty() {
+            apply_version_retention(config, db_pool).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VersionCopies {
+    fn drop(&mut self) {
+        for copy in self.copies.iter() {
+            fs::remove_file(copy).ok();
+        }
+    }
+}
+
+/// Keeps the current content of `path` as a new version. Versions are retained when a
+/// `FileModify` is inserted, this is only needed to keep content that is replaced otherwise.
+/// Returns `None` if the file does not exist in the storage directory.
+pub async fn retain_file_version(
+    path: &str,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Option<data::FileVersion>, sqlx::Error> {
+    let mut copies = VersionCopies::default();
+    let mut transaction = db_pool.begin().await?;
+    let version = copies.insert(path, config, &mut transaction).await?;
+    copies.commit(transaction, config, db_pool).await?;
+    Ok(version)
+}
+
+/// Inserts `file_modify` (received from `origin`, if any), which keeps the current content of
+/// the file as a new version in the same transaction. Must be called before the new content is
+/// written to the storage directory. Same as [`insert_change`](super::insert_change) and
+/// [`insert_change_from`](super::insert_change_from).
+pub async fn insert_file_modify(
+    file_modify: data::FileModify,
+    origin: Option<&ChangeOrigin>,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<i32, InsertError> {
+    super::insert_change::insert_change_with_origin(file_modify.into(), origin, config, db_pool)
+        .await
+}
+
+/// Keeps the versions of the files moved by `change` (a `FileMove` or `DirectoryMove`)
+/// attached to their new path. Versions of a file that was previously at the new path are
+/// kept as older versions.
+pub(crate) async fn move_file_versions(
+    change: &data::ChangeEvent,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+    let (from_path, to_path) = match change {
+        data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+            (file_move.from_path(), file_move.to_path())
+        }
+        data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => {
+            (dir_move.from_path(), dir_move.to_path())
+        }
+        _ => return Ok(()),
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE file_versions AS moved
+        SET path = moved_to.path,
+            version = moved.version + COALESCE(
+                (SELECT MAX(version) FROM file_versions WHERE path = moved_to.path), 0
+            )
+        FROM (
+            SELECT id, $2 || SUBSTRING(path FROM LENGTH($1) + 1) AS path
+            FROM file_versions
+            WHERE path = $1 OR LEFT(path, LENGTH($1) + 1) = $1 || '/'
+        ) AS moved_to
+        WHERE moved.id = moved_to.id
+        "#,
+    )
+    .bind(from_path)
+    .bind(to_path)
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns every retained version of `path`, from oldest to newest.
+pub async fn list_file_versions(
+    path: &str,
+    db_pool: &sqlx::PgPool,
+) -> Result<data::FileVersions, sqlx::Error> {
+    let versions = sqlx::query("SELECT * FROM file_versions WHERE path = $1 ORDER BY version")
+        .bind(path)
+        .map(row_to_file_version)
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(data::FileVersions::new(path.to_string(), versions))
+}
+
+/// Replaces the content of `path` with a retained version. The content that is replaced is
+/// itself retained as a new version. Returns the resulting `FileModify` change, which should
+/// be sent to the clients. Fails with [`InsertError::QuotaExceeded`] as
+/// [`insert_change`](super::insert_change) does.
+///
+/// The storage directory is only changed once the change has been committed, so nothing is
+/// restored if any step fails.
+pub async fn restore_file_version(
+    path: &str,
+    version: i32,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<(i32, data::ChangeEvent), InsertError> {
+    let mut copies = VersionCopies::default();
+    let mut transaction = db_pool.begin().await?;
+
+    let (id, size): (i32, i64) =
+        sqlx::query("SELECT id, size FROM file_versions WHERE path = $1 AND version = $2")
+            .bind(path)
+            .bind(version)
+            .map(|row: sqlx::postgres::PgRow| (row.get("id"), row.get("size")))
+            .fetch_one(&mut transaction)
+            .await?;
+
+    let change: data::ChangeEvent = data::FileModify::new(size as u64, path.to_string()).into();
+    copies.retain(&[&change], config, &mut transaction).await?;
+
+    // Copied next to the versions, so that it can be renamed into place after the commit
+    let restored = config.versions_directory().join(format!("{}.restore", id));
+    fs::create_dir_all(config.versions_directory()).map_err(sqlx::Error::from)?;
+    copies.copies.push(restored.clone());
+    fs::copy(version_path(id, config), &restored).map_err(sqlx::Error::from)?;
+
+    let change_id = super::insert_change::insert_change_in(&change, None, &mut transaction).await?;
+    super::insert_change::enforce_quota(&[(change_id, &change)], config, &mut transaction).await?;
+    copies.commit(transaction, config, db_pool).await?;
+
+    let storage_path = config.storage_directory().join(path);
+    if let Some(parent) = storage_path.parent() {
+        fs::create_dir_all(parent).map_err(sqlx::Error::from)?;
+    }
+    fs::rename(&restored, &storage_path).map_err(sqlx::Error::from)?;
+
+    Ok((change_id, change))
+}
+
+/// Removes every version that exceeds the configured [`VersionRetention`](super::VersionRetention).
+/// Returns the number of versions that were removed.
+pub async fn apply_version_retention(
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<usize, sqlx::Error> {
+    let mut removed: Vec<i32> = vec![];
+
+    if let Some(max_versions) = config.version_retention().max_versions() {
+        let ids: Vec<i32> = sqlx::query(
+            r#"
+            DELETE FROM file_versions WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (PARTITION BY path ORDER BY version DESC) AS n
+                    FROM file_versions
+                ) AS ranked
+                WHERE n > $1
+            )
+            RETURNING id
+            "#,
+        )
+        .bind(max_versions as i64)
+        .map(|row: sqlx::postgres::PgRow| row.get(0))
+        .fetch_all(db_pool)
+        .await?;
+        removed.extend(ids);
+    }
+
+    if let Some(max_age_days) = config.version_retention().max_age_days() {
+        let ids: Vec<i32> = sqlx::query(
+            r#"
+            DELETE FROM file_versions WHERE created_at < NOW() - make_interval(days => $1)
+            RETURNING id
+            "#,
+        )
+        .bind(max_age_days as i32)
+        .map(|row: sqlx::postgres::PgRow| row.get(0))
+        .fetch_all(db_pool)
+        .await?;
+        removed.extend(ids);
+    }
+
+    for id in removed.iter() {
+        let path = version_path(*id, config);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(removed.len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, env, fs};
+
+    use super::{
+        insert_file_modify, list_file_versions, restore_file_version, retain_file_version,
+    };
+    use crate::{
+        data,
+        server_database::{
+            insert_change, insert_changes_from, insert_uploaded_change, register_device,
+            ChangeOrigin, InsertError, Quotas, ServerFileHandlerConfig, VersionRetention,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_file_versions() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        sqlx::query("TRUNCATE TABLE file_versions")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let storage_directory = env::current_dir().unwrap().join("_server_storage_dir");
+        fs::remove_dir_all(&storage_directory).ok();
+        fs::create_dir_all(&storage_directory).unwrap();
+        let mut config = ServerFileHandlerConfig::new(storage_directory.clone());
+        config.set_version_retention(VersionRetention::new(Some(2), None));
+
+        let file = storage_directory.join("file.txt");
+        assert!(retain_file_version("file.txt", &config, &db_pool)
+            .await
+            .unwrap()
+            .is_none());
+        for content in ["first", "second"] {
+            fs::write(&file, content).unwrap();
+            retain_file_version("file.txt", &config, &db_pool)
+                .await
+                .unwrap();
+        }
+        fs::write(&file, "third").unwrap();
+
+        let versions = list_file_versions("file.txt", &db_pool).await.unwrap();
+        assert_eq!(versions.versions().len(), 2);
+        assert_eq!(versions.versions()[0].version(), 1);
+        assert_eq!(versions.versions()[0].size(), 5);
+
+        let (_, change) = restore_file_version("file.txt", 1, &config, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+        assert_eq!(
+            change,
+            crate::data::FileModify::new(5, "file.txt".to_string()).into()
+        );
+
+        let versions = list_file_versions("file.txt", &db_pool).await.unwrap();
+        assert_eq!(
+            versions
+                .versions()
+                .iter()
+                .map(|v| v.version())
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        fs::remove_dir_all(&storage_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_versions_follow_moves() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        sqlx::query("TRUNCATE TABLE file_versions")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let storage_directory = env::current_dir().unwrap().join("_server_storage_dir");
+        fs::remove_dir_all(&storage_directory).ok();
+        fs::create_dir_all(storage_directory.join("dir")).unwrap();
+        let config = ServerFileHandlerConfig::new(storage_directory.clone());
+
+        // The versions are retained when the modify is inserted
+        fs::write(storage_directory.join("dir/a.txt"), "first").unwrap();
+        insert_file_modify(
+            data::FileModify::new(6, "dir/a.txt".to_string()),
+            None,
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        fs::write(storage_directory.join("dir/a.txt"), "second").unwrap();
+        fs::write(storage_directory.join("b.txt"), "other").unwrap();
+        retain_file_version("b.txt", &config, &db_pool)
+            .await
+            .unwrap();
+
+        insert_change(
+            data::FileMove::new("dir/a.txt".to_string(), "dir/b.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        insert_change(
+            data::DirectoryMove::new("dir".to_string(), "moved".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        assert!(list_file_versions("dir/a.txt", &db_pool)
+            .await
+            .unwrap()
+            .versions()
+            .is_empty());
+        let versions = list_file_versions("moved/b.txt", &db_pool).await.unwrap();
+        assert_eq!(versions.versions().len(), 1);
+        assert_eq!(versions.versions()[0].size(), 5);
+
+        // Versions of a file that was deleted before another one was moved to its path are kept
+        insert_change(
+            data::FileMove::new("moved/b.txt".to_string(), "b.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        let versions = list_file_versions("b.txt", &db_pool).await.unwrap();
+        assert_eq!(
+            versions
+                .versions()
+                .iter()
+                .map(|v| v.version())
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        fs::remove_dir_all(&storage_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_versions_of_inserted_modifies() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        sqlx::query("TRUNCATE TABLE file_versions")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let storage_directory = env::current_dir().unwrap().join("_server_storage_dir");
+        fs::remove_dir_all(&storage_directory).ok();
+        fs::create_dir_all(storage_directory.join("alice")).unwrap();
+        let mut config = ServerFileHandlerConfig::new(storage_directory.clone());
+        let device_id = register_device("test_file_versions_of_inserted_modifies", &db_pool)
+            .await
+            .unwrap();
+        let origin = ChangeOrigin::new(device_id, None);
+
+        let file = storage_directory.join("alice/a.txt");
+        for change in [
+            data::FileCreate::new(5, "alice/a.txt".to_string()),
+            data::FileCreate::new(3, "alice/b.txt".to_string()),
+        ] {
+            insert_change(change.into(), &config, &db_pool)
+                .await
+                .unwrap();
+        }
+        fs::write(&file, "first").unwrap();
+
+        // Uploaded modifies keep the content that they replace
+        insert_uploaded_change(
+            &origin,
+            &data::UploadedChange::new(
+                1,
+                data::FileModify::new(3, "alice/a.txt".to_string()).into(),
+            ),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        fs::write(&file, "2nd").unwrap();
+
+        // The content is retained once for modifies of the same file in a batch
+        let modifies: Vec<data::ChangeEvent> = vec![
+            data::FileModify::new(3, "alice/a.txt".to_string()).into(),
+            data::FileModify::new(3, "alice/a.txt".to_string()).into(),
+        ];
+        insert_changes_from(&modifies, &origin, &config, &db_pool)
+            .await
+            .unwrap();
+        fs::write(&file, "3rd").unwrap();
+
+        let versions = list_file_versions("alice/a.txt", &db_pool).await.unwrap();
+        assert_eq!(
+            versions
+                .versions()
+                .iter()
+                .map(|v| (v.version(), v.size()))
+                .collect::<Vec<_>>(),
+            vec![(1, 5), (2, 3)]
+        );
+
+        // Restoring a version is rejected if it takes the namespace over its quota
+        config.set_quotas(Quotas::new(
+            None,
+            BTreeMap::from([("alice".to_string(), 7)]),
+        ));
+        let result = restore_file_version("alice/a.txt", 1, &config, &db_pool).await;
+        assert!(matches!(result, Err(InsertError::QuotaExceeded(_))));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "3rd");
+        assert_eq!(
+            list_file_versions("alice/a.txt", &db_pool)
+                .await
+                .unwrap()
+                .versions()
+                .len(),
+            2
+        );
+
+        fs::remove_dir_all(&storage_directory).unwrap();
+    }
+}
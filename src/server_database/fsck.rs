@@ -0,0 +1,296 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, path,
+};
+
+use sqlx::Row;
+
+use crate::data;
+
+use super::{ServerFileHandlerConfig, QUARANTINE_DIRECTORY, TRASH_DIRECTORY, VERSIONS_DIRECTORY};
+
+/// Result of comparing the storage directory against the tree computed from the change log.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FsckReport {
+    server_version: i32,
+    /// Files and directories that should exist but do not.
+    missing: Vec<String>,
+    /// Files and directories that exist but are not part of the tree.
+    orphaned: Vec<String>,
+    /// Path of each file whose size does not match, with the expected and actual size.
+    size_mismatched: Vec<(String, u64, u64)>,
+    /// Orphaned files and directories that were moved to the quarantine directory.
+    quarantined: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn server_version(&self) -> i32 {
+        self.server_version
+    }
+
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    pub fn orphaned(&self) -> &[String] {
+        &self.orphaned
+    }
+
+    pub fn size_mismatched(&self) -> &[(String, u64, u64)] {
+        &self.size_mismatched
+    }
+
+    pub fn quarantined(&self) -> &[String] {
+        &self.quarantined
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty() && self.size_mismatched.is_empty()
+    }
+}
+
+/// Everything found in the storage directory, with paths relative to it.
+#[derive(Default)]
+struct StoredTree {
+    files: BTreeMap<String, u64>,
+    directories: BTreeSet<String>,
+    symlinks: BTreeSet<String>,
+}
+
+/// Walks `dir`, skipping the directories used by the server itself ([`VERSIONS_DIRECTORY`],
+/// [`TRASH_DIRECTORY`] and [`QUARANTINE_DIRECTORY`]) at the top level of the storage directory.
+fn walk_storage_dir(
+    dir: &path::Path,
+    relative_dir: &str,
+    tree: &mut StoredTree,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if relative_dir.is_empty()
+            && [VERSIONS_DIRECTORY, TRASH_DIRECTORY, QUARANTINE_DIRECTORY].contains(&name.as_str())
+        {
+            continue;
+        }
+        let relative_path = match relative_dir.is_empty() {
+            true => name,
+            false => format!("{}/{}", relative_dir, name),
+        };
+
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            tree.symlinks.insert(relative_path);
+        } else if file_type.is_dir() {
+            walk_storage_dir(&entry.path(), &relative_path, tree)?;
+            tree.directories.insert(relative_path);
+        } else {
+            tree.files.insert(relative_path, entry.metadata()?.len());
+        }
+    }
+    Ok(())
+}
+
+/// Every directory that contains `path` (at any depth).
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+/// Compares the storage directory against the tree at the current server version (see
+/// [`get_snapshot`](super::get_snapshot)) and reports missing, orphaned and size-mismatched
+/// files.
+///
+/// With `repair`, orphaned files and directories are moved into the quarantine directory,
+/// missing directories are recreated and missing files are added to the content requests (see
+/// [`get_content_requests`]), so that clients can upload them again. Size mismatches and
+/// missing symlinks are only reported.
+pub async fn check_storage(
+    config: &ServerFileHandlerConfig,
+    repair: bool,
+    db_pool: &sqlx::PgPool,
+) -> Result<FsckReport, sqlx::Error> {
+    let server_version = super::get_server_version(db_pool).await?;
+    let snapshot = super::get_snapshot(server_version, db_pool).await?;
+
+    let mut stored = StoredTree::default();
+    walk_storage_dir(config.storage_directory(), "", &mut stored)?;
+
+    let mut report = FsckReport {
+        server_version,
+        ..Default::default()
+    };
+
+    // Directories that must exist, including the parents of files that were created without
+    // a `DirectoryCreate`
+    let expected_directories = snapshot
+        .directories()
+        .iter()
+        .map(|d| d.as_str())
+        .chain(snapshot.files().keys().flat_map(|f| ancestors(f)))
+        .chain(snapshot.symlinks().keys().flat_map(|s| ancestors(s)))
+        .collect::<BTreeSet<_>>();
+
+    for directory in snapshot.directories() {
+        if !stored.directories.contains(directory) {
+            report.missing.push(directory.clone());
+        }
+    }
+    for (file, size) in snapshot.files() {
+        match stored.files.get(file) {
+            Some(stored_size) if stored_size != size => {
+                report
+                    .size_mismatched
+                    .push((file.clone(), *size, *stored_size));
+            }
+            Some(_) => {}
+            None => report.missing.push(file.clone()),
+        }
+    }
+    for symlink in snapshot.symlinks().keys() {
+        if !stored.symlinks.contains(symlink) {
+            report.missing.push(symlink.clone());
+        }
+    }
+
+    // Anything inside of an orphaned directory is covered by the directory itself
+    let in_expected_directory =
+        |path: &str| ancestors(path).all(|d| expected_directories.contains(d));
+    for directory in stored.directories.iter() {
+        if !expected_directories.contains(directory.as_str()) && in_expected_directory(directory) {
+            report.orphaned.push(directory.clone());
+        }
+    }
+    for file in stored.files.keys() {
+        if !snapshot.files().contains_key(file) && in_expected_directory(file) {
+            report.orphaned.push(file.clone());
+        }
+    }
+    for symlink in stored.symlinks.iter() {
+        if !snapshot.symlinks().contains_key(symlink) && in_expected_directory(symlink) {
+            report.orphaned.push(symlink.clone());
+        }
+    }
+    report.missing.sort();
+    report.orphaned.sort();
+
+    if repair {
+        let quarantine_directory = config
+            .quarantine_directory()
+            .join(server_version.to_string());
+        for orphan in report.orphaned.iter() {
+            let from = config.storage_directory().join(orphan);
+            let to = quarantine_directory.join(orphan);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(from, to)?;
+            report.quarantined.push(orphan.clone());
+        }
+
+        for missing in report.missing.iter() {
+            if snapshot.symlinks().contains_key(missing) {
+                continue;
+            }
+            if !snapshot.files().contains_key(missing) {
+                fs::create_dir_all(config.storage_directory().join(missing))?;
+                continue;
+            }
+            sqlx::query("INSERT INTO content_requests (path) VALUES ($1) ON CONFLICT DO NOTHING")
+                .bind(missing)
+                .execute(db_pool)
+                .await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns the files whose content should be uploaded again by the clients.
+pub async fn get_content_requests(
+    db_pool: &sqlx::PgPool,
+) -> Result<data::MissingContent, sqlx::Error> {
+    let paths = sqlx::query("SELECT path FROM content_requests ORDER BY path")
+        .map(|row: sqlx::postgres::PgRow| row.get(0))
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(data::MissingContent::new(paths))
+}
+
+/// Should be called once a client has uploaded the content of `path`.
+pub async fn fulfil_content_request(path: &str, db_pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM content_requests WHERE path = $1")
+        .bind(path)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{check_storage, get_content_requests};
+    use crate::{
+        data,
+        server_database::{insert_changes, ServerFileHandlerConfig},
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_check_storage() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        sqlx::query("TRUNCATE TABLE content_requests")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let storage_directory = env::current_dir().unwrap().join("_server_storage_dir");
+        fs::remove_dir_all(&storage_directory).ok();
+        let config = ServerFileHandlerConfig::new(storage_directory.clone());
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::DirectoryCreate::new("dir".to_string()).into(),
+            data::FileCreate::new(1, "dir/a.txt".to_string()).into(),
+            data::FileCreate::new(1, "b.txt".to_string()).into(),
+            data::FileCreate::new(1, "missing.txt".to_string()).into(),
+            data::SymlinkCreate::new("missing_link".to_string(), "b.txt".to_string()).into(),
+        ];
+        insert_changes(&changes, &config, &db_pool).await.unwrap();
+
+        fs::create_dir_all(storage_directory.join("dir")).unwrap();
+        fs::create_dir_all(storage_directory.join("orphan_dir/sub")).unwrap();
+        fs::create_dir_all(config.trash_directory()).unwrap();
+        fs::create_dir_all(storage_directory.join(".hcs_other")).unwrap();
+        fs::write(storage_directory.join("dir/a.txt"), "a").unwrap();
+        fs::write(storage_directory.join("b.txt"), "bb").unwrap();
+        fs::write(storage_directory.join("orphan.txt"), "o").unwrap();
+        fs::write(storage_directory.join("orphan_dir/sub/c.txt"), "c").unwrap();
+
+        let report = check_storage(&config, false, &db_pool).await.unwrap();
+        assert!(!report.is_consistent());
+        assert_eq!(report.missing(), ["missing.txt", "missing_link"]);
+        assert_eq!(
+            report.orphaned(),
+            [".hcs_other", "orphan.txt", "orphan_dir"]
+        );
+        assert_eq!(report.size_mismatched(), [("b.txt".to_string(), 1, 2)]);
+
+        let report = check_storage(&config, true, &db_pool).await.unwrap();
+        assert_eq!(
+            report.quarantined(),
+            [".hcs_other", "orphan.txt", "orphan_dir"]
+        );
+        assert!(!storage_directory.join("missing_link").exists());
+        assert!(!storage_directory.join("orphan_dir").exists());
+        assert_eq!(
+            get_content_requests(&db_pool).await.unwrap(),
+            data::MissingContent::new(vec!["missing.txt".to_string()])
+        );
+
+        let report = check_storage(&config, false, &db_pool).await.unwrap();
+        assert!(report.orphaned().is_empty());
+
+        fs::remove_dir_all(&storage_directory).unwrap();
+    }
+}
@@ -8,73 +8,22 @@ fn row_to_change_event(
 ) -> (i32, data::ChangeEvent) {
     let change_event_id: i32 = row.get("change_event_id");
 
-    match table.change_type_id() {
-        1 => (
-            change_event_id,
-            data::ChangeEvent::File(data::FileEvent::Create(data::FileCreate::from(row).into())),
-        ),
-        2 => (
-            change_event_id,
-            data::ChangeEvent::File(data::FileEvent::Modify(data::FileModify::from(row).into())),
-        ),
-        3 => (
-            change_event_id,
-            data::ChangeEvent::File(data::FileEvent::Move(data::FileMove::from(row).into())),
-        ),
-        4 => (
-            change_event_id,
-            data::ChangeEvent::File(data::FileEvent::Delete(data::FileDelete::from(row).into())),
-        ),
-        5 => (
-            change_event_id,
-            data::ChangeEvent::File(data::FileEvent::UndoDelete(
-                data::FileUndoDelete::from(row).into(),
-            )),
-        ),
-        6 => (
-            change_event_id,
-            data::ChangeEvent::Directory(data::DirectoryEvent::Create(
-                data::DirectoryCreate::from(row).into(),
-            )),
-        ),
-        7 => (
-            change_event_id,
-            data::ChangeEvent::Directory(data::DirectoryEvent::Move(
-                data::DirectoryMove::from(row).into(),
-            )),
-        ),
-        8 => (
-            change_event_id,
-            data::ChangeEvent::Directory(data::DirectoryEvent::Delete(
-                data::DirectoryDelete::from(row).into(),
-            )),
-        ),
-        9 => (
-            change_event_id,
-            data::ChangeEvent::Directory(data::DirectoryEvent::UndoDelete(
-                data::DirectoryUndoDelete::from(row).into(),
-            )),
-        ),
-        10 => (
-            change_event_id,
-            data::ChangeEvent::Symlink(data::SymlinkEvent::Create(
-                data::SymlinkCreate::from(row).into(),
-            )),
-        ),
-        11 => (
-            change_event_id,
-            data::ChangeEvent::Symlink(data::SymlinkEvent::Delete(
-                data::SymlinkDelete::from(row).into(),
-            )),
-        ),
-        _ => panic!(),
-    }
+    (change_event_id, table.decode(&row))
 }
 
 pub async fn get_changes(
     change_id_from: i32,
     change_id_to: i32,
     db_pool: &sqlx::PgPool,
+) -> Result<Vec<(i32, data::ChangeEvent)>, sqlx::Error> {
+    get_changes_in(change_id_from, change_id_to, &mut *db_pool.acquire().await?).await
+}
+
+/// Same as [`get_changes`], but on `connection`, e.g. as part of a transaction.
+pub(crate) async fn get_changes_in(
+    change_id_from: i32,
+    change_id_to: i32,
+    connection: &mut sqlx::PgConnection,
 ) -> Result<Vec<(i32, data::ChangeEvent)>, sqlx::Error> {
     let mut changes_in_tables = vec![];
 
@@ -91,7 +40,7 @@ pub async fn get_changes(
         .bind(change_id_from)
         .bind(change_id_to)
         .map(|row: sqlx::postgres::PgRow| row_to_change_event(row, &table))
-        .fetch_all(db_pool)
+        .fetch_all(&mut *connection)
         .await?;
 
         changes_in_tables.push(changes);
@@ -109,7 +58,7 @@ mod test {
 
     #[tokio::test]
     async fn test_get_changes() {
-        let db_pool = clear_tables_and_get_pool().await.unwrap();
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
 
         let changes = get_changes(0, 100, &db_pool).await.unwrap();
 
@@ -16,113 +16,145 @@ pub async fn initialize_db(pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
     "#;
     sqlx::query(sql).execute(pool).await?;
 
+    for table in super::TABLES {
+        sqlx::query(
+            r#"
+            INSERT INTO change_types (id, description)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(table.change_type_id() as i16)
+        .bind(table.table_description())
+        .execute(pool)
+        .await?;
+
+        sqlx::query(&table.create_table_sql()).execute(pool).await?;
+        sqlx::query(&table.add_columns_sql()).execute(pool).await?;
+    }
+
     let sql = r#"
-        INSERT INTO change_types (id, description)
-        VALUES (1, 'File Create'),
-               (2, 'File Modify'),
-               (3, 'File Move'),
-               (4, 'File Delete'),
-               (5, 'Undo File Delete'),
-               (6, 'Directory Create'),
-               (7, 'Directory Move'),
-               (8, 'Directory Delete'),
-               (9, 'Undo Directory Delete'),
-               (10, 'Symlink Create'),
-               (11, 'Symlink Delete')
-        ON CONFLICT DO NOTHING;
+        CREATE TABLE IF NOT EXISTS devices (
+            id SERIAL PRIMARY KEY,
+            name VARCHAR(64) NOT NULL UNIQUE,
+            last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            server_version INTEGER NOT NULL DEFAULT 0
+        )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
+    // Audit columns, the originating device is `NULL` for changes made by the server itself
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS file_create (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
-        )
+        ALTER TABLE change_events
+        ADD COLUMN IF NOT EXISTS device_id INTEGER REFERENCES devices(id) ON DELETE SET NULL,
+        ADD COLUMN IF NOT EXISTS remote_address VARCHAR(64)
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS file_modify (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS snapshot_checkpoints (
+            server_version INTEGER PRIMARY KEY NOT NULL,
+            snapshot TEXT NOT NULL
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS file_move (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            old_path VARCHAR(128) NOT NULL,
-            new_path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS file_versions (
+            id SERIAL PRIMARY KEY,
+            path VARCHAR(128) NOT NULL,
+            version INTEGER NOT NULL,
+            size BIGINT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            UNIQUE (path, version)
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS file_delete (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS trash (
+            id SERIAL PRIMARY KEY,
+            path VARCHAR(128) NOT NULL,
+            is_directory BOOLEAN NOT NULL,
+            change_event_id INTEGER NOT NULL,
+            trashed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMPTZ
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
+    // `change_event_id` does not reference `change_events`, as the change may be removed by
+    // compaction while the device can still send it again
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS undo_file_delete (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS uploaded_changes (
+            device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+            local_change_id BIGINT NOT NULL,
+            change_event_id INTEGER NOT NULL,
+            PRIMARY KEY (device_id, local_change_id)
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS directory_create (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
-        )
+        ALTER TABLE uploaded_changes
+        DROP CONSTRAINT IF EXISTS uploaded_changes_change_event_id_fkey
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS directory_move (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            old_path VARCHAR(128) NOT NULL,
-            new_path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS content_requests (
+            path VARCHAR(128) PRIMARY KEY,
+            requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS directory_delete (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS shared_folders (
+            id SERIAL PRIMARY KEY,
+            path VARCHAR(128) NOT NULL UNIQUE
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS undo_directory_delete (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
+        CREATE TABLE IF NOT EXISTS shared_folder_members (
+            shared_folder_id INTEGER NOT NULL REFERENCES shared_folders(id) ON DELETE CASCADE,
+            namespace VARCHAR(64) NOT NULL,
+            read_only BOOLEAN NOT NULL,
+            PRIMARY KEY (shared_folder_id, namespace)
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
+    // The size of every file, for the usage of each namespace. Deleted files keep their size,
+    // with the delete and the path that it deleted, until the delete is undone
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS symlink_create (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
+        CREATE TABLE IF NOT EXISTS file_sizes (
+            id SERIAL PRIMARY KEY,
             path VARCHAR(128) NOT NULL,
-            target VARCHAR(128) NOT NULL
+            size BIGINT NOT NULL,
+            deleted_by INTEGER,
+            deleted_path VARCHAR(128)
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
     let sql = r#"
-        CREATE TABLE IF NOT EXISTS symlink_delete (
-            change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id),
-            path VARCHAR(128) NOT NULL
+        CREATE UNIQUE INDEX IF NOT EXISTS file_sizes_live_path
+        ON file_sizes (path) WHERE deleted_by IS NULL
+    "#;
+    sqlx::query(sql).execute(pool).await?;
+
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS namespace_usage (
+            namespace VARCHAR(64) PRIMARY KEY,
+            used BIGINT NOT NULL
         )
     "#;
     sqlx::query(sql).execute(pool).await?;
 
+    super::quotas::initialize_usage(pool).await?;
+
     Ok(())
 }
@@ -2,195 +2,298 @@ use sqlx::Row;
 
 use crate::data;
 
-use super::TableDetailsTrait;
+use super::{
+    file_versions::VersionCopies, ChangeOrigin, ServerFileHandlerConfig, TableDetailsTrait,
+};
 
+/// Why changes were not inserted. Nothing is inserted if any of the changes fails.
+#[derive(Debug)]
+pub enum InsertError {
+    /// The changes would take a namespace over its quota, see [`check_quota`](super::check_quota).
+    QuotaExceeded(data::StorageUsage),
+    /// The namespace of the origin of the changes may not write one of them, see
+    /// [`check_access`](super::check_access).
+    AccessDenied(data::AccessDenied),
+    /// The changes conflict with changes made by other devices, see
+    /// [`check_conflicts`](super::check_conflicts).
+    Conflict(Vec<data::Conflict>),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for InsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InsertError::QuotaExceeded(usage) => {
+                write!(f, "quota of namespace `{}` exceeded", usage.namespace())
+            }
+            InsertError::AccessDenied(denied) => {
+                write!(f, "access to `{}` denied", denied.path())
+            }
+            InsertError::Conflict(conflicts) => {
+                write!(f, "{} conflicting change(s)", conflicts.len())
+            }
+            InsertError::Database(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for InsertError {}
+
+impl From<sqlx::Error> for InsertError {
+    fn from(e: sqlx::Error) -> Self {
+        InsertError::Database(e)
+    }
+}
+
+/// Updates the usage for `changes` (with their `change_event_id`) and fails with
+/// [`InsertError::QuotaExceeded`] if they take a namespace over its quota.
+pub(crate) async fn enforce_quota(
+    changes: &[(i32, &data::ChangeEvent)],
+    config: &ServerFileHandlerConfig,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), InsertError> {
+    match super::quotas::check_quota_in(changes, config, &mut *transaction).await? {
+        Some(usage) => Err(InsertError::QuotaExceeded(usage)),
+        None => Ok(()),
+    }
+}
+
+/// Fails with [`InsertError::AccessDenied`] if the namespace of `origin` may not write one of
+/// `changes`. Changes without an origin, or from an origin without a namespace, are not checked.
+pub(crate) async fn enforce_access(
+    changes: &[&data::ChangeEvent],
+    origin: Option<&ChangeOrigin>,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), InsertError> {
+    let namespace = match origin.and_then(|origin| origin.namespace()) {
+        Some(namespace) => namespace,
+        None => return Ok(()),
+    };
+    match super::shared_folders::check_access_in(namespace, changes, &mut *transaction).await? {
+        Some(denied) => Err(InsertError::AccessDenied(denied)),
+        None => Ok(()),
+    }
+}
+
+/// Inserts a change made by the server itself (e.g. restoring a file version), so it is not
+/// annotated with a device. Use [`insert_change_from`] for changes received from a client.
 pub async fn insert_change(
     change: data::ChangeEvent,
+    config: &ServerFileHandlerConfig,
     db_pool: &sqlx::PgPool,
-) -> Result<i32, sqlx::Error> {
+) -> Result<i32, InsertError> {
+    insert_change_with_origin(change, None, config, db_pool).await
+}
+
+/// Inserts a change received from a client, recording `origin` for the audit log.
+pub async fn insert_change_from(
+    change: data::ChangeEvent,
+    origin: &ChangeOrigin,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<i32, InsertError> {
+    insert_change_with_origin(change, Some(origin), config, db_pool).await
+}
+
+pub(crate) async fn insert_change_with_origin(
+    change: data::ChangeEvent,
+    origin: Option<&ChangeOrigin>,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<i32, InsertError> {
+    let mut copies = VersionCopies::default();
     // begin transaction
     let mut transaction = db_pool.begin().await?;
 
-    // insert change
+    enforce_access(&[&change], origin, &mut transaction).await?;
+    copies.retain(&[&change], config, &mut transaction).await?;
+    let change_id = insert_change_in(&change, origin, &mut transaction).await?;
+    enforce_quota(&[(change_id, &change)], config, &mut transaction).await?;
+
+    // transaction.rollback().await?;
+    copies.commit(transaction, config, db_pool).await?;
+
+    Ok(change_id)
+}
+
+/// Inserts `change` as part of `transaction`, so that it can be inserted together with other
+/// rows. The version retained by a `FileModify` must be inserted separately, see
+/// [`VersionCopies::retain`].
+pub(crate) async fn insert_change_in(
+    change: &data::ChangeEvent,
+    origin: Option<&ChangeOrigin>,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<i32, sqlx::Error> {
     let change_id: i32 = sqlx::query(
         r#"
-        INSERT INTO change_events (change_type_id)
-        VALUES ($1)
+        INSERT INTO change_events (change_type_id, device_id, remote_address)
+        VALUES ($1, $2, $3)
         RETURNING id
         "#,
     )
     .bind(change.table_details().change_type_id())
+    .bind(origin.map(|origin| origin.device_id()))
+    .bind(origin.and_then(|origin| origin.remote_address()))
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    insert_change_details(change_id, change, &mut *transaction).await?;
+    super::file_versions::move_file_versions(change, &mut *transaction).await?;
+
+    Ok(change_id)
+}
+
+/// Inserts a change uploaded by the device of `origin`. If the same `local_change_id` has
+/// already been inserted for the device (e.g. the connection dropped before the client received
+/// the response and it sent the change again), the change is not inserted a second time and the
+/// originally assigned `change_event_id` is returned instead.
+pub async fn insert_uploaded_change(
+    origin: &ChangeOrigin,
+    uploaded_change: &data::UploadedChange,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<i32, InsertError> {
+    let device_id = origin.device_id();
+    let mut copies = VersionCopies::default();
+    let mut transaction = db_pool.begin().await?;
+
+    let change_id: i32 = sqlx::query(
+        r#"
+        INSERT INTO change_events (change_type_id, device_id, remote_address)
+        VALUES ($1, $2, $3)
+        RETURNING id
+        "#,
+    )
+    .bind(uploaded_change.change().table_details().change_type_id())
+    .bind(device_id)
+    .bind(origin.remote_address())
     .map(|row: sqlx::postgres::PgRow| row.get(0))
     .fetch_one(&mut transaction)
     .await?;
 
-    match change {
-        data::ChangeEvent::File(file_event) => match file_event {
-            data::FileEvent::Create(file_create) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO file_create (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(file_create.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::FileEvent::Delete(file_delete) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO file_delete (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(file_delete.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::FileEvent::Modify(file_modify) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO file_modify (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(file_modify.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::FileEvent::Move(file_move) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO file_move (change_event_id, old_path, new_path)
-                        VALUES ($1, $2, $3)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(file_move.from_path())
-                .bind(file_move.to_path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::FileEvent::UndoDelete(file_undo_delete) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO file_undo_delete (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(file_undo_delete.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-        },
-        data::ChangeEvent::Directory(directory_event) => match directory_event {
-            data::DirectoryEvent::Create(directory_create) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO directory_create (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(directory_create.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::DirectoryEvent::Delete(directory_delete) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO directory_delete (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(directory_delete.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::DirectoryEvent::Move(directory_move) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO directory_move (change_event_id, old_path, new_path)
-                        VALUES ($1, $2, $3)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(directory_move.from_path())
-                .bind(directory_move.to_path())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::DirectoryEvent::UndoDelete(directory_undo_delete) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO directory_undo_delete (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(directory_undo_delete.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-        },
-        data::ChangeEvent::Symlink(symlink_event) => match symlink_event {
-            data::SymlinkEvent::Create(symlink_create) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO symlink_create (change_event_id, path, target)
-                        VALUES ($1, $2, $3)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(symlink_create.path())
-                .bind(symlink_create.links_to())
-                .execute(&mut transaction)
-                .await?;
-            }
-            data::SymlinkEvent::Delete(symlink_delete) => {
-                sqlx::query(
-                    r#"
-                        INSERT INTO symlink_delete (change_event_id, path)
-                        VALUES ($1, $2)
-                        "#,
-                )
-                .bind(change_id)
-                .bind(symlink_delete.path())
-                .execute(&mut transaction)
-                .await?;
-            }
-        },
+    let inserted: Option<i32> = sqlx::query(
+        r#"
+        INSERT INTO uploaded_changes (device_id, local_change_id, change_event_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        RETURNING change_event_id
+        "#,
+    )
+    .bind(device_id)
+    .bind(uploaded_change.local_change_id())
+    .bind(change_id)
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    if inserted.is_none() {
+        // The change has been uploaded before
+        transaction.rollback().await?;
+        let change_id: i32 = sqlx::query(
+            r#"
+            SELECT change_event_id FROM uploaded_changes
+            WHERE device_id = $1 AND local_change_id = $2
+            "#,
+        )
+        .bind(device_id)
+        .bind(uploaded_change.local_change_id())
+        .map(|row: sqlx::postgres::PgRow| row.get(0))
+        .fetch_one(db_pool)
+        .await?;
+        return Ok(change_id);
     }
 
-    // transaction.rollback().await?;
-    transaction.commit().await?;
+    enforce_access(&[uploaded_change.change()], Some(origin), &mut transaction).await?;
+    enforce_quota(
+        &[(change_id, uploaded_change.change())],
+        config,
+        &mut transaction,
+    )
+    .await?;
+    copies
+        .retain(&[uploaded_change.change()], config, &mut transaction)
+        .await?;
+    insert_change_details(change_id, uploaded_change.change(), &mut transaction).await?;
+    super::file_versions::move_file_versions(uploaded_change.change(), &mut transaction).await?;
+
+    copies.commit(transaction, config, db_pool).await?;
 
     Ok(change_id)
 }
 
+/// Inserts the row of the table specific to the type of `change`. The `change_events` row with
+/// `change_id` must already exist.
+pub(crate) async fn insert_change_details(
+    change_id: i32,
+    change: &data::ChangeEvent,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+    let table = change.table_details();
+    let sql = table.insert_sql();
+
+    let mut query = sqlx::query(&sql).bind(change_id);
+    for value in table.encode(change) {
+        query = query.bind(value);
+    }
+    query.execute(&mut *transaction).await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use super::insert_change;
-    use crate::{server_database::get_changes, testing_utils::clear_tables_and_get_pool};
+    use super::{insert_change, insert_uploaded_change};
+    use crate::{
+        data,
+        server_database::{get_changes, register_device, ChangeOrigin, ServerFileHandlerConfig},
+        testing_utils::clear_tables_and_get_pool,
+    };
 
     #[tokio::test]
     async fn test_insert_change() {
-        let db_pool = clear_tables_and_get_pool().await.unwrap();
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
 
         let change = crate::data::ChangeEvent::File(crate::data::FileEvent::Create(
             crate::data::FileCreate::new(0, "hello.txt".to_string()),
         ));
 
-        let _change_id = insert_change(change, &db_pool).await.unwrap();
+        let _change_id = insert_change(change, &config, &db_pool).await.unwrap();
 
         let changes = get_changes(0, 100, &db_pool).await.unwrap();
         dbg!(&changes);
         assert_eq!(changes.len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_insert_uploaded_change() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        let device_id = register_device("test_insert_uploaded_change", &db_pool)
+            .await
+            .unwrap();
+        let origin = ChangeOrigin::new(device_id, None);
+
+        let uploaded_change =
+            data::UploadedChange::new(1, data::FileCreate::new(0, "a.txt".to_string()).into());
+        let change_id = insert_uploaded_change(&origin, &uploaded_change, &config, &db_pool)
+            .await
+            .unwrap();
+        let replayed_change_id =
+            insert_uploaded_change(&origin, &uploaded_change, &config, &db_pool)
+                .await
+                .unwrap();
+        assert_eq!(change_id, replayed_change_id);
+
+        let uploaded_change =
+            data::UploadedChange::new(2, data::FileModify::new(1, "a.txt".to_string()).into());
+        let next_change_id = insert_uploaded_change(&origin, &uploaded_change, &config, &db_pool)
+            .await
+            .unwrap();
+        assert!(next_change_id > change_id);
+
+        let changes = get_changes(0, next_change_id, &db_pool).await.unwrap();
+        assert_eq!(changes.len(), 2);
+    }
 }
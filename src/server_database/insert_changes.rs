@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sqlx::Row;
+
+use crate::data;
+
+use super::{
+    file_versions::VersionCopies,
+    insert_change::{enforce_access, enforce_quota},
+    table_details::ColumnDetails,
+    ChangeOrigin, InsertError, ServerFileHandlerConfig, TableDetailsTrait,
+};
+
+/// Rows to insert into a single change table, stored column by column so that they can be
+/// bound as arrays.
+struct TableRows<'a> {
+    columns: &'a [ColumnDetails],
+    change_ids: Vec<i32>,
+    values: Vec<Vec<String>>,
+}
+
+/// Inserts every change in `changes` in a single transaction, so either all of them are
+/// inserted or none of them are. Uses one multi-row insert per table rather than one insert per
+/// change. Returns the `change_event_id` assigned to each change, in the same order as
+/// `changes`.
+pub async fn insert_changes(
+    changes: &[data::ChangeEvent],
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<i32>, InsertError> {
+    insert_changes_with_origin(changes, None, config, db_pool).await
+}
+
+/// Same as [`insert_changes`], but records `origin` for the audit log of every change.
+pub async fn insert_changes_from(
+    changes: &[data::ChangeEvent],
+    origin: &ChangeOrigin,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<i32>, InsertError> {
+    insert_changes_with_origin(changes, Some(origin), config, db_pool).await
+}
+
+async fn insert_changes_with_origin(
+    changes: &[data::ChangeEvent],
+    origin: Option<&ChangeOrigin>,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<i32>, InsertError> {
+    if changes.is_empty() {
+        return Ok(vec![]);
+    }
+    let changes = changes.iter().collect::<Vec<_>>();
+
+    let mut copies = VersionCopies::default();
+    let mut transaction = db_pool.begin().await?;
+
+    enforce_access(&changes, origin, &mut transaction).await?;
+    copies.retain(&changes, config, &mut transaction).await?;
+    let change_ids = reserve_change_ids(changes.len(), &mut transaction).await?;
+    insert_changes_in(&change_ids, &changes, origin, &mut transaction).await?;
+    enforce_quota(
+        &change_ids
+            .iter()
+            .copied()
+            .zip(changes.iter().copied())
+            .collect::<Vec<_>>(),
+        config,
+        &mut transaction,
+    )
+    .await?;
+
+    copies.commit(transaction, config, db_pool).await?;
+
+    Ok(change_ids)
+}
+
+/// Same as [`insert_changes_from`], but for changes uploaded by the device of `origin`. As with
+/// [`insert_uploaded_change`](super::insert_uploaded_change), changes whose `local_change_id`
+/// has already been inserted for the device are not inserted again, and the originally
+/// assigned `change_event_id` is returned for them instead.
+pub async fn insert_uploaded_changes(
+    origin: &ChangeOrigin,
+    uploaded_changes: &[data::UploadedChange],
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<i32>, InsertError> {
+    if uploaded_changes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut copies = VersionCopies::default();
+    let mut transaction = db_pool.begin().await?;
+    let change_ids = insert_uploaded_changes_in(
+        origin,
+        uploaded_changes,
+        config,
+        &mut copies,
+        &mut transaction,
+    )
+    .await?;
+    copies.commit(transaction, config, db_pool).await?;
+
+    Ok(change_ids)
+}
+
+/// Same as [`insert_uploaded_changes`], but as part of `transaction`. The versions retained by
+/// the changes are added to `copies`.
+pub(crate) async fn insert_uploaded_changes_in(
+    origin: &ChangeOrigin,
+    uploaded_changes: &[data::UploadedChange],
+    config: &ServerFileHandlerConfig,
+    copies: &mut VersionCopies,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<Vec<i32>, InsertError> {
+    let device_id = origin.device_id();
+    let local_change_ids = uploaded_changes
+        .iter()
+        .map(|uploaded_change| uploaded_change.local_change_id())
+        .collect::<Vec<_>>();
+
+    let reserved_ids = reserve_change_ids(uploaded_changes.len(), &mut *transaction).await?;
+    sqlx::query(
+        r#"
+        INSERT INTO uploaded_changes (device_id, local_change_id, change_event_id)
+        SELECT $1, local_change_id, change_event_id
+        FROM UNNEST($2::BIGINT[], $3::INTEGER[]) AS rows (local_change_id, change_event_id)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(device_id)
+    .bind(&local_change_ids)
+    .bind(&reserved_ids)
+    .execute(&mut *transaction)
+    .await?;
+
+    let assigned_ids: HashMap<i64, i32> = sqlx::query(
+        r#"
+        SELECT local_change_id, change_event_id FROM uploaded_changes
+        WHERE device_id = $1 AND local_change_id = ANY($2)
+        "#,
+    )
+    .bind(device_id)
+    .bind(&local_change_ids)
+    .map(|row: sqlx::postgres::PgRow| (row.get(0), row.get(1)))
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .collect();
+
+    let change_ids = local_change_ids
+        .iter()
+        .map(|local_change_id| assigned_ids[local_change_id])
+        .collect::<Vec<_>>();
+
+    // Only the changes that were assigned their reserved id have not been uploaded before
+    let (new_ids, new_changes): (Vec<i32>, Vec<&data::ChangeEvent>) = change_ids
+        .iter()
+        .zip(reserved_ids.iter())
+        .zip(uploaded_changes.iter())
+        .filter(|((change_id, reserved_id), _)| change_id == reserved_id)
+        .map(|((change_id, _), uploaded_change)| (*change_id, uploaded_change.change()))
+        .unzip();
+    enforce_access(&new_changes, Some(origin), &mut *transaction).await?;
+    copies
+        .retain(&new_changes, config, &mut *transaction)
+        .await?;
+    insert_changes_in(&new_ids, &new_changes, Some(origin), &mut *transaction).await?;
+    enforce_quota(
+        &new_ids
+            .iter()
+            .copied()
+            .zip(new_changes.iter().copied())
+            .collect::<Vec<_>>(),
+        config,
+        &mut *transaction,
+    )
+    .await?;
+
+    Ok(change_ids)
+}
+
+/// Reserves `count` change ids up front, so that they are increasing in the order of the
+/// changes they are assigned to.
+async fn reserve_change_ids(
+    count: usize,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<Vec<i32>, sqlx::Error> {
+    let mut change_ids: Vec<i32> = sqlx::query(
+        r#"
+        SELECT nextval(pg_get_serial_sequence('change_events', 'id'))::INTEGER
+        FROM generate_series(1, $1)
+        "#,
+    )
+    .bind(count as i32)
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_all(&mut *transaction)
+    .await?;
+    change_ids.sort_unstable();
+
+    Ok(change_ids)
+}
+
+/// Inserts each of `changes` with the id at the same index of `change_ids`.
+async fn insert_changes_in(
+    change_ids: &[i32],
+    changes: &[&data::ChangeEvent],
+    origin: Option<&ChangeOrigin>,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO change_events (id, change_type_id, device_id, remote_address)
+        SELECT id, change_type_id, $3, $4
+        FROM UNNEST($1::INTEGER[], $2::SMALLINT[]) AS rows (id, change_type_id)
+        "#,
+    )
+    .bind(change_ids)
+    .bind(
+        changes
+            .iter()
+            .map(|change| change.table_details().change_type_id() as i16)
+            .collect::<Vec<_>>(),
+    )
+    .bind(origin.map(|origin| origin.device_id()))
+    .bind(origin.and_then(|origin| origin.remote_address()))
+    .execute(&mut *transaction)
+    .await?;
+
+    let mut tables: BTreeMap<&str, TableRows> = BTreeMap::new();
+    for (change_id, change) in change_ids.iter().zip(changes.iter().copied()) {
+        let table = change.table_details();
+        let rows = tables
+            .entry(table.table_name())
+            .or_insert_with(|| TableRows {
+                columns: table.columns(),
+                change_ids: vec![],
+                values: vec![vec![]; table.columns().len()],
+            });
+        rows.change_ids.push(*change_id);
+        for (i, value) in table.encode(change).into_iter().enumerate() {
+            rows.values[i].push(value);
+        }
+    }
+
+    for (table_name, rows) in tables {
+        let parameters = (2..rows.columns.len() + 2)
+            .map(|i| format!("${}::TEXT[]", i))
+            .collect::<Vec<_>>();
+        let names = rows
+            .columns
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>();
+        let casts = rows
+            .columns
+            .iter()
+            .map(|column| format!("{}::{}", column.name(), column.bind_type()))
+            .collect::<Vec<_>>();
+        let sql = format!(
+            r#"
+            INSERT INTO {table} (change_event_id, {columns})
+            SELECT change_event_id, {casts}
+            FROM UNNEST($1::INTEGER[], {parameters}) AS rows (change_event_id, {columns})
+            "#,
+            table = table_name,
+            columns = names.join(", "),
+            casts = casts.join(", "),
+            parameters = parameters.join(", ")
+        );
+
+        let mut query = sqlx::query(&sql).bind(rows.change_ids);
+        for values in rows.values {
+            query = query.bind(values);
+        }
+        query.execute(&mut *transaction).await?;
+    }
+
+    for change in changes {
+        super::file_versions::move_file_versions(change, &mut *transaction).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{insert_changes, insert_uploaded_changes};
+    use crate::{
+        data,
+        server_database::{
+            compact_changes, get_changes, get_server_version, insert_uploaded_change,
+            register_device, ChangeOrigin, ServerFileHandlerConfig,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_insert_changes() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::DirectoryCreate::new("dir".to_string()).into(),
+            data::FileCreate::new(12, "dir/a.txt".to_string()).into(),
+            data::FileMove::new("dir/a.txt".to_string(), "dir/b.txt".to_string()).into(),
+            data::SymlinkCreate::new("link".to_string(), "dir/b.txt".to_string()).into(),
+            data::FileCreate::new(0, "c.txt".to_string()).into(),
+        ];
+        let change_ids = insert_changes(&changes, &config, &db_pool).await.unwrap();
+        assert_eq!(change_ids.len(), changes.len());
+        assert!(change_ids.windows(2).all(|ids| ids[0] < ids[1]));
+
+        let inserted = get_changes(0, *change_ids.last().unwrap(), &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            inserted,
+            change_ids.into_iter().zip(changes).collect::<Vec<_>>()
+        );
+
+        // A failing batch inserts nothing
+        let too_long = "a".repeat(200);
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileCreate::new(0, "d.txt".to_string()).into(),
+            data::FileCreate::new(0, too_long).into(),
+        ];
+        assert!(insert_changes(&changes, &config, &db_pool).await.is_err());
+        assert_eq!(get_changes(0, 100, &db_pool).await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_insert_uploaded_changes() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        let device_id = register_device("test_insert_uploaded_changes", &db_pool)
+            .await
+            .unwrap();
+        let origin = ChangeOrigin::new(device_id, None);
+
+        let first =
+            data::UploadedChange::new(1, data::FileCreate::new(0, "a.txt".to_string()).into());
+        let first_id = insert_uploaded_change(&origin, &first, &config, &db_pool)
+            .await
+            .unwrap();
+
+        // The first change is sent again together with new ones
+        let uploaded_changes = vec![
+            first,
+            data::UploadedChange::new(
+                2,
+                data::FileMove::new("a.txt".to_string(), "c.txt".to_string()).into(),
+            ),
+            data::UploadedChange::new(3, data::FileCreate::new(0, "b.txt".to_string()).into()),
+        ];
+        let change_ids = insert_uploaded_changes(&origin, &uploaded_changes, &config, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(change_ids[0], first_id);
+        assert!(first_id < change_ids[1] && change_ids[1] < change_ids[2]);
+
+        let replayed_ids = insert_uploaded_changes(&origin, &uploaded_changes, &config, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(replayed_ids, change_ids);
+        assert_eq!(get_changes(0, 100, &db_pool).await.unwrap().len(), 3);
+
+        // The changes are still recognised after they have been compacted
+        let server_version = get_server_version(&db_pool).await.unwrap();
+        compact_changes(server_version, &db_pool).await.unwrap();
+        assert_eq!(get_changes(0, 100, &db_pool).await.unwrap().len(), 2);
+        let replayed_ids = insert_uploaded_changes(&origin, &uploaded_changes, &config, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(replayed_ids, change_ids);
+        assert_eq!(get_changes(0, 100, &db_pool).await.unwrap().len(), 2);
+    }
+}
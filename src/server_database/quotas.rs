@@ -0,0 +1,457 @@
+use std::collections::BTreeMap;
+
+use sqlx::Row;
+
+use crate::data;
+
+use super::ServerFileHandlerConfig;
+
+/// The namespace that `path` belongs to, which is its top level directory. Files directly in
+/// the storage directory belong to the `""` namespace.
+pub fn namespace_of(path: &str) -> &str {
+    match path.split_once('/') {
+        Some((namespace, _)) => namespace,
+        None => "",
+    }
+}
+
+/// Whether `path` is inside of the directory `dir` (at any depth), in SQL.
+const IN_DIRECTORY: &str = "LEFT(path, LENGTH($1) + 1) = $1 || '/'";
+
+/// Removes the live size of `path`, returning it.
+async fn remove_live_size(
+    path: &str,
+    connection: &mut sqlx::PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let size: Option<i64> =
+        sqlx::query("DELETE FROM file_sizes WHERE path = $1 AND deleted_by IS NULL RETURNING size")
+            .bind(path)
+            .map(|row: sqlx::postgres::PgRow| row.get(0))
+            .fetch_optional(&mut *connection)
+            .await?;
+    Ok(size.unwrap_or(0) as u64)
+}
+
+/// Sets the live size of `path`, returning the size that it replaces.
+async fn set_live_size(
+    path: &str,
+    size: u64,
+    connection: &mut sqlx::PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let replaced = remove_live_size(path, &mut *connection).await?;
+    sqlx::query("INSERT INTO file_sizes (path, size) VALUES ($1, $2)")
+        .bind(path)
+        .bind(size as i64)
+        .execute(&mut *connection)
+        .await?;
+    Ok(replaced)
+}
+
+/// Updates the tracked size of every file affected by `changes` (with their `change_event_id`),
+/// and the usage of the namespaces they are in. Returns by how much the usage of each namespace
+/// changed.
+///
+/// Deleted files keep their size, so that an undo delete restores it.
+pub(crate) async fn update_usage_in(
+    changes: &[(i32, &data::ChangeEvent)],
+    connection: &mut sqlx::PgConnection,
+) -> Result<BTreeMap<String, i64>, sqlx::Error> {
+    let mut deltas: BTreeMap<String, i64> = BTreeMap::new();
+    let mut add = |path: &str, delta: i64| {
+        *deltas.entry(namespace_of(path).to_string()).or_default() += delta;
+    };
+
+    for (change_id, change) in changes {
+        match change {
+            data::ChangeEvent::File(file_event) => match file_event {
+                data::FileEvent::Create(file_create) => {
+                    let (path, size) = (file_create.path(), file_create.size());
+                    let replaced = set_live_size(path, size, &mut *connection).await?;
+                    add(path, size as i64 - replaced as i64);
+                }
+                data::FileEvent::Modify(file_modify) => {
+                    let (path, size) = (file_modify.path(), file_modify.size());
+                    let replaced = set_live_size(path, size, &mut *connection).await?;
+                    add(path, size as i64 - replaced as i64);
+                }
+                data::FileEvent::Move(file_move) => {
+                    let size = remove_live_size(file_move.from_path(), &mut *connection).await?;
+                    let replaced =
+                        set_live_size(file_move.to_path(), size, &mut *connection).await?;
+                    add(file_move.from_path(), -(size as i64));
+                    add(file_move.to_path(), size as i64 - replaced as i64);
+                }
+                data::FileEvent::Delete(file_delete) => {
+                    let size: Option<i64> = sqlx::query(
+                        r#"
+                        UPDATE file_sizes SET deleted_by = $2, deleted_path = $1
+                        WHERE path = $1 AND deleted_by IS NULL
+                        RETURNING size
+                        "#,
+                    )
+                    .bind(file_delete.path())
+                    .bind(change_id)
+                    .map(|row: sqlx::postgres::PgRow| row.get(0))
+                    .fetch_optional(&mut *connection)
+                    .await?;
+                    add(file_delete.path(), -size.unwrap_or(0));
+                }
+                data::FileEvent::UndoDelete(file_undo_delete) => {
+                    let path = file_undo_delete.path();
+                    let size: Option<i64> = sqlx::query(
+                        r#"
+                        DELETE FROM file_sizes WHERE id = (
+                            SELECT id FROM file_sizes WHERE deleted_path = $1 AND path = $1
+                            ORDER BY deleted_by DESC
+                            LIMIT 1
+                        )
+                        RETURNING size
+                        "#,
+                    )
+                    .bind(path)
+                    .map(|row: sqlx::postgres::PgRow| row.get(0))
+                    .fetch_optional(&mut *connection)
+                    .await?;
+                    let size = size
+                        .map(|size| size as u64)
+                        .unwrap_or(file_undo_delete.size());
+                    let replaced = set_live_size(path, size, &mut *connection).await?;
+                    add(path, size as i64 - replaced as i64);
+                }
+            },
+            data::ChangeEvent::Directory(dir_event) => match dir_event {
+                data::DirectoryEvent::Create(_) => {}
+                data::DirectoryEvent::Move(dir_move) => {
+                    let (from_dir, to_dir) = (dir_move.from_path(), dir_move.to_path());
+                    let moved: Vec<(String, i64)> = sqlx::query(&format!(
+                        r#"
+                        UPDATE file_sizes SET path = $2 || SUBSTRING(path FROM LENGTH($1) + 1)
+                        WHERE deleted_by IS NULL AND {}
+                        RETURNING path, size
+                        "#,
+                        IN_DIRECTORY
+                    ))
+                    .bind(from_dir)
+                    .bind(to_dir)
+                    .map(|row: sqlx::postgres::PgRow| (row.get(0), row.get(1)))
+                    .fetch_all(&mut *connection)
+                    .await?;
+                    for (path, size) in moved {
+                        add(&format!("{}{}", from_dir, &path[to_dir.len()..]), -size);
+                        add(&path, size);
+                    }
+                }
+                data::DirectoryEvent::Delete(dir_delete) => {
+                    let deleted: Vec<(String, i64)> = sqlx::query(&format!(
+                        r#"
+                        UPDATE file_sizes SET deleted_by = $2, deleted_path = $1
+                        WHERE deleted_by IS NULL AND {}
+                        RETURNING path, size
+                        "#,
+                        IN_DIRECTORY
+                    ))
+                    .bind(dir_delete.path())
+                    .bind(change_id)
+                    .map(|row: sqlx::postgres::PgRow| (row.get(0), row.get(1)))
+                    .fetch_all(&mut *connection)
+                    .await?;
+                    for (path, size) in deleted {
+                        add(&path, -size);
+                    }
+                }
+                data::DirectoryEvent::UndoDelete(dir_undo_delete) => {
+                    let restored: Vec<(String, i64)> = sqlx::query(
+                        r#"
+                        UPDATE file_sizes SET deleted_by = NULL, deleted_path = NULL
+                        WHERE deleted_path = $1 AND path <> $1 AND deleted_by = (
+                            SELECT MAX(deleted_by) FROM file_sizes
+                            WHERE deleted_path = $1 AND path <> $1
+                        )
+                        RETURNING path, size
+                        "#,
+                    )
+                    .bind(dir_undo_delete.path())
+                    .map(|row: sqlx::postgres::PgRow| (row.get(0), row.get(1)))
+                    .fetch_all(&mut *connection)
+                    .await?;
+                    for (path, size) in restored {
+                        add(&path, size);
+                    }
+                }
+            },
+            data::ChangeEvent::Symlink(_) => {}
+        }
+    }
+
+    for (namespace, delta) in deltas.iter() {
+        sqlx::query(
+            r#"
+            INSERT INTO namespace_usage (namespace, used) VALUES ($1, $2)
+            ON CONFLICT (namespace) DO UPDATE SET used = namespace_usage.used + $2
+            "#,
+        )
+        .bind(namespace)
+        .bind(delta)
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(deltas)
+}
+
+/// Computes the tracked sizes and usage from the tree of the current server version, for change
+/// logs that were written before the usage was tracked. Deleted files are not tracked, so an
+/// undo delete of any of them restores the size carried by the change.
+pub(crate) async fn initialize_usage(db_pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+    let tracked: bool = sqlx::query(
+        "SELECT EXISTS (SELECT 1 FROM file_sizes) OR EXISTS (SELECT 1 FROM namespace_usage)",
+    )
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_one(db_pool)
+    .await?;
+    if tracked {
+        return Ok(());
+    }
+
+    let server_version = super::get_server_version(db_pool).await?;
+    let snapshot = super::get_snapshot(server_version, db_pool).await?;
+    let changes = snapshot
+        .files()
+        .iter()
+        .map(|(path, size)| data::FileCreate::new(*size, path.clone()).into())
+        .collect::<Vec<data::ChangeEvent>>();
+
+    let mut transaction = db_pool.begin().await?;
+    update_usage_in(
+        &changes.iter().map(|change| (0, change)).collect::<Vec<_>>(),
+        &mut transaction,
+    )
+    .await?;
+    transaction.commit().await
+}
+
+/// Returns the number of bytes used by the live files in `namespace`, as carried by
+/// `FileCreate` and `FileModify`. The usage is tracked as changes are inserted. Trashed files
+/// and retained versions are not included.
+pub async fn get_storage_usage(
+    namespace: &str,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<data::StorageUsage, sqlx::Error> {
+    Ok(data::StorageUsage::new(
+        namespace.to_string(),
+        get_used(namespace, &mut *db_pool.acquire().await?).await?,
+        config.quotas().quota(namespace),
+    ))
+}
+
+async fn get_used(
+    namespace: &str,
+    connection: &mut sqlx::PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let used: Option<i64> = sqlx::query("SELECT used FROM namespace_usage WHERE namespace = $1")
+        .bind(namespace)
+        .map(|row: sqlx::postgres::PgRow| row.get(0))
+        .fetch_optional(connection)
+        .await?;
+    Ok(used.unwrap_or(0) as u64)
+}
+
+/// Checks whether applying `changes` would take any namespace over its quota. Returns the
+/// resulting usage of the first such namespace, in which case the changes should be rejected
+/// with [`ErrorType::QuotaExceeded`](data::ErrorType::QuotaExceeded). Changes that do not
+/// increase the usage of a namespace are always accepted, so that a namespace that is already
+/// over its quota can still be cleaned up.
+///
+/// Changes are also checked when they are inserted (see
+/// [`InsertError::QuotaExceeded`](super::InsertError::QuotaExceeded)), so this is only needed
+/// to check changes without inserting them.
+pub async fn check_quota(
+    changes: &[data::ChangeEvent],
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<Option<data::StorageUsage>, sqlx::Error> {
+    // The usage is updated in a transaction that is rolled back
+    let mut transaction = db_pool.begin().await?;
+    let changes = changes
+        .iter()
+        .enumerate()
+        .map(|(i, change)| (i as i32, change))
+        .collect::<Vec<_>>();
+    check_quota_in(&changes, config, &mut transaction).await
+}
+
+/// Updates the usage for `changes` (see [`update_usage_in`]) on `connection`, as part of the
+/// transaction that inserts them, and checks them as [`check_quota`] does.
+pub(crate) async fn check_quota_in(
+    changes: &[(i32, &data::ChangeEvent)],
+    config: &ServerFileHandlerConfig,
+    connection: &mut sqlx::PgConnection,
+) -> Result<Option<data::StorageUsage>, sqlx::Error> {
+    let deltas = update_usage_in(changes, &mut *connection).await?;
+
+    for (namespace, delta) in deltas {
+        let quota = config.quotas().quota(&namespace);
+        if delta <= 0 || quota.is_none() {
+            continue;
+        }
+        let used = get_used(&namespace, &mut *connection).await?;
+        let usage = data::StorageUsage::new(namespace, used, quota);
+        if usage.is_exceeded() {
+            return Ok(Some(usage));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{check_quota, get_storage_usage, namespace_of};
+    use crate::{
+        data,
+        server_database::{
+            insert_change, insert_changes, InsertError, Quotas, ServerFileHandlerConfig,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[test]
+    fn test_namespace_of() {
+        assert_eq!(namespace_of("alice/docs/a.txt"), "alice");
+        assert_eq!(namespace_of("a.txt"), "");
+    }
+
+    #[tokio::test]
+    async fn test_check_quota() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+
+        let mut config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        config.set_quotas(Quotas::new(
+            None,
+            BTreeMap::from([("alice".to_string(), 100)]),
+        ));
+
+        insert_change(
+            data::FileCreate::new(60, "alice/a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        insert_change(
+            data::FileCreate::new(500, "bob/a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        let usage = get_storage_usage("alice", &config, &db_pool).await.unwrap();
+        assert_eq!(
+            usage,
+            data::StorageUsage::new("alice".to_string(), 60, Some(100))
+        );
+
+        let changes: Vec<data::ChangeEvent> =
+            vec![data::FileModify::new(90, "alice/a.txt".to_string()).into()];
+        assert!(check_quota(&changes, &config, &db_pool)
+            .await
+            .unwrap()
+            .is_none());
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileCreate::new(50, "alice/b.txt".to_string()).into(),
+            data::FileCreate::new(1000, "bob/b.txt".to_string()).into(),
+        ];
+        assert_eq!(
+            check_quota(&changes, &config, &db_pool).await.unwrap(),
+            Some(data::StorageUsage::new("alice".to_string(), 110, Some(100)))
+        );
+
+        // The quota is enforced when the changes are inserted
+        let result = insert_changes(&changes, &config, &db_pool).await;
+        assert!(
+            matches!(result, Err(InsertError::QuotaExceeded(ref usage)) if usage.namespace() == "alice")
+        );
+        let result = insert_change(changes[0].clone(), &config, &db_pool).await;
+        assert!(matches!(result, Err(InsertError::QuotaExceeded(_))));
+        assert_eq!(
+            get_storage_usage("alice", &config, &db_pool)
+                .await
+                .unwrap()
+                .used(),
+            60
+        );
+    }
+
+    #[tokio::test]
+    async fn test_storage_usage() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        let steps: Vec<(data::ChangeEvent, u64, u64)> = vec![
+            (
+                data::FileCreate::new(10, "alice/d/a.txt".to_string()).into(),
+                10,
+                0,
+            ),
+            (
+                data::FileCreate::new(20, "alice/d/b.txt".to_string()).into(),
+                30,
+                0,
+            ),
+            (
+                data::FileCreate::new(5, "bob/c.txt".to_string()).into(),
+                30,
+                5,
+            ),
+            (
+                data::FileMove::new("alice/d/a.txt".to_string(), "bob/a.txt".to_string()).into(),
+                20,
+                15,
+            ),
+            (
+                data::DirectoryDelete::new("alice/d".to_string()).into(),
+                0,
+                15,
+            ),
+            (
+                data::DirectoryUndoDelete::new("alice/d".to_string()).into(),
+                20,
+                15,
+            ),
+            (
+                data::FileModify::new(1, "bob/a.txt".to_string()).into(),
+                20,
+                6,
+            ),
+            (data::FileDelete::new("bob/c.txt".to_string()).into(), 20, 1),
+            // The size is kept while the file is deleted
+            (
+                data::FileUndoDelete::new(0, "bob/c.txt".to_string()).into(),
+                20,
+                6,
+            ),
+            (
+                data::DirectoryMove::new("alice/d".to_string(), "bob/d".to_string()).into(),
+                0,
+                26,
+            ),
+        ];
+        for (change, alice, bob) in steps {
+            insert_change(change, &config, &db_pool).await.unwrap();
+            for (namespace, used) in [("alice", alice), ("bob", bob)] {
+                assert_eq!(
+                    get_storage_usage(namespace, &config, &db_pool)
+                        .await
+                        .unwrap()
+                        .used(),
+                    used
+                );
+            }
+        }
+    }
+}
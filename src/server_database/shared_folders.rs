@@ -0,0 +1,462 @@
+use sqlx::Row;
+
+use crate::data;
+
+/// Whether `path` is `folder` or anything inside of it.
+fn in_folder(path: &str, folder: &str) -> bool {
+    path == folder || (path.starts_with(folder) && path.as_bytes().get(folder.len()) == Some(&b'/'))
+}
+
+/// Shares the folder at `path` (relative to the storage directory). Members are added with
+/// [`add_shared_folder_member`]. Returns the id of the shared folder.
+pub async fn create_shared_folder(path: &str, db_pool: &sqlx::PgPool) -> Result<i32, sqlx::Error> {
+    let shared_folder_id: i32 = sqlx::query(
+        r#"
+        INSERT INTO shared_folders (path)
+        VALUES ($1)
+        ON CONFLICT (path) DO UPDATE SET path = $1
+        RETURNING id
+        "#,
+    )
+    .bind(path)
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(shared_folder_id)
+}
+
+/// Gives `namespace` (see [`namespace_of`](super::namespace_of)) access to the shared folder at
+/// `path`, or changes its permission if it is already a member.
+pub async fn add_shared_folder_member(
+    path: &str,
+    namespace: &str,
+    permission: data::Permission,
+    db_pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO shared_folder_members (shared_folder_id, namespace, read_only)
+        SELECT id, $2, $3 FROM shared_folders WHERE path = $1
+        ON CONFLICT (shared_folder_id, namespace) DO UPDATE SET read_only = $3
+        "#,
+    )
+    .bind(path)
+    .bind(namespace)
+    .bind(permission == data::Permission::ReadOnly)
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn remove_shared_folder_member(
+    path: &str,
+    namespace: &str,
+    db_pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DELETE FROM shared_folder_members
+        USING shared_folders
+        WHERE shared_folders.id = shared_folder_id AND path = $1 AND namespace = $2
+        "#,
+    )
+    .bind(path)
+    .bind(namespace)
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns every folder that is shared with `namespace`.
+pub async fn get_shared_folders(
+    namespace: &str,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<data::SharedFolder>, sqlx::Error> {
+    get_shared_folders_in(namespace, &mut *db_pool.acquire().await?).await
+}
+
+async fn get_shared_folders_in(
+    namespace: &str,
+    connection: &mut sqlx::PgConnection,
+) -> Result<Vec<data::SharedFolder>, sqlx::Error> {
+    let shared_folders = sqlx::query(
+        r#"
+        SELECT path, read_only FROM shared_folders
+        JOIN shared_folder_members ON shared_folders.id = shared_folder_id
+        WHERE namespace = $1
+        ORDER BY path
+        "#,
+    )
+    .bind(namespace)
+    .map(|row: sqlx::postgres::PgRow| {
+        let read_only: bool = row.get("read_only");
+        let permission = match read_only {
+            true => data::Permission::ReadOnly,
+            false => data::Permission::ReadWrite,
+        };
+        data::SharedFolder::new(row.get("path"), permission)
+    })
+    .fetch_all(connection)
+    .await?;
+
+    Ok(shared_folders)
+}
+
+/// The change stream of `namespace`: every change between `change_id_from` and
+/// `change_id_to` (see [`get_changes`](super::get_changes)) that affects the namespace itself
+/// or one of the folders shared with it. Changes inside of a shared folder are therefore
+/// received by every member.
+///
+/// A move out of what the namespace can see is received as a delete of the old path, and a
+/// move into it as a create of the new path (including everything inside of a moved
+/// directory), since the other side of the move does not exist for the namespace.
+pub async fn get_changes_for_namespace(
+    namespace: &str,
+    change_id_from: i32,
+    change_id_to: i32,
+    db_pool: &sqlx::PgPool,
+) -> Result<Vec<(i32, data::ChangeEvent)>, sqlx::Error> {
+    let shared_folders = get_shared_folders(namespace, db_pool).await?;
+    let mut connection = db_pool.acquire().await?;
+    let changes =
+        super::get_changes::get_changes_in(change_id_from, change_id_to, &mut connection).await?;
+
+    let visible = |path: &str| {
+        super::namespace_of(path) == namespace
+            || shared_folders.iter().any(|f| in_folder(path, f.path()))
+    };
+    let moved_in = |change: &data::ChangeEvent| match change {
+        data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+            !visible(file_move.from_path()) && visible(file_move.to_path())
+        }
+        data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => {
+            !visible(dir_move.from_path()) && visible(dir_move.to_path())
+        }
+        _ => false,
+    };
+
+    // Moves into the namespace need the size and contents of what was moved
+    let mut snapshot = match changes.iter().any(|(_, change)| moved_in(change)) {
+        true => Some(super::snapshot::replay_snapshot(change_id_from, &mut connection).await?),
+        false => None,
+    };
+
+    let mut namespace_changes = vec![];
+    for (change_id, change) in changes {
+        match &change {
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                match (visible(file_move.from_path()), visible(file_move.to_path())) {
+                    (true, true) => namespace_changes.push((change_id, change.clone())),
+                    (true, false) => namespace_changes.push((
+                        change_id,
+                        data::FileDelete::new(file_move.from_path().to_string()).into(),
+                    )),
+                    (false, true) => {
+                        let size = snapshot
+                            .as_ref()
+                            .and_then(|s| s.files().get(file_move.from_path()).copied())
+                            .unwrap_or(0);
+                        namespace_changes.push((
+                            change_id,
+                            data::FileCreate::new(size, file_move.to_path().to_string()).into(),
+                        ));
+                    }
+                    (false, false) => {}
+                }
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => {
+                match (visible(dir_move.from_path()), visible(dir_move.to_path())) {
+                    (true, true) => namespace_changes.push((change_id, change.clone())),
+                    (true, false) => namespace_changes.push((
+                        change_id,
+                        data::DirectoryDelete::new(dir_move.from_path().to_string()).into(),
+                    )),
+                    (false, true) => namespace_changes.extend(
+                        directory_creates(
+                            snapshot.as_ref(),
+                            dir_move.from_path(),
+                            dir_move.to_path(),
+                        )
+                        .into_iter()
+                        .map(|create| (change_id, create)),
+                    ),
+                    (false, false) => {}
+                }
+            }
+            _ => {
+                if change.paths().into_iter().any(visible) {
+                    namespace_changes.push((change_id, change.clone()));
+                }
+            }
+        }
+
+        if let Some(snapshot) = snapshot.as_mut() {
+            snapshot.apply_change(change_id, &change);
+        }
+    }
+
+    Ok(namespace_changes)
+}
+
+/// The changes that create the directory `from_dir` of `snapshot`, and everything inside of
+/// it, at `to_dir`. Parents are created before their contents.
+fn directory_creates(
+    snapshot: Option<&data::Snapshot>,
+    from_dir: &str,
+    to_dir: &str,
+) -> Vec<data::ChangeEvent> {
+    let mut creates: Vec<data::ChangeEvent> =
+        vec![data::DirectoryCreate::new(to_dir.to_string()).into()];
+    let snapshot = match snapshot {
+        Some(snapshot) => snapshot,
+        None => return creates,
+    };
+    let relocate = |path: &str| match path.strip_prefix(from_dir) {
+        Some(rest) if rest.starts_with('/') => Some(format!("{}{}", to_dir, rest)),
+        _ => None,
+    };
+
+    // Parents sort before their contents
+    for dir in snapshot.directories() {
+        if let Some(path) = relocate(dir) {
+            creates.push(data::DirectoryCreate::new(path).into());
+        }
+    }
+    for (file, size) in snapshot.files() {
+        if let Some(path) = relocate(file) {
+            creates.push(data::FileCreate::new(*size, path).into());
+        }
+    }
+    for (symlink, links_to) in snapshot.symlinks() {
+        if let Some(path) = relocate(symlink) {
+            creates.push(data::SymlinkCreate::new(path, links_to.clone()).into());
+        }
+    }
+
+    creates
+}
+
+/// Checks that `namespace` may write every path affected by `changes`, i.e. each path is
+/// inside of the namespace itself or inside of a folder shared with it as
+/// [`ReadWrite`](data::Permission::ReadWrite). Returns the first path that may not be written,
+/// in which case the changes should be rejected with
+/// [`ErrorType::AccessDenied`](data::ErrorType::AccessDenied).
+///
+/// Changes received from an origin with a namespace are also checked when they are inserted
+/// (see [`InsertError::AccessDenied`](super::InsertError::AccessDenied)), so this is only
+/// needed to check changes without inserting them.
+pub async fn check_access(
+    namespace: &str,
+    changes: &[data::ChangeEvent],
+    db_pool: &sqlx::PgPool,
+) -> Result<Option<data::AccessDenied>, sqlx::Error> {
+    let changes = changes.iter().collect::<Vec<_>>();
+    check_access_in(namespace, &changes, &mut *db_pool.acquire().await?).await
+}
+
+/// Same as [`check_access`], but on `connection`, e.g. as part of the transaction that inserts
+/// `changes`.
+pub(crate) async fn check_access_in(
+    namespace: &str,
+    changes: &[&data::ChangeEvent],
+    connection: &mut sqlx::PgConnection,
+) -> Result<Option<data::AccessDenied>, sqlx::Error> {
+    let shared_folders = get_shared_folders_in(namespace, connection).await?;
+
+    for path in changes.iter().flat_map(|change| change.paths()) {
+        // The innermost shared folder decides
+        let shared_folder = shared_folders
+            .iter()
+            .filter(|f| in_folder(path, f.path()))
+            .max_by_key(|f| f.path().len());
+        match shared_folder {
+            Some(f) if f.permission() == data::Permission::ReadWrite => {}
+            Some(f) => {
+                return Ok(Some(data::AccessDenied::new(
+                    path.to_string(),
+                    Some(f.clone()),
+                )))
+            }
+            None if super::namespace_of(path) == namespace => {}
+            None => return Ok(Some(data::AccessDenied::new(path.to_string(), None))),
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        add_shared_folder_member, check_access, create_shared_folder, get_changes_for_namespace,
+    };
+    use crate::{
+        data,
+        server_database::{
+            get_server_version, insert_changes, insert_changes_from, insert_uploaded_change,
+            register_device, ChangeOrigin, InsertError, ServerFileHandlerConfig,
+        },
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_shared_folders() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        sqlx::query("TRUNCATE TABLE shared_folders CASCADE")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        create_shared_folder("Photos", &db_pool).await.unwrap();
+        add_shared_folder_member("Photos", "alice", data::Permission::ReadWrite, &db_pool)
+            .await
+            .unwrap();
+        add_shared_folder_member("Photos", "bob", data::Permission::ReadOnly, &db_pool)
+            .await
+            .unwrap();
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::FileCreate::new(0, "alice/private.txt".to_string()).into(),
+            data::FileCreate::new(0, "Photos/beach.jpg".to_string()).into(),
+            data::FileCreate::new(0, "bob/private.txt".to_string()).into(),
+        ];
+        let change_ids = insert_changes(&changes, &config, &db_pool).await.unwrap();
+
+        let bob_changes = get_changes_for_namespace("bob", 0, change_ids[2], &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            bob_changes.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            change_ids[1..]
+        );
+
+        assert!(check_access("alice", &changes[..2], &db_pool)
+            .await
+            .unwrap()
+            .is_none());
+        let denied = check_access("bob", &changes[1..], &db_pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(denied.path(), "Photos/beach.jpg");
+        assert_eq!(
+            denied.shared_folder().unwrap().permission(),
+            data::Permission::ReadOnly
+        );
+        let denied = check_access("alice", &changes[2..], &db_pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(denied.shared_folder().is_none());
+
+        // Access is checked when changes from a namespace are inserted
+        let mut bob = ChangeOrigin::new(
+            register_device("test_shared_folders", &db_pool)
+                .await
+                .unwrap(),
+            None,
+        );
+        bob.set_namespace("bob".to_string());
+        let modify: data::ChangeEvent =
+            data::FileModify::new(1, "Photos/beach.jpg".to_string()).into();
+        let result = insert_uploaded_change(
+            &bob,
+            &data::UploadedChange::new(1, modify.clone()),
+            &config,
+            &db_pool,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(InsertError::AccessDenied(ref denied)) if denied.path() == "Photos/beach.jpg")
+        );
+        let result =
+            insert_changes_from(std::slice::from_ref(&modify), &bob, &config, &db_pool).await;
+        assert!(matches!(result, Err(InsertError::AccessDenied(_))));
+        assert_eq!(get_server_version(&db_pool).await.unwrap(), change_ids[2]);
+
+        add_shared_folder_member("Photos", "bob", data::Permission::ReadWrite, &db_pool)
+            .await
+            .unwrap();
+        insert_changes_from(&[modify], &bob, &config, &db_pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_moves_across_shared_folders() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+        sqlx::query("TRUNCATE TABLE shared_folders CASCADE")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        create_shared_folder("Photos", &db_pool).await.unwrap();
+        add_shared_folder_member("Photos", "bob", data::Permission::ReadWrite, &db_pool)
+            .await
+            .unwrap();
+
+        let changes: Vec<data::ChangeEvent> = vec![
+            data::DirectoryCreate::new("alice/dir".to_string()).into(),
+            data::FileCreate::new(5, "alice/dir/a.txt".to_string()).into(),
+            data::FileCreate::new(3, "alice/b.txt".to_string()).into(),
+            data::FileMove::new("alice/b.txt".to_string(), "Photos/b.txt".to_string()).into(),
+            data::DirectoryMove::new("alice/dir".to_string(), "Photos/dir".to_string()).into(),
+            data::FileMove::new("Photos/b.txt".to_string(), "alice/c.txt".to_string()).into(),
+            data::DirectoryMove::new("Photos/dir".to_string(), "alice/dir".to_string()).into(),
+        ];
+        let change_ids = insert_changes(&changes, &config, &db_pool).await.unwrap();
+
+        // Moves in are received as creates, moves out as deletes
+        let bob_changes = get_changes_for_namespace("bob", change_ids[2], change_ids[6], &db_pool)
+            .await
+            .unwrap();
+        let expected: Vec<(i32, data::ChangeEvent)> = vec![
+            (
+                change_ids[3],
+                data::FileCreate::new(3, "Photos/b.txt".to_string()).into(),
+            ),
+            (
+                change_ids[4],
+                data::DirectoryCreate::new("Photos/dir".to_string()).into(),
+            ),
+            (
+                change_ids[4],
+                data::FileCreate::new(5, "Photos/dir/a.txt".to_string()).into(),
+            ),
+            (
+                change_ids[5],
+                data::FileDelete::new("Photos/b.txt".to_string()).into(),
+            ),
+            (
+                change_ids[6],
+                data::DirectoryDelete::new("Photos/dir".to_string()).into(),
+            ),
+        ];
+        assert_eq!(bob_changes, expected);
+
+        // Alice is not a member, so the same holds the other way around
+        let alice_changes = get_changes_for_namespace("alice", 0, change_ids[6], &db_pool)
+            .await
+            .unwrap();
+        let mut expected = changes[..3].to_vec();
+        expected.extend(vec![
+            data::FileDelete::new("alice/b.txt".to_string()).into(),
+            data::DirectoryDelete::new("alice/dir".to_string()).into(),
+            data::FileCreate::new(3, "alice/c.txt".to_string()).into(),
+            data::DirectoryCreate::new("alice/dir".to_string()).into(),
+            data::FileCreate::new(5, "alice/dir/a.txt".to_string()).into(),
+        ]);
+        assert_eq!(
+            alice_changes
+                .into_iter()
+                .map(|(_, c)| c)
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use sqlx::Row;
+
+use crate::data;
+
+/// Computes the tree at `change_event_id`. Starts from the most recent checkpoint at or before
+/// `change_event_id` (if any) and replays the remaining changes on top of it.
+/// Deleted files and directories are not included.
+pub async fn get_snapshot(
+    change_event_id: i32,
+    db_pool: &sqlx::PgPool,
+) -> Result<data::Snapshot, sqlx::Error> {
+    get_snapshot_in(change_event_id, &mut *db_pool.acquire().await?).await
+}
+
+/// Same as [`get_snapshot`], but on `connection`, e.g. as part of a transaction.
+pub(crate) async fn get_snapshot_in(
+    change_event_id: i32,
+    connection: &mut sqlx::PgConnection,
+) -> Result<data::Snapshot, sqlx::Error> {
+    let mut snapshot = replay_snapshot(change_event_id, connection).await?;
+    snapshot.clear_trash();
+
+    Ok(snapshot)
+}
+
+/// Same as [`get_snapshot`], but keeps the trash so that undo deletes after `change_event_id`
+/// can be replayed on top of the result.
+pub(super) async fn replay_snapshot(
+    change_event_id: i32,
+    connection: &mut sqlx::PgConnection,
+) -> Result<data::Snapshot, sqlx::Error> {
+    let checkpoint: Option<String> = sqlx::query(
+        r#"
+        SELECT snapshot FROM snapshot_checkpoints
+        WHERE server_version <= $1
+        ORDER BY server_version DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(change_event_id)
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_optional(&mut *connection)
+    .await?;
+
+    let mut snapshot = match checkpoint {
+        Some(checkpoint) => {
+            serde_json::from_str(&checkpoint).map_err(|e| sqlx::Error::Decode(Box::new(e)))?
+        }
+        None => data::Snapshot::new(0),
+    };
+
+    let changes =
+        super::get_changes::get_changes_in(snapshot.server_version(), change_event_id, connection)
+            .await?;
+    for (change_id, change) in changes.iter() {
+        snapshot.apply_change(*change_id, change);
+    }
+    snapshot.set_server_version(change_event_id);
+
+    Ok(snapshot)
+}
+
+/// Stores the tree at the current server version so that later calls to [`get_snapshot`] do
+/// not have to replay the whole change log. Returns the server version of the checkpoint.
+pub async fn create_snapshot_checkpoint(db_pool: &sqlx::PgPool) -> Result<i32, sqlx::Error> {
+    let server_version = super::get_server_version(db_pool).await?;
+    let snapshot = replay_snapshot(server_version, &mut *db_pool.acquire().await?).await?;
+    let snapshot =
+        serde_json::to_string(&snapshot).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO snapshot_checkpoints (server_version, snapshot)
+        VALUES ($1, $2)
+        ON CONFLICT (server_version) DO UPDATE SET snapshot = $2
+        "#,
+    )
+    .bind(server_version)
+    .bind(snapshot)
+    .execute(db_pool)
+    .await?;
+
+    Ok(server_version)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{create_snapshot_checkpoint, get_snapshot};
+    use crate::{
+        data,
+        server_database::{compact_changes, insert_change, ServerFileHandlerConfig},
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_get_snapshot() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        let create_dir = insert_change(
+            data::DirectoryCreate::new("dir".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        insert_change(
+            data::FileCreate::new(0, "dir/a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        let checkpoint = create_snapshot_checkpoint(&db_pool).await.unwrap();
+        insert_change(
+            data::DirectoryMove::new("dir".to_string(), "dir2".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        let snapshot = get_snapshot(create_dir, &db_pool).await.unwrap();
+        assert!(snapshot.directories().contains("dir"));
+        assert!(snapshot.files().is_empty());
+
+        let snapshot = get_snapshot(checkpoint, &db_pool).await.unwrap();
+        assert!(snapshot.files().contains_key("dir/a.txt"));
+
+        let snapshot = get_snapshot(checkpoint + 1, &db_pool).await.unwrap();
+        assert!(snapshot.files().contains_key("dir2/a.txt"));
+        assert!(snapshot.directories().contains("dir2"));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_without_trash() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        insert_change(
+            data::FileCreate::new(3, "a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        insert_change(
+            data::FileDelete::new("a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        create_snapshot_checkpoint(&db_pool).await.unwrap();
+        let undo_delete = insert_change(
+            data::FileUndoDelete::new(0, "a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        let snapshot = get_snapshot(undo_delete - 1, &db_pool).await.unwrap();
+        assert!(snapshot.files().is_empty());
+        assert!(!serde_json::to_string(&snapshot)
+            .unwrap()
+            .contains("trashed"));
+
+        // The checkpoint still knows what was deleted
+        let snapshot = get_snapshot(undo_delete, &db_pool).await.unwrap();
+        assert_eq!(snapshot.files().get("a.txt"), Some(&3));
+    }
+
+    #[tokio::test]
+    async fn test_compaction_removes_checkpoints() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        let config = ServerFileHandlerConfig::new("_server_storage_dir".into());
+
+        insert_change(
+            data::FileCreate::new(0, "a.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        create_snapshot_checkpoint(&db_pool).await.unwrap();
+        let cutoff = insert_change(
+            data::FileMove::new("a.txt".to_string(), "b.txt".to_string()).into(),
+            &config,
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        compact_changes(cutoff, &db_pool).await.unwrap();
+
+        let snapshot = get_snapshot(cutoff, &db_pool).await.unwrap();
+        assert_eq!(snapshot.files().keys().collect::<Vec<_>>(), vec!["b.txt"]);
+    }
+}
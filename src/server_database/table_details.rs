@@ -1,75 +1,267 @@
+use sqlx::Row;
+
 use crate::data;
 
+/// Declarative description of every change table. The table definitions (see
+/// [`initialize_db`](super::initialize_db)), inserts and row decoding are all generated from
+/// this, so adding an event type only requires adding an entry here.
 pub const TABLES: [TableDetails; 11] = [
     TableDetails {
         table_description: "File Create",
         change_type_id: 1,
         table_name: "file_create",
+        columns: &[ColumnDetails::path("path"), ColumnDetails::size("size")],
+        encode: |change| match change {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => Some(vec![
+                file_create.path().to_string(),
+                file_create.size().to_string(),
+            ]),
+            _ => None,
+        },
+        decode: |row| {
+            let size: i64 = row.get("size");
+            data::FileCreate::new(size as u64, row.get("path")).into()
+        },
     },
     TableDetails {
         table_description: "File Modify",
         change_type_id: 2,
         table_name: "file_modify",
+        columns: &[ColumnDetails::path("path"), ColumnDetails::size("size")],
+        encode: |change| match change {
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => Some(vec![
+                file_modify.path().to_string(),
+                file_modify.size().to_string(),
+            ]),
+            _ => None,
+        },
+        decode: |row| {
+            let size: i64 = row.get("size");
+            data::FileModify::new(size as u64, row.get("path")).into()
+        },
     },
     TableDetails {
         table_description: "File Move",
         change_type_id: 3,
         table_name: "file_move",
+        columns: &[
+            ColumnDetails::path("old_path"),
+            ColumnDetails::path("new_path"),
+        ],
+        encode: |change| match change {
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => Some(vec![
+                file_move.from_path().to_string(),
+                file_move.to_path().to_string(),
+            ]),
+            _ => None,
+        },
+        decode: |row| data::FileMove::new(row.get("old_path"), row.get("new_path")).into(),
     },
     TableDetails {
         table_description: "File Delete",
         change_type_id: 4,
         table_name: "file_delete",
+        columns: &[ColumnDetails::path("path")],
+        encode: |change| match change {
+            data::ChangeEvent::File(data::FileEvent::Delete(file_delete)) => {
+                Some(vec![file_delete.path().to_string()])
+            }
+            _ => None,
+        },
+        decode: |row| data::FileDelete::new(row.get("path")).into(),
     },
     TableDetails {
         table_description: "Undo File Delete",
         change_type_id: 5,
         table_name: "undo_file_delete",
+        columns: &[ColumnDetails::path("path"), ColumnDetails::size("size")],
+        encode: |change| match change {
+            data::ChangeEvent::File(data::FileEvent::UndoDelete(file_undo_delete)) => Some(vec![
+                file_undo_delete.path().to_string(),
+                file_undo_delete.size().to_string(),
+            ]),
+            _ => None,
+        },
+        decode: |row| {
+            let size: i64 = row.get("size");
+            data::FileUndoDelete::new(size as u64, row.get("path")).into()
+        },
     },
     TableDetails {
         table_description: "Directory Create",
         change_type_id: 6,
         table_name: "directory_create",
+        columns: &[ColumnDetails::path("path")],
+        encode: |change| match change {
+            data::ChangeEvent::Directory(data::DirectoryEvent::Create(dir_create)) => {
+                Some(vec![dir_create.path().to_string()])
+            }
+            _ => None,
+        },
+        decode: |row| data::DirectoryCreate::new(row.get("path")).into(),
     },
     TableDetails {
         table_description: "Directory Move",
         change_type_id: 7,
         table_name: "directory_move",
+        columns: &[
+            ColumnDetails::path("old_path"),
+            ColumnDetails::path("new_path"),
+        ],
+        encode: |change| match change {
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(dir_move)) => Some(vec![
+                dir_move.from_path().to_string(),
+                dir_move.to_path().to_string(),
+            ]),
+            _ => None,
+        },
+        decode: |row| data::DirectoryMove::new(row.get("old_path"), row.get("new_path")).into(),
     },
     TableDetails {
         table_description: "Directory Delete",
         change_type_id: 8,
         table_name: "directory_delete",
+        columns: &[ColumnDetails::path("path")],
+        encode: |change| match change {
+            data::ChangeEvent::Directory(data::DirectoryEvent::Delete(dir_delete)) => {
+                Some(vec![dir_delete.path().to_string()])
+            }
+            _ => None,
+        },
+        decode: |row| data::DirectoryDelete::new(row.get("path")).into(),
     },
     TableDetails {
         table_description: "Undo Directory Delete",
         change_type_id: 9,
         table_name: "undo_directory_delete",
+        columns: &[ColumnDetails::path("path")],
+        encode: |change| match change {
+            data::ChangeEvent::Directory(data::DirectoryEvent::UndoDelete(dir_undo_delete)) => {
+                Some(vec![dir_undo_delete.path().to_string()])
+            }
+            _ => None,
+        },
+        decode: |row| data::DirectoryUndoDelete::new(row.get("path")).into(),
     },
     TableDetails {
         table_description: "Symlink Create",
         change_type_id: 10,
         table_name: "symlink_create",
+        columns: &[
+            ColumnDetails::path("path"),
+            ColumnDetails::link_target("target"),
+        ],
+        encode: |change| match change {
+            data::ChangeEvent::Symlink(data::SymlinkEvent::Create(symlink_create)) => Some(vec![
+                symlink_create.path().to_string(),
+                symlink_create.links_to().to_string(),
+            ]),
+            _ => None,
+        },
+        decode: |row| data::SymlinkCreate::new(row.get("path"), row.get("target")).into(),
     },
     TableDetails {
         table_description: "Symlink Delete",
         change_type_id: 11,
         table_name: "symlink_delete",
+        columns: &[ColumnDetails::path("path")],
+        encode: |change| match change {
+            data::ChangeEvent::Symlink(data::SymlinkEvent::Delete(symlink_delete)) => {
+                Some(vec![symlink_delete.path().to_string()])
+            }
+            _ => None,
+        },
+        decode: |row| data::SymlinkDelete::new(row.get("path")).into(),
     },
 ];
 
-#[derive(Debug, PartialEq, Clone)]
+/// A column of a change table, other than `change_event_id`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ColumnDetails {
+    name: &'static str,
+    /// Type used in the table definition.
+    sql_type: &'static str,
+    /// Values are bound as text and cast to this type when inserted.
+    bind_type: &'static str,
+    /// Used to fill the column of existing rows when it is added to an existing table.
+    default: Option<&'static str>,
+    /// Whether the column holds one of the [`paths`](data::ChangeEvent::paths) of the change.
+    is_path: bool,
+}
+
+impl ColumnDetails {
+    const fn path(name: &'static str) -> Self {
+        Self {
+            name,
+            sql_type: "VARCHAR(128)",
+            bind_type: "TEXT",
+            default: None,
+            is_path: true,
+        }
+    }
+
+    /// The path that a symlink links to, which is not changed by the change.
+    const fn link_target(name: &'static str) -> Self {
+        Self {
+            is_path: false,
+            ..Self::path(name)
+        }
+    }
+
+    const fn size(name: &'static str) -> Self {
+        Self {
+            name,
+            sql_type: "BIGINT",
+            bind_type: "BIGINT",
+            default: Some("0"),
+            is_path: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    pub fn is_path(&self) -> bool {
+        self.is_path
+    }
+
+    pub fn bind_type(&self) -> &str {
+        self.bind_type
+    }
+
+    /// The column definition, as used in `CREATE TABLE` and `ADD COLUMN`.
+    pub fn definition(&self) -> String {
+        match self.default {
+            Some(default) => format!(
+                "{} {} NOT NULL DEFAULT {}",
+                self.name, self.sql_type, default
+            ),
+            None => format!("{} {} NOT NULL", self.name, self.sql_type),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct TableDetails {
     table_description: &'static str,
     change_type_id: i32,
     table_name: &'static str,
+    columns: &'static [ColumnDetails],
+    /// Returns the values of `columns` if the change is stored in this table.
+    encode: fn(&data::ChangeEvent) -> Option<Vec<String>>,
+    decode: fn(&sqlx::postgres::PgRow) -> data::ChangeEvent,
 }
 
-impl Copy for TableDetails {}
+impl PartialEq for TableDetails {
+    fn eq(&self, other: &Self) -> bool {
+        self.change_type_id == other.change_type_id
+    }
+}
 
 impl TableDetails {
     pub fn table_name(&self) -> &str {
-        &self.table_name
+        self.table_name
     }
 
     pub fn change_type_id(&self) -> i32 {
@@ -77,7 +269,66 @@ impl TableDetails {
     }
 
     pub fn table_description(&self) -> &str {
-        &self.table_description
+        self.table_description
+    }
+
+    pub fn columns(&self) -> &[ColumnDetails] {
+        self.columns
+    }
+
+    /// The values of [`columns`](Self::columns) for `change`, which must be stored in this
+    /// table.
+    pub fn encode(&self, change: &data::ChangeEvent) -> Vec<String> {
+        (self.encode)(change).expect("change is not stored in this table")
+    }
+
+    pub fn decode(&self, row: &sqlx::postgres::PgRow) -> data::ChangeEvent {
+        (self.decode)(row)
+    }
+
+    /// `CREATE TABLE` statement for the table.
+    pub fn create_table_sql(&self) -> String {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| format!(",\n    {}", column.definition()))
+            .collect::<String>();
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n    change_event_id INTEGER PRIMARY KEY NOT NULL REFERENCES change_events(id){}\n)",
+            self.table_name, columns
+        )
+    }
+
+    /// Adds any columns that are missing from a table created by an older version.
+    pub fn add_columns_sql(&self) -> String {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| format!("ADD COLUMN IF NOT EXISTS {}", column.definition()))
+            .collect::<Vec<_>>();
+        format!("ALTER TABLE {} {}", self.table_name, columns.join(", "))
+    }
+
+    /// `INSERT` statement for a single row. The values of the columns are bound as text, after
+    /// the `change_event_id`.
+    pub fn insert_sql(&self) -> String {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| column.name)
+            .collect::<Vec<_>>();
+        let values = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("${}::{}", i + 2, column.bind_type))
+            .collect::<Vec<_>>();
+        format!(
+            "INSERT INTO {} (change_event_id, {}) VALUES ($1, {})",
+            self.table_name,
+            columns.join(", "),
+            values.join(", ")
+        )
     }
 }
 
@@ -87,24 +338,34 @@ pub trait TableDetailsTrait {
 
 impl TableDetailsTrait for data::ChangeEvent {
     fn table_details(&self) -> &TableDetails {
-        match self {
-            data::ChangeEvent::File(file_event) => match file_event {
-                data::FileEvent::Create(_) => &TABLES[0],
-                data::FileEvent::Modify(_) => &TABLES[1],
-                data::FileEvent::Move(_) => &TABLES[2],
-                data::FileEvent::Delete(_) => &TABLES[3],
-                data::FileEvent::UndoDelete(_) => &TABLES[4],
-            },
-            data::ChangeEvent::Directory(dir_event) => match dir_event {
-                data::DirectoryEvent::Create(_) => &TABLES[5],
-                data::DirectoryEvent::Move(_) => &TABLES[6],
-                data::DirectoryEvent::Delete(_) => &TABLES[7],
-                data::DirectoryEvent::UndoDelete(_) => &TABLES[8],
-            },
-            data::ChangeEvent::Symlink(symlink_event) => match symlink_event {
-                data::SymlinkEvent::Create(_) => &TABLES[9],
-                data::SymlinkEvent::Delete(_) => &TABLES[10],
-            },
-        }
+        TABLES
+            .iter()
+            .find(|table| (table.encode)(self).is_some())
+            .expect("every change event has a table")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TableDetailsTrait, TABLES};
+    use crate::data;
+
+    #[test]
+    fn test_table_details() {
+        let change: data::ChangeEvent =
+            data::FileMove::new("a.txt".to_string(), "b.txt".to_string()).into();
+        let table = change.table_details();
+        assert_eq!(table.table_name(), "file_move");
+        assert_eq!(table.encode(&change), vec!["a.txt", "b.txt"]);
+        assert_eq!(
+            table.insert_sql(),
+            "INSERT INTO file_move (change_event_id, old_path, new_path) VALUES ($1, $2::TEXT, $3::TEXT)"
+        );
+
+        let change_type_ids = TABLES
+            .iter()
+            .map(|t| t.change_type_id())
+            .collect::<Vec<_>>();
+        assert_eq!(change_type_ids, (1..=11).collect::<Vec<_>>());
     }
 }
@@ -0,0 +1,372 @@
+use std::{fs, io, path};
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::data;
+
+use super::ServerFileHandlerConfig;
+
+/// A deleted file or directory whose content is kept in the trash directory.
+/// `change_event_id` is the id of the delete change that moved it there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrashEntry {
+    trash_id: i32,
+    path: String,
+    is_directory: bool,
+    change_event_id: i32,
+    trashed_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TrashEntry {
+    pub fn trash_id(&self) -> i32 {
+        self.trash_id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.is_directory
+    }
+
+    pub fn change_event_id(&self) -> i32 {
+        self.change_event_id
+    }
+
+    pub fn trashed_at(&self) -> &DateTime<Utc> {
+        &self.trashed_at
+    }
+
+    pub fn expires_at(&self) -> Option<&DateTime<Utc>> {
+        self.expires_at.as_ref()
+    }
+}
+
+impl From<sqlx::postgres::PgRow> for TrashEntry {
+    fn from(row: sqlx::postgres::PgRow) -> Self {
+        Self {
+            trash_id: row.get("id"),
+            path: row.get("path"),
+            is_directory: row.get("is_directory"),
+            change_event_id: row.get("change_event_id"),
+            trashed_at: row.get("trashed_at"),
+            expires_at: row.get("expires_at"),
+        }
+    }
+}
+
+/// Trashed content is stored by its id rather than its path, so that the same path can be
+/// deleted more than once.
+fn trash_path(trash_id: i32, config: &ServerFileHandlerConfig) -> path::PathBuf {
+    config.trash_directory().join(trash_id.to_string())
+}
+
+fn remove_path(path: &path::Path) -> io::Result<()> {
+    match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    }
+}
+
+/// Why content could not be moved into or restored from the trash.
+#[derive(Debug)]
+pub enum TrashError {
+    /// There is no file or directory at the path to delete, or nothing is in the trash for the
+    /// path to restore.
+    NotFound(String),
+    /// The path to restore has been reused since it was deleted.
+    AlreadyExists(String),
+    Database(sqlx::Error),
+    /// Moving content into or out of the trash directory failed.
+    Io(io::Error),
+}
+
+impl std::fmt::Display for TrashError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrashError::NotFound(path) => write!(f, "`{}` not found", path),
+            TrashError::AlreadyExists(path) => {
+                write!(f, "`{}` already exists, cannot undo delete", path)
+            }
+            TrashError::Database(e) => e.fmt(f),
+            TrashError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TrashError {}
+
+impl From<sqlx::Error> for TrashError {
+    fn from(e: sqlx::Error) -> Self {
+        TrashError::Database(e)
+    }
+}
+
+impl From<io::Error> for TrashError {
+    fn from(e: io::Error) -> Self {
+        TrashError::Io(e)
+    }
+}
+
+/// Deletes `path` by moving its content into the trash and inserting the matching
+/// `FileDelete` or `DirectoryDelete` change. The content expires after
+/// [`trash_expiry_days`](ServerFileHandlerConfig::trash_expiry_days).
+/// Returns the resulting change, which should be sent to the clients.
+///
+/// The change is only committed once the content has been moved, and the content is moved
+/// back if the commit fails.
+pub async fn move_to_trash(
+    path: &str,
+    is_directory: bool,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<(i32, data::ChangeEvent), TrashError> {
+    let storage_path = config.storage_directory().join(path);
+    if storage_path.is_dir() != is_directory || !storage_path.exists() {
+        return Err(TrashError::NotFound(path.to_string()));
+    }
+
+    let change: data::ChangeEvent = match is_directory {
+        true => data::DirectoryDelete::new(path.to_string()).into(),
+        false => data::FileDelete::new(path.to_string()).into(),
+    };
+
+    let mut transaction = db_pool.begin().await?;
+    let change_id = super::insert_change::insert_change_in(&change, None, &mut transaction).await?;
+    super::quotas::update_usage_in(&[(change_id, &change)], &mut transaction).await?;
+
+    let trash_id: i32 = sqlx::query(
+        r#"
+        INSERT INTO trash (path, is_directory, change_event_id, expires_at)
+        VALUES ($1, $2, $3, NOW() + make_interval(days => $4))
+        RETURNING id
+        "#,
+    )
+    .bind(path)
+    .bind(is_directory)
+    .bind(change_id)
+    .bind(config.trash_expiry_days().map(|days| days as i32))
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let trashed_path = trash_path(trash_id, config);
+    fs::create_dir_all(config.trash_directory())?;
+    fs::rename(&storage_path, &trashed_path)?;
+
+    if let Err(e) = transaction.commit().await {
+        fs::rename(&trashed_path, &storage_path).ok();
+        return Err(e.into());
+    }
+
+    Ok((change_id, change))
+}
+
+/// Restores the most recently trashed content of `path` and inserts the matching
+/// `FileUndoDelete` or `DirectoryUndoDelete` change. Fails with [`TrashError::NotFound`] if
+/// nothing is in the trash for `path`, and with [`TrashError::AlreadyExists`] if `path` has been
+/// reused since it was deleted.
+/// Returns the resulting change, which should be sent to the clients.
+///
+/// As with [`move_to_trash`], the content is moved back if the change cannot be committed.
+pub async fn restore_from_trash(
+    path: &str,
+    is_directory: bool,
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<(i32, data::ChangeEvent), TrashError> {
+    let mut transaction = db_pool.begin().await?;
+
+    let trash_id: i32 = sqlx::query(
+        r#"
+        SELECT id FROM trash
+        WHERE path = $1 AND is_directory = $2
+        ORDER BY id DESC
+        LIMIT 1
+        FOR UPDATE
+        "#,
+    )
+    .bind(path)
+    .bind(is_directory)
+    .map(|row: sqlx::postgres::PgRow| row.get(0))
+    .fetch_optional(&mut transaction)
+    .await?
+    .ok_or_else(|| TrashError::NotFound(path.to_string()))?;
+
+    let storage_path = config.storage_directory().join(path);
+    if storage_path.exists() {
+        return Err(TrashError::AlreadyExists(path.to_string()));
+    }
+
+    sqlx::query("DELETE FROM trash WHERE id = $1")
+        .bind(trash_id)
+        .execute(&mut transaction)
+        .await?;
+
+    let trashed_path = trash_path(trash_id, config);
+    let change: data::ChangeEvent = match is_directory {
+        true => data::DirectoryUndoDelete::new(path.to_string()).into(),
+        false => {
+            let size = fs::metadata(&trashed_path)?.len();
+            data::FileUndoDelete::new(size, path.to_string()).into()
+        }
+    };
+    let change_id = super::insert_change::insert_change_in(&change, None, &mut transaction).await?;
+    super::quotas::update_usage_in(&[(change_id, &change)], &mut transaction).await?;
+
+    if let Some(parent) = storage_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&trashed_path, &storage_path)?;
+
+    if let Err(e) = transaction.commit().await {
+        fs::rename(&storage_path, &trashed_path).ok();
+        return Err(e.into());
+    }
+
+    Ok((change_id, change))
+}
+
+/// Returns everything that is currently in the trash, from oldest to newest.
+pub async fn list_trash(db_pool: &sqlx::PgPool) -> Result<Vec<TrashEntry>, sqlx::Error> {
+    let entries = sqlx::query("SELECT * FROM trash ORDER BY id")
+        .map(TrashEntry::from)
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(entries)
+}
+
+/// Permanently removes every trashed file and directory that has expired.
+/// Returns the number of entries that were removed.
+pub async fn purge_expired_trash(
+    config: &ServerFileHandlerConfig,
+    db_pool: &sqlx::PgPool,
+) -> Result<usize, sqlx::Error> {
+    let (ids, change_ids): (Vec<i32>, Vec<i32>) =
+        sqlx::query("DELETE FROM trash WHERE expires_at < NOW() RETURNING id, change_event_id")
+            .map(|row: sqlx::postgres::PgRow| (row.get::<i32, _>(0), row.get::<i32, _>(1)))
+            .fetch_all(db_pool)
+            .await?
+            .into_iter()
+            .unzip();
+
+    // What has been purged can no longer be restored
+    sqlx::query("DELETE FROM file_sizes WHERE deleted_by = ANY($1)")
+        .bind(&change_ids)
+        .execute(db_pool)
+        .await?;
+
+    for id in ids.iter() {
+        let path = trash_path(*id, config);
+        if path.exists() {
+            remove_path(&path)?;
+        }
+    }
+
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{list_trash, move_to_trash, purge_expired_trash, restore_from_trash, TrashError};
+    use crate::{
+        data,
+        server_database::{get_changes, ServerFileHandlerConfig},
+        testing_utils::clear_tables_and_get_pool,
+    };
+
+    #[tokio::test]
+    async fn test_trash() {
+        let (db_pool, _guard) = clear_tables_and_get_pool().await.unwrap();
+        sqlx::query("TRUNCATE TABLE trash")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let storage_directory = env::current_dir().unwrap().join("_server_storage_dir");
+        fs::remove_dir_all(&storage_directory).ok();
+        fs::create_dir_all(storage_directory.join("dir")).unwrap();
+        fs::write(storage_directory.join("dir/a.txt"), "a").unwrap();
+        fs::write(storage_directory.join("b.txt"), "b").unwrap();
+        let config = ServerFileHandlerConfig::new(storage_directory.clone());
+
+        let (delete_id, _) = move_to_trash("dir", true, &config, &db_pool).await.unwrap();
+        move_to_trash("b.txt", false, &config, &db_pool)
+            .await
+            .unwrap();
+        assert!(!storage_directory.join("dir").exists());
+        assert!(!storage_directory.join("b.txt").exists());
+        assert!(matches!(
+            move_to_trash("b.txt", false, &config, &db_pool).await,
+            Err(TrashError::NotFound(_))
+        ));
+
+        let trash = list_trash(&db_pool).await.unwrap();
+        assert_eq!(trash.len(), 2);
+        assert_eq!(trash[0].change_event_id(), delete_id);
+        assert!(trash[0].expires_at().is_none());
+
+        let (undo_id, change) = restore_from_trash("dir", true, &config, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            change,
+            data::DirectoryUndoDelete::new("dir".to_string()).into()
+        );
+        assert_eq!(
+            fs::read_to_string(storage_directory.join("dir/a.txt")).unwrap(),
+            "a"
+        );
+        assert!(matches!(
+            restore_from_trash("dir", true, &config, &db_pool).await,
+            Err(TrashError::NotFound(_))
+        ));
+
+        // `b.txt` has been created again since it was deleted
+        fs::write(storage_directory.join("b.txt"), "new").unwrap();
+        assert!(matches!(
+            restore_from_trash("b.txt", false, &config, &db_pool).await,
+            Err(TrashError::AlreadyExists(_))
+        ));
+        assert_eq!(list_trash(&db_pool).await.unwrap().len(), 1);
+
+        let changes = get_changes(0, undo_id, &db_pool).await.unwrap();
+        assert_eq!(
+            changes.last(),
+            Some(&(
+                undo_id,
+                data::DirectoryUndoDelete::new("dir".to_string()).into()
+            ))
+        );
+
+        // Nothing has expired
+        assert_eq!(purge_expired_trash(&config, &db_pool).await.unwrap(), 0);
+        sqlx::query("UPDATE trash SET expires_at = NOW() - INTERVAL '1 day'")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        assert_eq!(purge_expired_trash(&config, &db_pool).await.unwrap(), 1);
+        assert!(list_trash(&db_pool).await.unwrap().is_empty());
+        assert_eq!(fs::read_dir(config.trash_directory()).unwrap().count(), 0);
+
+        // Restored files carry their size
+        move_to_trash("b.txt", false, &config, &db_pool)
+            .await
+            .unwrap();
+        let (_, change) = restore_from_trash("b.txt", false, &config, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            change,
+            data::FileUndoDelete::new(3, "b.txt".to_string()).into()
+        );
+
+        fs::remove_dir_all(&storage_directory).unwrap();
+    }
+}
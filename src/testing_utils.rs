@@ -41,10 +41,27 @@ pub fn rm_dirs_ce_dirs_get_default_helpers() -> (
     fs::create_dir_all(&file_handler_config.program_data_directory);
 
     let mut change_counter =
-        client_database::ChangeCounter::init(&file_handler_config.program_data_directory);
+        client_database::ChangeCounter::init(&file_handler_config.program_data_directory).unwrap();
     (file_handler_config, change_counter)
 }
-pub async fn clear_tables_and_get_pool() -> Result<sqlx::postgres::PgPool, sqlx::Error> {
+
+/// Whether `file` has custom metadata in the client database of `change_counter`.
+pub fn has_custom_metadata(
+    file: &client_database::FilePaths,
+    change_counter: &mut client_database::ChangeCounter,
+) -> bool {
+    client_database::CustomMetadata::read_from_database(file, change_counter.database())
+        .unwrap()
+        .is_some()
+}
+
+/// Tests that use the database share the same tables, so they are run one at a time.
+static DB_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub async fn clear_tables_and_get_pool(
+) -> Result<(sqlx::postgres::PgPool, tokio::sync::MutexGuard<'static, ()>), sqlx::Error> {
+    let guard = DB_LOCK.lock().await;
+
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL environment variable not set");
 
     let db_conf = DbConfig::new(db_url, 5);
@@ -53,10 +70,10 @@ pub async fn clear_tables_and_get_pool() -> Result<sqlx::postgres::PgPool, sqlx:
     assert!(db_pool.is_ok());
     let db_pool = db_pool.unwrap();
 
-    let sql = r#"TRUNCATE TABLE change_events CASCADE;"#;
+    let sql = r#"TRUNCATE TABLE change_events, snapshot_checkpoints, uploaded_changes, file_sizes, namespace_usage RESTART IDENTITY CASCADE;"#;
     let result = sqlx::query(sql).execute(&db_pool).await;
 
     assert!(result.is_ok());
 
-    Ok(db_pool)
+    Ok((db_pool, guard))
 }